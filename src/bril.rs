@@ -0,0 +1,558 @@
+use std::fmt;
+
+use serde_json::{json, Map, Value};
+
+use crate::typecheck::infer_types;
+use crate::types::*;
+
+// Import/export of Bril JSON programs (https://capra.cs.cornell.edu/bril/).
+// Only the core ops that have a matching `Instruction` are supported,
+// anything else is rejected with `BrilError::Unsupported`.
+
+#[derive(Debug)]
+pub enum BrilError {
+    Json(serde_json::Error),
+    Malformed { func: String, message: String },
+    Unsupported { func: String, index: usize, what: String },
+}
+
+impl fmt::Display for BrilError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BrilError::Json(e) => write!(f, "invalid JSON: {}", e),
+            BrilError::Malformed { func, message } => write!(f, "@{}: malformed Bril: {}", func, message),
+            BrilError::Unsupported { func, index, what } => {
+                write!(f, "@{}: instruction {}: unsupported {}", func, index, what)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BrilError {}
+
+impl From<serde_json::Error> for BrilError {
+    fn from(e: serde_json::Error) -> Self {
+        BrilError::Json(e)
+    }
+}
+
+// two operand ops of core Bril, the rest of ours (`rem`, `ne`, shifts and
+// bitwise ops) have no Bril counterpart
+const BRIL_BINARY: &[BinOp] = &[
+    BinOp::Add, BinOp::Mul, BinOp::Sub, BinOp::Div, BinOp::Eq, BinOp::Lt, BinOp::Gt, BinOp::Le, BinOp::Ge,
+    BinOp::And, BinOp::Or, BinOp::FAdd, BinOp::FMul, BinOp::FSub, BinOp::FDiv, BinOp::FEq, BinOp::FLt,
+    BinOp::FLe, BinOp::FGt, BinOp::FGe,
+];
+
+fn bril_binary(opcode: &str) -> Option<BinOp> {
+    BinOp::from_opcode(opcode).filter(|op| BRIL_BINARY.contains(op))
+}
+
+// =====================================
+//             LOADER
+// =====================================
+
+/// Read every function of a Bril JSON program.
+pub fn from_bril_json(src: &str) -> Result<Program, BrilError> {
+    let program: Value = serde_json::from_str(src)?;
+    let functions = program
+        .get("functions")
+        .and_then(Value::as_array)
+        .ok_or_else(|| malformed("", "missing `functions` array"))?;
+
+    let functions = functions.iter().map(load_function).collect::<Result<_, _>>()?;
+    Ok(Program { functions })
+}
+
+fn load_function(func: &Value) -> Result<Function, BrilError> {
+    let name = func
+        .get("name")
+        .and_then(Value::as_str)
+        .ok_or_else(|| malformed("", "function without a `name`"))?
+        .to_string();
+
+    let mut args = Vec::new();
+    for arg in func.get("args").and_then(Value::as_array).into_iter().flatten() {
+        let arg_name = arg
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| malformed(&name, "argument without a `name`"))?;
+        let typ = load_type(&name, 0, arg.get("type"))?;
+        args.push(Argument { name: arg_name.to_string(), typ });
+    }
+    let ret_type = func.get("type").map(|typ| load_type(&name, 0, Some(typ))).transpose()?;
+
+    let instrs = func
+        .get("instrs")
+        .and_then(Value::as_array)
+        .ok_or_else(|| malformed(&name, "missing `instrs` array"))?;
+
+    let mut instr = Vec::new();
+    for (index, value) in instrs.iter().enumerate() {
+        if let Some(loaded) = load_instruction(&name, index, value)? {
+            instr.push(loaded);
+        }
+    }
+
+    Ok(Function { name, args, ret_type, instr })
+}
+
+// `None` for instructions that have no effect (`nop`)
+fn load_instruction(func: &str, index: usize, value: &Value) -> Result<Option<Instruction>, BrilError> {
+    if let Some(label) = value.get("label") {
+        let label = label.as_str().ok_or_else(|| malformed(func, "label is not a string"))?;
+        return Ok(Some(Instruction::Label { label: label.to_string() }));
+    }
+
+    let op = value
+        .get("op")
+        .and_then(Value::as_str)
+        .ok_or_else(|| malformed(func, format!("instruction {} has neither `op` nor `label`", index)))?;
+    let unsupported = |what: String| BrilError::Unsupported { func: func.to_string(), index, what };
+
+    let args = strings(func, value, "args")?;
+    let labels = strings(func, value, "labels")?;
+    let dest = || {
+        value
+            .get("dest")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| malformed(func, format!("`{}` without a `dest`", op)))
+    };
+    let arity = |n: usize, list: &[String], kind: &str| {
+        if list.len() == n {
+            Ok(())
+        } else {
+            Err(malformed(func, format!("`{}` expects {} {}, found {}", op, n, kind, list.len())))
+        }
+    };
+
+    let instr = match op {
+        "const" => {
+            let typ = load_type(func, index, value.get("type"))?;
+            let raw = value.get("value").ok_or_else(|| malformed(func, "`const` without a `value`"))?;
+            let values = match (&typ, raw) {
+                (Types::Int, Value::Number(n)) if n.is_i64() => Literal::Int(n.as_i64().unwrap()),
+                (Types::Bool, Value::Bool(b)) => Literal::Bool(*b),
+                (Types::Float, Value::Number(n)) => Literal::Float(n.as_f64().unwrap()),
+                _ => return Err(malformed(func, format!("constant {} does not match type {}", raw, typ))),
+            };
+            Instruction::Const { dest: dest()?, typ, values }
+        }
+        _ if let Some(binop) = bril_binary(op) => {
+            arity(2, &args, "args")?;
+            Instruction::binary(binop, dest()?, args[0].clone(), args[1].clone())
+        }
+        "not" => {
+            arity(1, &args, "args")?;
+            Instruction::Not { dest: dest()?, src: args[0].clone() }
+        }
+        "id" => {
+            arity(1, &args, "args")?;
+            Instruction::Id { dest: dest()?, src: args[0].clone() }
+        }
+        "jmp" => {
+            arity(1, &labels, "labels")?;
+            Instruction::Jmp { label: labels[0].clone() }
+        }
+        "br" => {
+            arity(1, &args, "args")?;
+            arity(2, &labels, "labels")?;
+            Instruction::Br { cond: args[0].clone(), then_label: labels[0].clone(), else_label: labels[1].clone() }
+        }
+        "ret" => match args.as_slice() {
+            [] => Instruction::Ret { value: None },
+            [v] => Instruction::Ret { value: Some(v.clone()) },
+            _ => return Err(malformed(func, "`ret` takes at most one arg")),
+        },
+        "print" => {
+            if args.len() != 1 {
+                return Err(unsupported(format!("`print` with {} args (only one is supported)", args.len())));
+            }
+            Instruction::Print { value: args[0].clone() }
+        }
+        "alloc" => {
+            arity(1, &args, "args")?;
+            let typ = load_type(func, index, value.get("type"))?;
+            if !matches!(typ, Types::Ptr(_)) {
+                return Err(malformed(func, format!("`alloc` of non pointer type {}", typ)));
+            }
+            Instruction::Alloc { dest: dest()?, typ, size: args[0].clone() }
+        }
+        "free" => {
+            arity(1, &args, "args")?;
+            Instruction::Free { ptr: args[0].clone() }
+        }
+        "load" => {
+            arity(1, &args, "args")?;
+            Instruction::Load { dest: dest()?, ptr: args[0].clone() }
+        }
+        "store" => {
+            arity(2, &args, "args")?;
+            Instruction::Store { ptr: args[0].clone(), value: args[1].clone() }
+        }
+        "ptradd" => {
+            arity(2, &args, "args")?;
+            Instruction::PtrAdd { dest: dest()?, ptr: args[0].clone(), offset: args[1].clone() }
+        }
+        "phi" => {
+            arity(args.len(), &labels, "labels")?;
+            Instruction::Phi { dest: dest()?, args: labels.iter().cloned().zip(args.iter().cloned()).collect() }
+        }
+        "call" => {
+            let funcs = strings(func, value, "funcs")?;
+            arity(1, &funcs, "funcs")?;
+            let dest = match value.get("dest") {
+                Some(_) => Some((dest()?, load_type(func, index, value.get("type"))?)),
+                None => None,
+            };
+            Instruction::Call { dest, func: funcs[0].clone(), args }
+        }
+        "nop" => return Ok(None),
+        other => return Err(unsupported(format!("op `{}`", other))),
+    };
+
+    Ok(Some(instr))
+}
+
+fn strings(func: &str, value: &Value, key: &str) -> Result<Vec<String>, BrilError> {
+    match value.get(key) {
+        None => Ok(Vec::new()),
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|item| {
+                item.as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| malformed(func, format!("`{}` must only contain strings", key)))
+            })
+            .collect(),
+        Some(_) => Err(malformed(func, format!("`{}` is not an array", key))),
+    }
+}
+
+fn load_type(func: &str, index: usize, typ: Option<&Value>) -> Result<Types, BrilError> {
+    match typ {
+        Some(Value::String(name)) if name == "int" => Ok(Types::Int),
+        Some(Value::String(name)) if name == "bool" => Ok(Types::Bool),
+        Some(Value::String(name)) if name == "float" => Ok(Types::Float),
+        // `{"ptr": inner}`
+        Some(Value::Object(map)) if map.len() == 1 && map.contains_key("ptr") => {
+            Ok(Types::Ptr(Box::new(load_type(func, index, map.get("ptr"))?)))
+        }
+        _ => Err(BrilError::Unsupported { func: func.to_string(), index, what: format!("type {:?}", typ) }),
+    }
+}
+
+fn malformed(func: &str, message: impl Into<String>) -> BrilError {
+    BrilError::Malformed { func: func.to_string(), message: message.into() }
+}
+
+// =====================================
+//             WRITER
+// =====================================
+
+/// Emit `program` as Bril JSON.
+pub fn to_bril_json(program: &Program) -> Result<String, BrilError> {
+    let functions = program.functions.iter().map(function_to_value).collect::<Result<Vec<_>, _>>()?;
+    Ok(serde_json::to_string_pretty(&json!({ "functions": functions }))?)
+}
+
+fn function_to_value(f: &Function) -> Result<Value, BrilError> {
+    // Bril wants a type on every value operation, ours only carry it on `Const`
+    let types = infer_types(f).types;
+    let type_of = |var: &String| {
+        types
+            .get(var)
+            .map(type_value)
+            .ok_or_else(|| malformed(&f.name, format!("cannot determine the type of `{}`", var)))
+    };
+
+    let mut instrs = Vec::new();
+    for (index, instr) in f.instr.iter().enumerate() {
+        let value = match instr {
+            Instruction::Label { label } => json!({ "label": label }),
+            Instruction::Const { dest, typ, values } => {
+                let literal = match values {
+                    Literal::Int(v) => json!(v),
+                    Literal::Float(v) if v.is_finite() => json!(v),
+                    // JSON numbers cannot spell NaN or the infinities
+                    Literal::Float(v) => {
+                        let what = format!("float constant {:?}", v);
+                        return Err(BrilError::Unsupported { func: f.name.clone(), index, what });
+                    }
+                    Literal::Bool(v) => json!(v),
+                };
+                json!({ "op": "const", "dest": dest, "type": type_value(typ), "value": literal })
+            }
+            _ if let Some((op, dest, op1, op2)) = instr.as_binary() => {
+                if !BRIL_BINARY.contains(&op) {
+                    return Err(BrilError::Unsupported { func: f.name.clone(), index, what: format!("op `{}`", op) });
+                }
+                value_op(op.opcode(), dest, type_of(dest)?, &[op1, op2])
+            }
+            Instruction::Not { dest, src } => value_op("not", dest, type_of(dest)?, &[src]),
+            // Bril has no separate move, both are plain copies
+            Instruction::Id { dest, src } | Instruction::Move { dest, src } => {
+                value_op("id", dest, type_of(dest)?, &[src])
+            }
+            Instruction::Jmp { label } => json!({ "op": "jmp", "labels": [label] }),
+            Instruction::Br { cond, then_label, else_label } => {
+                json!({ "op": "br", "args": [cond], "labels": [then_label, else_label] })
+            }
+            Instruction::Ret { value: Some(v) } => json!({ "op": "ret", "args": [v] }),
+            Instruction::Ret { value: None } => json!({ "op": "ret", "args": [] }),
+            Instruction::Print { value } => json!({ "op": "print", "args": [value] }),
+            Instruction::Call { dest: Some((dest, typ)), func, args } => {
+                json!({ "op": "call", "dest": dest, "type": type_value(typ), "funcs": [func], "args": args })
+            }
+            Instruction::Call { dest: None, func, args } => json!({ "op": "call", "funcs": [func], "args": args }),
+            Instruction::Alloc { dest, typ, size } => value_op("alloc", dest, type_value(typ), &[size]),
+            Instruction::Free { ptr } => json!({ "op": "free", "args": [ptr] }),
+            Instruction::Load { dest, ptr } => value_op("load", dest, type_of(dest)?, &[ptr]),
+            Instruction::Store { ptr, value } => json!({ "op": "store", "args": [ptr, value] }),
+            Instruction::PtrAdd { dest, ptr, offset } => value_op("ptradd", dest, type_of(dest)?, &[ptr, offset]),
+            Instruction::Phi { dest, args } => {
+                let (labels, vars): (Vec<&String>, Vec<&String>) = args.iter().map(|(l, v)| (l, v)).unzip();
+                json!({ "op": "phi", "dest": dest, "type": type_of(dest)?, "args": vars, "labels": labels })
+            }
+            _ => unreachable!("every two operand instruction is matched by `as_binary`"),
+        };
+        instrs.push(value);
+    }
+
+    let mut func = Map::new();
+    func.insert("name".to_string(), json!(f.name));
+    if !f.args.is_empty() {
+        let args: Vec<Value> = f.args.iter().map(|a| json!({ "name": a.name, "type": type_value(&a.typ) })).collect();
+        func.insert("args".to_string(), Value::Array(args));
+    }
+    // a function written without a signature still returns whatever its `ret` returns
+    let ret_var = f.instr.iter().find_map(|i| match i {
+        Instruction::Ret { value: Some(v) } => Some(v),
+        _ => None,
+    });
+    match (&f.ret_type, ret_var) {
+        (Some(typ), _) => {
+            func.insert("type".to_string(), type_value(typ));
+        }
+        (None, Some(v)) => {
+            func.insert("type".to_string(), type_of(v)?);
+        }
+        (None, None) => {}
+    }
+    func.insert("instrs".to_string(), Value::Array(instrs));
+    Ok(Value::Object(func))
+}
+
+fn type_value(typ: &Types) -> Value {
+    match typ {
+        Types::Ptr(inner) => json!({ "ptr": type_value(inner) }),
+        _ => Value::String(typ.to_string()),
+    }
+}
+
+fn value_op(op: &str, dest: &str, typ: Value, args: &[&String]) -> Value {
+    json!({ "op": op, "dest": dest, "type": typ, "args": args })
+}
+
+// =====================================
+//             TESTS
+// =====================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::parse_function;
+
+    const PROGRAM: &str = r#"{
+  "functions": [
+    {
+      "name": "main",
+      "instrs": [
+        { "op": "const", "dest": "v0", "type": "bool", "value": true },
+        { "op": "br", "args": ["v0"], "labels": ["then_blk", "else_blk"] },
+        { "label": "then_blk" },
+        { "op": "const", "dest": "v1", "type": "int", "value": 10 },
+        { "op": "jmp", "labels": ["merge_blk"] },
+        { "label": "else_blk" },
+        { "op": "const", "dest": "v1", "type": "int", "value": 20 },
+        { "op": "nop" },
+        { "op": "jmp", "labels": ["merge_blk"] },
+        { "label": "merge_blk" },
+        { "op": "add", "dest": "v2", "type": "int", "args": ["v1", "v1"] },
+        { "op": "eq", "dest": "v3", "type": "bool", "args": ["v2", "v1"] },
+        { "op": "sub", "dest": "v4", "type": "int", "args": ["v2", "v1"] },
+        { "op": "lt", "dest": "v5", "type": "bool", "args": ["v4", "v1"] },
+        { "op": "not", "dest": "v6", "type": "bool", "args": ["v5"] },
+        { "op": "print", "args": ["v3"] },
+        { "op": "ret", "args": [] }
+      ]
+    }
+  ]
+}"#;
+
+    #[test]
+    fn test_load_program() {
+        let funcs = from_bril_json(PROGRAM).unwrap().functions;
+        assert_eq!(funcs.len(), 1);
+
+        let expected = parse_function(
+            "
+@main {
+  v0: bool = const true
+  br v0 .then_blk .else_blk
+.then_blk:
+  v1: int = const 10
+  jmp .merge_blk
+.else_blk:
+  v1: int = const 20
+  jmp .merge_blk
+.merge_blk:
+  v2 = add v1 v1
+  v3 = eq v2 v1
+  v4 = sub v2 v1
+  v5 = lt v4 v1
+  v6 = not v5
+  print v3
+  ret
+}
+",
+        )
+        .unwrap();
+        assert_eq!(funcs[0], expected);
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let f = parse_function(
+            "
+@main {
+  a: int = const 4
+  b = id a
+  c = mul b a
+  d = move c
+  ret d
+}
+",
+        )
+        .unwrap();
+
+        let json = to_bril_json(&Program { functions: vec![f.clone()] }).unwrap();
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["functions"][0]["type"], "int");
+        assert_eq!(value["functions"][0]["instrs"][1]["type"], "int");
+        assert_eq!(value["functions"][0]["instrs"][3]["op"], "id");
+
+        // `move` comes back as `id`, everything else is unchanged
+        let back = from_bril_json(&json).unwrap().functions;
+        assert_eq!(back[0].instr[3], Instruction::Id { dest: "d".into(), src: "c".into() });
+        assert_eq!(back[0].instr[..3], f.instr[..3]);
+    }
+
+    #[test]
+    fn test_calls_and_arguments() {
+        let src = r#"{"functions":[
+            {"name":"main","instrs":[
+                {"op":"const","dest":"a","type":"int","value":2},
+                {"op":"call","dest":"b","type":"int","funcs":["double"],"args":["a"]},
+                {"op":"call","funcs":["show"],"args":["b"]}]},
+            {"name":"double","args":[{"name":"x","type":"int"}],"type":"int","instrs":[
+                {"op":"add","dest":"y","type":"int","args":["x","x"]},
+                {"op":"ret","args":["y"]}]},
+            {"name":"show","args":[{"name":"v","type":"int"}],"instrs":[
+                {"op":"print","args":["v"]}]}]}"#;
+        let program = from_bril_json(src).unwrap();
+
+        let double = program.function("double").unwrap();
+        assert_eq!(double.args, vec![Argument { name: "x".into(), typ: Types::Int }]);
+        assert_eq!(double.ret_type, Some(Types::Int));
+        let main = program.function("main").unwrap();
+        assert_eq!(
+            main.instr[1],
+            Instruction::Call { dest: Some(("b".into(), Types::Int)), func: "double".into(), args: vec!["a".into()] }
+        );
+        assert_eq!(main.instr[2], Instruction::Call { dest: None, func: "show".into(), args: vec!["b".into()] });
+
+        assert_eq!(from_bril_json(&to_bril_json(&program).unwrap()).unwrap(), program);
+    }
+
+    #[test]
+    fn test_memory_round_trip() {
+        let src = r#"{"functions":[{"name":"main","instrs":[
+            {"op":"const","dest":"n","type":"int","value":2},
+            {"op":"alloc","dest":"p","type":{"ptr":"int"},"args":["n"]},
+            {"op":"ptradd","dest":"q","type":{"ptr":"int"},"args":["p","n"]},
+            {"op":"store","args":["p","n"]},
+            {"op":"load","dest":"x","type":"int","args":["p"]},
+            {"op":"free","args":["p"]}]}]}"#;
+        let program = from_bril_json(src).unwrap();
+        let main = &program.functions[0];
+        assert_eq!(main.instr[1], Instruction::Alloc {
+            dest: "p".into(),
+            typ: Types::Ptr(Box::new(Types::Int)),
+            size: "n".into()
+        });
+        assert_eq!(main.instr[4], Instruction::Load { dest: "x".into(), ptr: "p".into() });
+
+        let json = to_bril_json(&program).unwrap();
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["functions"][0]["instrs"][2]["type"], json!({ "ptr": "int" }));
+        assert_eq!(from_bril_json(&json).unwrap(), program);
+    }
+
+    #[test]
+    fn test_phi_round_trip() {
+        let src = r#"{"functions":[{"name":"main","instrs":[
+            {"label":"a"},
+            {"op":"const","dest":"x","type":"int","value":1},
+            {"label":"b"},
+            {"op":"phi","dest":"y","type":"int","args":["x","y"],"labels":["a","b"]},
+            {"op":"jmp","labels":["b"]}]}]}"#;
+        let program = from_bril_json(src).unwrap();
+        assert_eq!(program.functions[0].instr[3], Instruction::Phi {
+            dest: "y".into(),
+            args: vec![("a".into(), "x".into()), ("b".into(), "y".into())]
+        });
+        assert_eq!(from_bril_json(&to_bril_json(&program).unwrap()).unwrap(), program);
+
+        let bad = r#"{"functions":[{"name":"main","instrs":[{"op":"phi","dest":"y","type":"int","args":["x"],"labels":[]}]}]}"#;
+        assert!(from_bril_json(bad).is_err());
+    }
+
+    #[test]
+    fn test_float_round_trip() {
+        let src = "@f {\n  a: float = const 0.1\n  b: float = const -0.0\n  c = fdiv a b\n  d = fle c a\n  print d\n}";
+        let f = parse_function(src).unwrap();
+        let back = from_bril_json(&to_bril_json(&Program { functions: vec![f.clone()] }).unwrap()).unwrap();
+        assert_eq!(back.functions, vec![f]);
+
+        // JSON has no NaN
+        let f = parse_function("@f {\n  a: float = const NaN\n}").unwrap();
+        assert!(matches!(to_bril_json(&Program { functions: vec![f] }), Err(BrilError::Unsupported { index: 0, .. })));
+    }
+
+    #[test]
+    fn test_rejects_unsupported() {
+        let src = r#"{"functions":[{"name":"f","instrs":[
+            {"op":"const","dest":"a","type":"int","value":1},
+            {"op":"speculate"}]}]}"#;
+        match from_bril_json(src) {
+            Err(BrilError::Unsupported { func, index, what }) => {
+                assert_eq!(func, "f");
+                assert_eq!(index, 1);
+                assert!(what.contains("speculate"));
+            }
+            other => panic!("expected an unsupported error, got {:?}", other),
+        }
+
+        let f = parse_function("@f {\n  a: int = const 1\n  b = rem a a\n}").unwrap();
+        assert!(matches!(to_bril_json(&Program { functions: vec![f] }), Err(BrilError::Unsupported { index: 1, .. })));
+
+        let src = r#"{"functions":[{"name":"f","instrs":[{"op":"print","args":["a","b"]}]}]}"#;
+        assert!(matches!(from_bril_json(src), Err(BrilError::Unsupported { .. })));
+
+        let src = r#"{"functions":[{"name":"f","instrs":[{"op":"jmp","labels":[]}]}]}"#;
+        assert!(matches!(from_bril_json(src), Err(BrilError::Malformed { .. })));
+
+        assert!(matches!(from_bril_json("{"), Err(BrilError::Json(_))));
+    }
+}
@@ -1,27 +1,29 @@
-use std::{collections::HashMap, hash::Hash};
-use petgraph::{graph::DiGraph, graph::NodeIndex};
+use std::collections::{HashMap, HashSet};
+use petgraph::{graph::DiGraph, graph::NodeIndex, visit::DfsPostOrder, Direction};
 
 
 use crate::types::*;
 
-pub fn build_blocks(f: &Function ) -> Vec<Vec<Instruction>>{
+/// Splits `f` at every leader: a label starts a block (unless the block so
+/// far holds nothing), and the instruction after a terminator does too.
+/// Labels stay in their blocks, as their first instruction.
+pub fn build_blocks(f: &Function) -> Vec<Vec<Instruction>> {
     let mut blocks = Vec::new();
     let mut current = Vec::new();
 
-    for instru in &f.instr {
-
-        current.push(instru.clone());
-
-        if is_terminator(instru){
-            blocks.push(current);
-            current = Vec::new();
-        } 
+    for instr in &f.instr {
+        if matches!(instr, Instruction::Label { .. }) && !current.is_empty() {
+            blocks.push(std::mem::take(&mut current));
+        }
+        current.push(instr.clone());
+        if is_terminator(instr) {
+            blocks.push(std::mem::take(&mut current));
+        }
     }
     if !current.is_empty() {
         blocks.push(current);
     }
     blocks
-
 }
 
 #[derive(Clone,Debug)]
@@ -30,77 +32,360 @@ pub struct BasicBlock {
     pub instructions: Vec<Instruction>,
 }
 
-
-pub fn build_cfg(blocks: &Vec<Vec<Instruction>>) -> DiGraph<BasicBlock, ()> {
+/// One node per block, in order. A block is named by its label, or
+/// `block{i}` (made unique against the labels) if it has none. There is an
+/// edge for every jump target and from each block that does not end in a
+/// terminator to the block after it.
+pub fn build_cfg(blocks: &[Vec<Instruction>]) -> DiGraph<BasicBlock, ()> {
     let mut graph = DiGraph::new();
+    let labels: HashSet<&String> = blocks.iter().filter_map(|block| block_label(block)).collect();
+    // a duplicated label is jumped to at its first block
     let mut block_to_node: HashMap<String, NodeIndex> = HashMap::new();
 
-    //name the blocks first
-    for (i,block) in blocks.iter().enumerate() {
-        let name = if i == 0{
-            "block0".to_string()
-        }else {
-            if let Instruction::Label { label } = &block[0] {
-                label.clone()
-            } else {
-                format!("block{}",i)
+    for (i, block) in blocks.iter().enumerate() {
+        let name = match block_label(block) {
+            Some(label) => label.clone(),
+            None => {
+                let base = format!("block{}", i);
+                let mut name = base.clone();
+                let mut k = 0;
+                while labels.contains(&name) {
+                    k += 1;
+                    name = format!("{}.{}", base, k);
+                }
+                name
             }
         };
-        let block = BasicBlock {
-            name: name.clone(),
-            instructions: block.clone()
-        };
-
-        let node = graph.add_node(block.clone());
-        block_to_node.insert(name, node);
-     }
-
-     // Now to add the edge
-     for (i,block) in blocks.iter().enumerate(){
-        let block_name = get_block_name(i, block);
-        let from = block_to_node[&block_name];
-        let last = block.last().unwrap();
+        let node = graph.add_node(BasicBlock { name: name.clone(), instructions: block.clone() });
+        block_to_node.entry(name).or_insert(node);
+    }
 
-        let successors = match last {
-            Instruction::Jmp { label } => vec![label.clone()],
-            Instruction::Br { then_label, else_label, .. } => vec![then_label.clone(), else_label.clone()],
-            Instruction::Ret { .. } => vec![],
+    for (i, block) in blocks.iter().enumerate() {
+        let from = NodeIndex::new(i);
+        let targets = match block.last() {
+            Some(Instruction::Jmp { label }) => vec![label],
+            Some(Instruction::Br { then_label, else_label, .. }) => vec![then_label, else_label],
+            Some(Instruction::Ret { .. }) => vec![],
             _ => {
                 if i + 1 < blocks.len() {
-                    vec![format!("block{}", i + 1)]
-                } else {
-                    vec![]
+                    graph.add_edge(from, NodeIndex::new(i + 1), ());
                 }
+                vec![]
             }
         };
-
-        for succ in successors{
-            if let Some(&to) = block_to_node.get(&succ){
+        for target in targets {
+            if let Some(&to) = block_to_node.get(target) {
                 graph.add_edge(from, to, ());
             }
         }
-     }
+    }
 
-     graph
+    graph
 }
 
-fn get_block_name(i: usize, block: &Vec<Instruction>) -> String {
-    if i == 0 {
-        "block0".to_string()
-    } else {
-        if let Instruction::Label { label } = &block[0] {
-            label.clone()
-        } else {
-            format!("block{}", i)
+fn block_label(block: &[Instruction]) -> Option<&String> {
+    match block.first() {
+        Some(Instruction::Label { label }) => Some(label),
+        _ => None,
+    }
+}
+
+pub fn is_terminator(i: &Instruction) -> bool {
+    matches!(i, Instruction::Br {..} | Instruction::Jmp{..} | Instruction::Ret{..})
+}
+
+/// A function as a graph of basic blocks. `entry` is the block execution
+/// starts in and `exit` an empty block every `ret` (and falling off the end)
+/// has an edge to, so backward analyses have a single place to start from.
+///
+/// Reading goes through `Deref` to the underlying graph, changes go through
+/// the methods below so the name map stays in sync. Passes may rewrite the
+/// instructions of a block (`cfg[node]`) but not its name.
+#[derive(Clone, Debug)]
+pub struct Cfg {
+    pub name: String,
+    pub args: Vec<Argument>,
+    pub ret_type: Option<Types>,
+    pub entry: NodeIndex,
+    pub exit: NodeIndex,
+    graph: DiGraph<BasicBlock, ()>,
+    names: HashMap<String, NodeIndex>,
+}
+
+impl Cfg {
+    pub fn from_function(f: &Function) -> Cfg {
+        let blocks = build_blocks(f);
+        let mut graph = build_cfg(&blocks);
+        if graph.node_count() == 0 {
+            graph.add_node(BasicBlock { name: "block0".to_string(), instructions: vec![] });
+        }
+        let mut names = HashMap::new();
+        for node in graph.node_indices() {
+            names.entry(graph[node].name.clone()).or_insert(node);
+        }
+        let mut cfg = Cfg {
+            name: f.name.clone(),
+            args: f.args.clone(),
+            ret_type: f.ret_type.clone(),
+            entry: NodeIndex::new(0),
+            exit: NodeIndex::new(0),
+            graph,
+            names,
+        };
+
+        let name = cfg.fresh_name("exit");
+        cfg.exit = cfg.add_block(BasicBlock { name, instructions: vec![] });
+        let last = cfg.graph.node_count() - 2;
+        for node in cfg.graph.node_indices().take(last + 1) {
+            let returns = match cfg.graph[node].instructions.last() {
+                Some(Instruction::Ret { .. }) => true,
+                Some(instr) => node.index() == last && !is_terminator(instr),
+                None => true,
+            };
+            if returns {
+                cfg.graph.add_edge(node, cfg.exit, ());
+            }
+        }
+        cfg
+    }
+
+    /// The block called `name` (its label, or `block{i}` if it has none).
+    pub fn block(&self, name: &str) -> Option<NodeIndex> {
+        self.names.get(name).copied()
+    }
+
+    pub fn successors(&self, node: NodeIndex) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.graph.neighbors_directed(node, Direction::Outgoing)
+    }
+
+    pub fn predecessors(&self, node: NodeIndex) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.graph.neighbors_directed(node, Direction::Incoming)
+    }
+
+    /// Blocks reachable from the entry, each after all of its successors
+    /// (back edges aside).
+    pub fn postorder(&self) -> Vec<NodeIndex> {
+        let mut dfs = DfsPostOrder::new(&self.graph, self.entry);
+        let mut order = Vec::new();
+        while let Some(node) = dfs.next(&self.graph) {
+            order.push(node);
+        }
+        order
+    }
+
+    /// Blocks reachable from the entry, each before all of its successors
+    /// (back edges aside). The usual order for forward analyses.
+    pub fn reverse_postorder(&self) -> Vec<NodeIndex> {
+        let mut order = self.postorder();
+        order.reverse();
+        order
+    }
+
+    /// A name no block has yet, `base` itself if it is free.
+    pub fn fresh_name(&self, base: &str) -> String {
+        let mut name = base.to_string();
+        let mut i = 0;
+        while self.names.contains_key(&name) {
+            i += 1;
+            name = format!("{}.{}", base, i);
+        }
+        name
+    }
+
+    /// Panics if a block with the same name exists.
+    pub fn add_block(&mut self, block: BasicBlock) -> NodeIndex {
+        assert!(!self.names.contains_key(&block.name), "duplicate block `{}`", block.name);
+        let name = block.name.clone();
+        let node = self.graph.add_node(block);
+        self.names.insert(name, node);
+        node
+    }
+
+    pub fn add_edge(&mut self, from: NodeIndex, to: NodeIndex) {
+        self.graph.add_edge(from, to, ());
+    }
+
+    /// Removes every edge `from -> to`.
+    pub fn remove_edge(&mut self, from: NodeIndex, to: NodeIndex) {
+        while let Some(edge) = self.graph.find_edge(from, to) {
+            self.graph.remove_edge(edge);
+        }
+    }
+
+    /// Removes a block and its edges. The last block takes over the index of
+    /// the removed one (as in `DiGraph`), so other indices held by the caller
+    /// may need the same fix up. The entry and exit can't be removed.
+    pub fn remove_block(&mut self, node: NodeIndex) -> BasicBlock {
+        assert!(node != self.entry && node != self.exit, "cannot remove the entry or exit block");
+        let moved = NodeIndex::new(self.graph.node_count() - 1);
+        let block = self.graph.remove_node(node).unwrap();
+        self.names.remove(&block.name);
+        if moved != node {
+            self.names.insert(self.graph[node].name.clone(), node);
+            if self.entry == moved {
+                self.entry = node;
+            }
+            if self.exit == moved {
+                self.exit = node;
+            }
+        }
+        block
+    }
+
+    /// Removes every block (other than the exit) that the entry can't reach,
+    /// returning their names.
+    pub fn remove_unreachable_blocks(&mut self) -> Vec<String> {
+        let reachable: HashSet<NodeIndex> = self.postorder().into_iter().collect();
+        let dead: Vec<NodeIndex> =
+            self.graph.node_indices().filter(|n| !reachable.contains(n) && *n != self.exit).collect();
+        // highest index first: `remove_block` moves the last node into the
+        // hole, which is then never one still to be removed
+        let mut removed: Vec<String> = dead.into_iter().rev().map(|node| self.remove_block(node).name).collect();
+        removed.reverse();
+        removed
+    }
+
+    /// Back to a list of instructions, blocks in index order with the entry
+    /// first. Labels are added to blocks that are jumped to without having
+    /// one, and a `jmp` (or `ret` to the exit) wherever a fall through would
+    /// otherwise end up in the wrong block.
+    pub fn to_function(&self) -> Function {
+        self.emit(&self.layout(Layout::Original), false)
+    }
+
+    /// Like `to_function` but with the blocks placed by `layout`, and any
+    /// `jmp` to the block right after it dropped. Unless the layout is
+    /// `Original`, blocks not reachable from the entry are left out.
+    pub fn linearize(&self, layout: Layout) -> Function {
+        self.emit(&self.layout(layout), true)
+    }
+
+    fn layout(&self, layout: Layout) -> Vec<NodeIndex> {
+        let mut order = match layout {
+            Layout::Original => {
+                let mut order = vec![self.entry];
+                order.extend(self.graph.node_indices().filter(|&n| n != self.entry));
+                order
+            }
+            Layout::ReversePostorder => self.reverse_postorder(),
+            Layout::Traces => {
+                let mut placed = HashSet::new();
+                let mut order = Vec::new();
+                for start in self.reverse_postorder() {
+                    let mut node = start;
+                    while node != self.exit && placed.insert(node) {
+                        order.push(node);
+                        match self.preferred_successor(node) {
+                            Some(next) => node = next,
+                            None => break,
+                        }
+                    }
+                }
+                order
+            }
+        };
+        order.retain(|&n| n != self.exit);
+        order
+    }
+
+    // the successor worth placing right after `node`: where it falls through
+    // or jumps to, or the `else` side of a branch
+    fn preferred_successor(&self, node: NodeIndex) -> Option<NodeIndex> {
+        match self.graph[node].instructions.last() {
+            Some(Instruction::Ret { .. }) => None,
+            Some(Instruction::Br { else_label, .. }) => self.block(else_label),
+            Some(Instruction::Jmp { label }) => self.block(label),
+            _ => self.successors(node).next(),
+        }
+    }
+
+    // the blocks of `order` one after another, every block except the exit
+    // must be in it
+    fn emit(&self, order: &[NodeIndex], drop_jumps: bool) -> Function {
+        // where a block that does not end in a jump goes next, if that is
+        // not simply the block after it
+        let mut fall_through: HashMap<NodeIndex, Instruction> = HashMap::new();
+        let mut jumped_to: HashSet<NodeIndex> = HashSet::new();
+        for (i, &node) in order.iter().enumerate() {
+            let next = order.get(i + 1).copied();
+            match self.graph[node].instructions.last() {
+                Some(instr) if is_terminator(instr) => {
+                    if !matches!(instr, Instruction::Ret { .. }) {
+                        jumped_to.extend(self.successors(node));
+                    }
+                }
+                _ => match self.successors(node).next() {
+                    Some(succ) if succ == self.exit => {
+                        if next.is_some() {
+                            fall_through.insert(node, Instruction::Ret { value: None });
+                        }
+                    }
+                    Some(succ) if Some(succ) != next => {
+                        jumped_to.insert(succ);
+                        fall_through.insert(node, Instruction::Jmp { label: self.graph[succ].name.clone() });
+                    }
+                    Some(_) => {}
+                    None => {
+                        if next.is_some() {
+                            fall_through.insert(node, Instruction::Ret { value: None });
+                        }
+                    }
+                },
+            }
+        }
+
+        let mut instr = Vec::new();
+        for (i, &node) in order.iter().enumerate() {
+            let block = &self.graph[node];
+            let labeled = matches!(block.instructions.first(), Some(Instruction::Label { .. }));
+            if jumped_to.contains(&node) && !labeled {
+                instr.push(Instruction::Label { label: block.name.clone() });
+            }
+            let mut body = block.instructions.as_slice();
+            if let (true, Some(Instruction::Jmp { label }), Some(next)) = (drop_jumps, body.last(), order.get(i + 1))
+                && self.graph[*next].name == *label
+            {
+                body = &body[..body.len() - 1];
+            }
+            instr.extend(body.iter().cloned());
+            instr.extend(fall_through.remove(&node));
         }
+
+        Function { name: self.name.clone(), args: self.args.clone(), ret_type: self.ret_type.clone(), instr }
     }
 }
 
+/// Block orders for `Cfg::linearize`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// Index order, which is source order for a freshly built `Cfg`.
+    Original,
+    ReversePostorder,
+    /// Chains of blocks each followed by where it jumps (or the `else` side
+    /// of its branch), so most unconditional jumps disappear.
+    Traces,
+}
 
+impl std::ops::Deref for Cfg {
+    type Target = DiGraph<BasicBlock, ()>;
 
+    fn deref(&self) -> &Self::Target {
+        &self.graph
+    }
+}
 
-fn is_terminator(i: &Instruction) -> bool {
-    matches!(i, Instruction::Br {..} | Instruction::Jmp{..} | Instruction::Ret{..})
+impl std::ops::Index<NodeIndex> for Cfg {
+    type Output = BasicBlock;
+
+    fn index(&self, node: NodeIndex) -> &BasicBlock {
+        &self.graph[node]
+    }
+}
+
+impl std::ops::IndexMut<NodeIndex> for Cfg {
+    fn index_mut(&mut self, node: NodeIndex) -> &mut BasicBlock {
+        &mut self.graph[node]
+    }
 }
 
 #[cfg(test)]
@@ -108,53 +393,26 @@ mod tests{
     use petgraph::Direction;
 
     use super::*;
+    use crate::interp::{interpret, DEFAULT_STEP_LIMIT};
+    use crate::text::parse_function;
+    use crate::verify::verify_cfg;
 
     #[test]
     fn test_cfg_graph() {
-        let f = Function {
-            name: "Main".to_string(),
-            instr: vec![
-                // ---- block0 ----
-                Instruction::Const {
-                    dest: "v0".to_string(),
-                    typ: Types::Bool,
-                    values: Literal::Bool(true),
-                },
-                Instruction::Br {
-                    cond: "v0".to_string(),
-                    then_label: "then_blk".to_string(),
-                    else_label: "else_blk".to_string(),
-                },
-
-                // ---- then_blk (block1) ----
-                Instruction::Label{label: "then_blk".to_string()},
-                Instruction::Const {
-                    dest: "v1".to_string(),
-                    typ: Types::Int,
-                    values: Literal::Int(10),
-                },
-                Instruction::Jmp {
-                    label: "merge_blk".to_string(),
-                },
-
-                // ---- else_blk (block2) ----
-                Instruction::Label{label:"else_blk".to_string()},
-                Instruction::Const {
-                    dest: "v2".to_string(),
-                    typ: Types::Int,
-                    values: Literal::Int(20),
-                },
-                Instruction::Jmp {
-                    label: "merge_blk".to_string(),
-                },
-
-                // ---- merge_blk (block3) ----
-                Instruction::Label{label: "merge_blk".to_string()},
-                Instruction::Ret {
-                    value: Some("v1".to_string()),
-                },
-            ],
-        };
+        let f = parse_function("
+@main {
+  v0: bool = const true
+  br v0 .then_blk .else_blk
+.then_blk:
+  v1: int = const 10
+  jmp .merge_blk
+.else_blk:
+  v2: int = const 20
+  jmp .merge_blk
+.merge_blk:
+  ret v1
+}
+").unwrap();
 
         let blocks = build_blocks(&f);
         let cfg = build_cfg(&blocks);
@@ -190,4 +448,278 @@ mod tests{
         assert_eq!(cfg.edge_count(), 4);  // block0->then, block0->else, then->merge, else->merge, no return edge
     }
 
+    const DIAMOND: &str = "
+@main {
+  v0: bool = const true
+  br v0 .then_blk .else_blk
+.then_blk:
+  v1: int = const 10
+  jmp .merge_blk
+.else_blk:
+  v1: int = const 20
+  jmp .merge_blk
+.merge_blk:
+  print v1
+}
+";
+
+    #[test]
+    fn test_cfg_struct() {
+        let f = parse_function(DIAMOND).unwrap();
+        let cfg = Cfg::from_function(&f);
+
+        assert_eq!(cfg[cfg.entry].name, "block0");
+        assert_eq!(cfg[cfg.exit].name, "exit");
+        assert!(cfg[cfg.exit].instructions.is_empty());
+        assert_eq!(cfg.node_count(), 5);
+        assert_eq!(cfg.edge_count(), 5);
+
+        let merge = cfg.block("merge_blk").unwrap();
+        let mut preds: Vec<&str> = cfg.predecessors(merge).map(|n| cfg[n].name.as_str()).collect();
+        preds.sort();
+        assert_eq!(preds, vec!["else_blk", "then_blk"]);
+        assert_eq!(cfg.successors(merge).collect::<Vec<_>>(), vec![cfg.exit]);
+        assert_eq!(cfg.block("nowhere"), None);
+
+        let rpo = cfg.reverse_postorder();
+        assert_eq!(rpo.len(), 5);
+        assert_eq!(rpo[0], cfg.entry);
+        assert_eq!(rpo[3], merge);
+        assert_eq!(rpo[4], cfg.exit);
+        let mut po = cfg.postorder();
+        po.reverse();
+        assert_eq!(po, rpo);
+
+        assert_eq!(cfg.to_function(), f);
+    }
+
+    #[test]
+    fn test_cfg_round_trip() {
+        // a dead unlabeled block, a loop and a `ret` in the middle
+        let f = parse_function("
+@f(n: int): int {
+  one: int = const 1
+  jmp .top
+.top:
+  n = sub n one
+  big = gt n one
+  br big .top .out
+.out:
+  ret n
+  print n
+}
+")
+        .unwrap();
+        let cfg = Cfg::from_function(&f);
+        assert_eq!(cfg.postorder().len(), 4); // the dead block is not reachable
+        assert_eq!(cfg.to_function(), f);
+
+        let empty = Function { name: "e".into(), args: vec![], ret_type: None, instr: vec![] };
+        let cfg = Cfg::from_function(&empty);
+        assert_eq!(cfg.node_count(), 2);
+        assert_eq!(cfg.to_function(), empty);
+    }
+
+    fn names(cfg: &Cfg, nodes: impl Iterator<Item = NodeIndex>) -> Vec<&str> {
+        let mut names: Vec<&str> = nodes.map(|n| cfg[n].name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_labeled_fall_through() {
+        // the entry keeps its label and is a loop header, `.b` is reached by
+        // falling through, and the label after `print` starts a new block
+        let f = parse_function("
+@f {
+.a:
+  x: int = const 1
+.b:
+  print x
+.c:
+  y = lt x x
+  br y .a .d
+.d:
+}
+")
+        .unwrap();
+        let blocks = build_blocks(&f);
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks.iter().map(|b| b.len()).collect::<Vec<_>>(), vec![2, 2, 3, 1]);
+
+        let cfg = Cfg::from_function(&f);
+        let (a, b, c, d) = ["a", "b", "c", "d"].map(|name| cfg.block(name).unwrap()).into();
+        assert_eq!(cfg.entry, a);
+        assert_eq!(names(&cfg, cfg.successors(a)), vec!["b"]);
+        assert_eq!(names(&cfg, cfg.successors(b)), vec!["c"]);
+        assert_eq!(names(&cfg, cfg.successors(c)), vec!["a", "d"]);
+        assert_eq!(names(&cfg, cfg.predecessors(a)), vec!["c"]);
+        assert_eq!(cfg.successors(d).collect::<Vec<_>>(), vec![cfg.exit]);
+        assert_eq!(cfg.to_function(), f);
+    }
+
+    #[test]
+    fn test_multi_entry_loop() {
+        // both `.left` and `.right` are entered from outside the loop they form
+        let f = parse_function("
+@f {
+  c: bool = const true
+  br c .left .right
+.left:
+  print c
+.right:
+  c = not c
+  br c .left .out
+.out:
+  ret
+}
+")
+        .unwrap();
+        let cfg = Cfg::from_function(&f);
+        let (left, right) = (cfg.block("left").unwrap(), cfg.block("right").unwrap());
+        assert_eq!(names(&cfg, cfg.predecessors(left)), vec!["block0", "right"]);
+        assert_eq!(names(&cfg, cfg.predecessors(right)), vec!["block0", "left"]);
+        assert_eq!(cfg.edge_count(), 6);
+        assert_eq!(cfg.to_function(), f);
+        assert!(verify_cfg(&cfg).is_ok());
+    }
+
+    #[test]
+    fn test_empty_blocks_and_names() {
+        // label only blocks, and an unlabeled block whose default name is taken
+        let f = parse_function("
+@f {
+  jmp .block1
+  print x
+.block1:
+.next:
+  ret
+.end:
+}
+")
+        .unwrap();
+        let blocks = build_blocks(&f);
+        assert_eq!(blocks.len(), 5);
+
+        let cfg = Cfg::from_function(&f);
+        let all = names(&cfg, cfg.node_indices());
+        assert_eq!(all, vec!["block0", "block1", "block1.1", "end", "exit", "next"]);
+        assert_eq!(names(&cfg, cfg.predecessors(cfg.block("block1").unwrap())), vec!["block0", "block1.1"]);
+        assert_eq!(names(&cfg, cfg.successors(cfg.block("block1.1").unwrap())), vec!["block1"]);
+        assert_eq!(names(&cfg, cfg.successors(cfg.block("block1").unwrap())), vec!["next"]);
+        assert_eq!(names(&cfg, cfg.predecessors(cfg.exit)), vec!["end", "next"]);
+        assert_eq!(cfg.to_function(), f);
+
+        assert!(build_blocks(&Function { name: "e".into(), args: vec![], ret_type: None, instr: vec![] }).is_empty());
+    }
+
+    #[test]
+    fn test_linearize() {
+        let f = parse_function(DIAMOND).unwrap();
+        let cfg = Cfg::from_function(&f);
+
+        // else_blk follows the branch and merge_blk follows else_blk, so
+        // only then_blk still needs its jump
+        let g = cfg.linearize(Layout::Traces);
+        let labels: Vec<&String> = g.instr.iter().filter_map(|i| match i {
+            Instruction::Label { label } => Some(label),
+            _ => None,
+        }).collect();
+        assert_eq!(labels, vec!["else_blk", "merge_blk", "then_blk"]);
+        assert_eq!(g.instr.len(), f.instr.len());
+        assert_eq!(g.instr.last(), Some(&Instruction::Jmp { label: "merge_blk".into() }));
+        assert_eq!(interpret(&g, DEFAULT_STEP_LIMIT).unwrap().output, vec!["10"]);
+
+        // unreachable blocks are left out, the labels of the others stay
+        let f = parse_function("
+@f {
+  a: int = const 1
+  jmp .end
+.dead:
+  print a
+  jmp .end
+.end:
+  print a
+}
+")
+        .unwrap();
+        let mut cfg = Cfg::from_function(&f);
+        let end = cfg.block("end").unwrap();
+        cfg[end].instructions.push(Instruction::Jmp { label: "more".into() });
+        let more = cfg.add_block(BasicBlock { name: "more".into(), instructions: vec![Instruction::Print { value: "a".into() }] });
+        cfg.remove_edge(end, cfg.exit);
+        cfg.add_edge(end, more);
+        cfg.add_edge(more, cfg.exit);
+        let g = cfg.linearize(Layout::ReversePostorder);
+        assert_eq!(
+            g.to_string(),
+            "@f {\n  a: int = const 1\n.end:\n  print a\n.more:\n  print a\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_edit_then_emit() {
+        let f = parse_function(DIAMOND).unwrap();
+        let mut cfg = Cfg::from_function(&f);
+
+        // route then_blk through a new block that falls through into the merge
+        let then = cfg.block("then_blk").unwrap();
+        let merge = cfg.block("merge_blk").unwrap();
+        let name = cfg.fresh_name("then_blk");
+        assert_eq!(name, "then_blk.1");
+        let pad = cfg.add_block(BasicBlock { name, instructions: vec![Instruction::Print { value: "v1".into() }] });
+        *cfg[then].instructions.last_mut().unwrap() = Instruction::Jmp { label: "then_blk.1".into() };
+        cfg.remove_edge(then, merge);
+        cfg.add_edge(then, pad);
+        cfg.add_edge(pad, merge);
+
+        let g = cfg.to_function();
+        assert_eq!(g.instr[g.instr.len() - 3..], [
+            Instruction::Label { label: "then_blk.1".into() },
+            Instruction::Print { value: "v1".into() },
+            Instruction::Jmp { label: "merge_blk".into() },
+        ]);
+        let out = interpret(&g, DEFAULT_STEP_LIMIT).unwrap();
+        assert_eq!(out.output, vec!["10", "10"]);
+
+        // dropping the else block moves the pad into its index
+        let else_blk = cfg.block("else_blk").unwrap();
+        cfg.remove_block(else_blk);
+        assert_eq!(cfg.block("then_blk.1"), Some(else_blk));
+        assert_eq!(cfg[cfg.exit].name, "exit");
+        assert_eq!(cfg.node_count(), 5);
+    }
+
+    #[test]
+    fn test_remove_unreachable_blocks() {
+        let f = parse_function("
+@main {
+  a: int = const 1
+  jmp .live
+.dead1:
+  jmp .dead2
+.live:
+  print a
+  jmp .end
+.dead2:
+  a: int = const 2
+.dead3:
+  print a
+.end:
+  print a
+}
+").unwrap();
+        let mut cfg = Cfg::from_function(&f);
+        assert_eq!(cfg.remove_unreachable_blocks(), vec!["dead1", "dead2", "dead3"]);
+        assert_eq!(cfg.node_count(), 4);
+        for name in ["block0", "live", "end"] {
+            let node = cfg.block(name).unwrap();
+            assert_eq!(cfg[node].name, name);
+        }
+        assert_eq!(cfg[cfg.exit].name, "exit");
+        assert!(cfg.remove_unreachable_blocks().is_empty());
+        let out = interpret(&cfg.to_function(), DEFAULT_STEP_LIMIT).unwrap();
+        assert_eq!(out.output, vec!["1", "1"]);
+    }
+
 }
@@ -0,0 +1,644 @@
+use std::collections::{HashMap, HashSet};
+
+use petgraph::graph::NodeIndex;
+
+use crate::cfg::*;
+use crate::dataflow::*;
+use crate::lvn::{fold_instr, get_dest};
+use crate::types::*;
+
+// Global constant propagation: a forward dataflow analysis over the CFG that
+// knows, at every point, which variables hold a single constant. Operations on
+// constants become `Const`s and branches on constants become jumps, after
+// which the blocks that can no longer run are dropped.
+//
+// `sccp` is the sparse conditional version (Wegman and Zadeck) for SSA form:
+// one value per variable instead of one per program point, and a block is
+// only looked at once an edge into it is known to be taken, so a constant
+// branch keeps its dead side from spoiling the phis below it.
+
+/// What is known about a variable at some point.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Lattice {
+    /// No definition reaches yet (or only along paths not seen so far).
+    Undefined,
+    Constant(Literal),
+    /// Might be more than one value.
+    Overdefined,
+}
+
+impl Lattice {
+    /// The least upper bound, what a variable is after a merge.
+    pub fn join(&self, other: &Lattice) -> Lattice {
+        match (self, other) {
+            (Lattice::Undefined, x) | (x, Lattice::Undefined) => x.clone(),
+            (Lattice::Constant(a), Lattice::Constant(b)) if a == b => self.clone(),
+            _ => Lattice::Overdefined,
+        }
+    }
+}
+
+/// The analysis for `solve`. A fact is a set of `(variable, value)` pairs
+/// with at most one pair per variable; a variable without a pair is
+/// `Undefined`, so bottom is the empty set.
+pub struct ConstProp;
+
+impl AbstractDataflow for ConstProp {
+    type Domain = (String, Lattice);
+
+    fn direction() -> GDirection {
+        GDirection::Forward
+    }
+
+    fn bottom() -> HashSet<(String, Lattice)> {
+        HashSet::new()
+    }
+
+    // arguments can be anything
+    fn boundary(cfg: &Cfg) -> HashSet<(String, Lattice)> {
+        cfg.args.iter().map(|arg| (arg.name.clone(), Lattice::Overdefined)).collect()
+    }
+
+    fn merge(in_sets: Vec<HashSet<(String, Lattice)>>) -> HashSet<(String, Lattice)> {
+        let mut values: HashMap<String, Lattice> = HashMap::new();
+        for (var, value) in in_sets.into_iter().flatten() {
+            let joined = values.get(&var).map_or(value.clone(), |old| old.join(&value));
+            values.insert(var, joined);
+        }
+        values.into_iter().collect()
+    }
+
+    fn transfer(block: &BasicBlock, _offset: usize, in_set: HashSet<(String, Lattice)>) -> HashSet<(String, Lattice)> {
+        let mut values: HashMap<String, Lattice> = in_set.into_iter().collect();
+        for instr in &block.instructions {
+            step(instr, &mut values);
+        }
+        values.into_iter().filter(|(_, value)| *value != Lattice::Undefined).collect()
+    }
+}
+
+/// The value `instr` gives its destination, given the values before it.
+pub fn evaluate(instr: &Instruction, values: &HashMap<String, Lattice>) -> Lattice {
+    let value = |var: &String| values.get(var).cloned().unwrap_or(Lattice::Undefined);
+    match instr {
+        Instruction::Const { values, .. } => Lattice::Constant(values.clone()),
+        Instruction::Id { src, .. } | Instruction::Move { src, .. } => value(src),
+        // without knowing which edge was taken, any argument may be the one
+        Instruction::Phi { args, .. } => args.iter().fold(Lattice::Undefined, |acc, (_, var)| acc.join(&value(var))),
+        _ if instr.as_binary().is_some() || matches!(instr, Instruction::Not { .. }) => {
+            let operands: Vec<Lattice> = crate::lvn::get_used_var(instr).iter().map(value).collect();
+            if operands.contains(&Lattice::Overdefined) {
+                return Lattice::Overdefined;
+            }
+            if operands.contains(&Lattice::Undefined) {
+                return Lattice::Undefined;
+            }
+            let folded = fold_instr(instr, |var| match value(var) {
+                Lattice::Constant(lit) => Some(lit),
+                _ => None,
+            });
+            match folded {
+                Some(Instruction::Const { values, .. }) => Lattice::Constant(values),
+                // division by zero is left to trap
+                _ => Lattice::Overdefined,
+            }
+        }
+        // loads, calls and pointers are not tracked
+        _ => Lattice::Overdefined,
+    }
+}
+
+fn step(instr: &Instruction, values: &mut HashMap<String, Lattice>) {
+    if let Some(dest) = get_dest(instr) {
+        let value = evaluate(instr, values);
+        values.insert(dest.clone(), value);
+    }
+}
+
+pub fn literal_type(lit: &Literal) -> Types {
+    match lit {
+        Literal::Int(_) => Types::Int,
+        Literal::Float(_) => Types::Float,
+        Literal::Bool(_) => Types::Bool,
+    }
+}
+
+/// Replaces every computation of a known constant by a `Const`, every branch
+/// on a known condition by a `jmp`, and drops the blocks no longer reached.
+/// Repeated until nothing changes, since dropping a block can make values
+/// merged from it constant.
+pub fn constant_propagation(f: &Function) -> Function {
+    let mut f = f.clone();
+    loop {
+        let mut cfg = Cfg::from_function(&f);
+        let facts = solve::<ConstProp>(&cfg);
+        let mut changed = false;
+
+        for node in cfg.node_indices().collect::<Vec<_>>() {
+            let mut values: HashMap<String, Lattice> = facts.in_sets[&node].iter().cloned().collect();
+            let mut instructions = cfg[node].instructions.clone();
+            let mut dropped_edge = None;
+
+            for instr in instructions.iter_mut() {
+                if let Instruction::Br { cond, then_label, else_label } = instr
+                    && let Some(Lattice::Constant(Literal::Bool(taken))) = values.get(cond)
+                {
+                    let (label, other) = if *taken { (then_label, else_label) } else { (else_label, then_label) };
+                    if label != other {
+                        dropped_edge = cfg.block(other);
+                    }
+                    *instr = Instruction::Jmp { label: label.clone() };
+                    changed = true;
+                    continue;
+                }
+
+                let value = evaluate(instr, &values);
+                if let (Some(dest), Lattice::Constant(lit)) = (get_dest(instr), &value)
+                    && !matches!(instr, Instruction::Const { .. })
+                    && !instr.has_side_effects()
+                {
+                    *instr = Instruction::Const { dest: dest.clone(), typ: literal_type(lit), values: lit.clone() };
+                    changed = true;
+                }
+                step(instr, &mut values);
+            }
+
+            cfg[node].instructions = instructions;
+            if let Some(other) = dropped_edge {
+                cfg.remove_edge(node, other);
+            }
+        }
+
+        if !cfg.remove_unreachable_blocks().is_empty() {
+            changed = true;
+        }
+        if !changed {
+            return f;
+        }
+        prune_phi_args(&mut cfg);
+        f = cfg.to_function();
+    }
+}
+
+// drops the phi arguments whose label is no longer a predecessor, after edges
+// or blocks were removed
+fn prune_phi_args(cfg: &mut Cfg) {
+    for node in cfg.node_indices().collect::<Vec<_>>() {
+        let preds: HashSet<String> = cfg.predecessors(node).map(|p| cfg[p].name.clone()).collect();
+        for instr in cfg[node].instructions.iter_mut() {
+            if let Instruction::Phi { args, .. } = instr {
+                args.retain(|(label, _)| preds.contains(label));
+            }
+        }
+    }
+}
+
+/// What `sccp_analysis` proved: the value of every variable over the whole
+/// function, and the edges and blocks that can be executed.
+#[derive(Clone, Debug, Default)]
+pub struct Sccp {
+    pub values: HashMap<String, Lattice>,
+    pub edges: HashSet<(NodeIndex, NodeIndex)>,
+    pub blocks: HashSet<NodeIndex>,
+}
+
+/// The blocks and edges `sccp` proved can never run, by block name, sorted.
+/// The exit only appears as the target of an edge.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SccpReport {
+    pub unreachable_blocks: Vec<String>,
+    pub unreachable_edges: Vec<(String, String)>,
+}
+
+impl Sccp {
+    pub fn value(&self, var: &String) -> Lattice {
+        self.values.get(var).cloned().unwrap_or(Lattice::Undefined)
+    }
+
+    pub fn report(&self, cfg: &Cfg) -> SccpReport {
+        let name = |n: NodeIndex| cfg[n].name.clone();
+        let mut unreachable_blocks: Vec<String> =
+            cfg.node_indices().filter(|n| *n != cfg.exit && !self.blocks.contains(n)).map(name).collect();
+        let mut unreachable_edges: Vec<(String, String)> = cfg
+            .edge_indices()
+            .filter_map(|e| cfg.edge_endpoints(e))
+            .filter(|edge| !self.edges.contains(edge))
+            .map(|(from, to)| (name(from), name(to)))
+            .collect();
+        unreachable_blocks.sort();
+        unreachable_edges.sort();
+        unreachable_edges.dedup();
+        SccpReport { unreachable_blocks, unreachable_edges }
+    }
+
+    // the value of a phi, from the arguments whose edge is executable
+    fn phi_value(&self, cfg: &Cfg, node: NodeIndex, args: &[(String, String)]) -> Lattice {
+        args.iter()
+            .filter(|(label, _)| cfg.block(label).is_some_and(|pred| self.edges.contains(&(pred, node))))
+            .fold(Lattice::Undefined, |acc, (_, var)| acc.join(&self.value(var)))
+    }
+}
+
+// the two worklists of the algorithm: edges that just became executable and
+// variables whose value just went up
+struct Propagation<'a> {
+    cfg: &'a Cfg,
+    uses: HashMap<String, Vec<(NodeIndex, usize)>>,
+    flow: Vec<(NodeIndex, NodeIndex)>,
+    ssa: Vec<String>,
+    result: Sccp,
+}
+
+impl Propagation<'_> {
+    fn visit_block(&mut self, node: NodeIndex) {
+        if self.cfg[node].instructions.is_empty() {
+            self.flow.extend(self.cfg.successors(node).map(|succ| (node, succ)));
+        }
+        for index in 0..self.cfg[node].instructions.len() {
+            self.visit(node, index);
+        }
+    }
+
+    fn visit(&mut self, node: NodeIndex, index: usize) {
+        let cfg = self.cfg;
+        let instructions = &cfg[node].instructions;
+        let instr = &instructions[index];
+        let target = |label: &String| cfg.block(label).map(|succ| (node, succ));
+        match instr {
+            Instruction::Jmp { label } => self.flow.extend(target(label)),
+            // an undefined condition takes neither edge until it gets a value
+            Instruction::Br { cond, then_label, else_label } => match self.result.value(cond) {
+                Lattice::Constant(Literal::Bool(true)) => self.flow.extend(target(then_label)),
+                Lattice::Constant(Literal::Bool(false)) => self.flow.extend(target(else_label)),
+                Lattice::Undefined => {}
+                _ => self.flow.extend(target(then_label).into_iter().chain(target(else_label))),
+            },
+            // `ret` and falling through
+            _ if index + 1 == instructions.len() => {
+                self.flow.extend(cfg.successors(node).map(|succ| (node, succ)));
+            }
+            _ => {}
+        }
+
+        let Some(dest) = get_dest(instr) else {
+            return;
+        };
+        let value = match instr {
+            Instruction::Phi { args, .. } => self.result.phi_value(cfg, node, args),
+            _ => evaluate(instr, &self.result.values),
+        };
+        // joined with what other definitions gave, so input not in SSA form
+        // still gets a sound (if weaker) answer
+        let old = self.result.value(dest);
+        let new = old.join(&value);
+        if new != old {
+            self.result.values.insert(dest.clone(), new);
+            self.ssa.push(dest.clone());
+        }
+    }
+}
+
+/// Sparse conditional constant propagation over `cfg`, which should be in
+/// SSA form. Arguments are `Overdefined`, everything else starts `Undefined`.
+pub fn sccp_analysis(cfg: &Cfg) -> Sccp {
+    let mut uses: HashMap<String, Vec<(NodeIndex, usize)>> = HashMap::new();
+    for node in cfg.node_indices() {
+        for (index, instr) in cfg[node].instructions.iter().enumerate() {
+            for var in crate::lvn::get_used_var(instr) {
+                uses.entry(var).or_default().push((node, index));
+            }
+        }
+    }
+    let values = cfg.args.iter().map(|arg| (arg.name.clone(), Lattice::Overdefined)).collect();
+    let mut prop = Propagation {
+        cfg,
+        uses,
+        flow: Vec::new(),
+        ssa: Vec::new(),
+        result: Sccp { values, edges: HashSet::new(), blocks: HashSet::from([cfg.entry]) },
+    };
+
+    prop.visit_block(cfg.entry);
+    loop {
+        if let Some(edge) = prop.flow.pop() {
+            if !prop.result.edges.insert(edge) {
+                continue;
+            }
+            let (_, node) = edge;
+            if prop.result.blocks.insert(node) {
+                prop.visit_block(node);
+            } else {
+                // only the phis depend on which edges come in
+                let phis = cfg[node].instructions.iter().enumerate().filter(|(_, i)| matches!(i, Instruction::Phi { .. }));
+                for index in phis.map(|(index, _)| index).collect::<Vec<_>>() {
+                    prop.visit(node, index);
+                }
+            }
+        } else if let Some(var) = prop.ssa.pop() {
+            let uses = prop.uses.get(&var).cloned().unwrap_or_default();
+            for (node, index) in uses {
+                if prop.result.blocks.contains(&node) {
+                    prop.visit(node, index);
+                }
+            }
+        } else {
+            return prop.result;
+        }
+    }
+}
+
+/// Runs `sccp_analysis` and rewrites `f` with it: constant definitions
+/// (phis included) become `Const`s, phis lose the arguments of edges never
+/// taken, branches on constants become jumps
+/// and the blocks no longer reached are dropped. Also returns what was
+/// proven unreachable in the input.
+pub fn sccp(f: &Function) -> (Function, SccpReport) {
+    let mut cfg = Cfg::from_function(f);
+    let analysis = sccp_analysis(&cfg);
+    let report = analysis.report(&cfg);
+
+    let mut dropped_edges = Vec::new();
+    for &node in &analysis.blocks {
+        let mut instructions = Vec::new();
+        // a constant replacing a phi goes after the last phi, phis read in parallel
+        let mut copies = Vec::new();
+        for instr in &cfg[node].instructions {
+            if let Instruction::Phi { dest, args } = instr {
+                if let Lattice::Constant(lit) = analysis.value(dest) {
+                    copies.push(Instruction::Const { dest: dest.clone(), typ: literal_type(&lit), values: lit });
+                    continue;
+                }
+                // one argument left is still a phi, an `id` would fail where
+                // the phi leaves its destination undefined
+                let mut args = args.clone();
+                args.retain(|(label, _)| cfg.block(label).is_some_and(|pred| analysis.edges.contains(&(pred, node))));
+                instructions.push(Instruction::Phi { dest: dest.clone(), args });
+                continue;
+            }
+            if !matches!(instr, Instruction::Label { .. }) {
+                instructions.append(&mut copies);
+            }
+
+            if let Instruction::Br { cond, then_label, else_label } = instr
+                && let Lattice::Constant(Literal::Bool(taken)) = analysis.value(cond)
+            {
+                let (label, other) = if taken { (then_label, else_label) } else { (else_label, then_label) };
+                if label != other {
+                    dropped_edges.extend(cfg.block(other).map(|other| (node, other)));
+                }
+                instructions.push(Instruction::Jmp { label: label.clone() });
+                continue;
+            }
+            match (get_dest(instr), evaluate(instr, &analysis.values)) {
+                (Some(dest), Lattice::Constant(lit))
+                    if !matches!(instr, Instruction::Const { .. }) && !instr.has_side_effects() =>
+                {
+                    instructions.push(Instruction::Const { dest: dest.clone(), typ: literal_type(&lit), values: lit });
+                }
+                _ => instructions.push(instr.clone()),
+            }
+        }
+        instructions.append(&mut copies);
+        cfg[node].instructions = instructions;
+    }
+
+    for (from, to) in dropped_edges {
+        cfg.remove_edge(from, to);
+    }
+    cfg.remove_unreachable_blocks();
+    prune_phi_args(&mut cfg);
+    (cfg.to_function(), report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::parse_function;
+
+    #[test]
+    fn test_lattice_join() {
+        let one = Lattice::Constant(Literal::Int(1));
+        let two = Lattice::Constant(Literal::Int(2));
+        assert_eq!(Lattice::Undefined.join(&one), one);
+        assert_eq!(one.join(&Lattice::Undefined), one);
+        assert_eq!(one.join(&one), one);
+        assert_eq!(one.join(&two), Lattice::Overdefined);
+        assert_eq!(Lattice::Overdefined.join(&Lattice::Undefined), Lattice::Overdefined);
+    }
+
+    #[test]
+    fn test_constants_cross_blocks() {
+        let f = parse_function("
+@main {
+  v0: bool = const true
+  a: int = const 4
+  br v0 .then_blk .else_blk
+.then_blk:
+  v1 = add a a
+  jmp .merge_blk
+.else_blk:
+  v1: int = const 20
+  jmp .merge_blk
+.merge_blk:
+  v2 = mul v1 a
+  print v2
+}
+")
+        .unwrap();
+        // only then_blk runs, so v1 is 8 at the merge
+        assert_eq!(constant_propagation(&f).to_string(), "@main {
+  v0: bool = const true
+  a: int = const 4
+  jmp .then_blk
+.then_blk:
+  v1: int = const 8
+  jmp .merge_blk
+.merge_blk:
+  v2: int = const 32
+  print v2
+}
+");
+    }
+
+    #[test]
+    fn test_merges_and_loops() {
+        let f = parse_function("
+@main(n: int) {
+  one: int = const 1
+  i: int = const 0
+  c = lt i n
+  br c .a .b
+.a:
+  k: int = const 3
+  x: int = const 5
+  jmp .loop
+.b:
+  k: int = const 3
+  x: int = const 6
+.loop:
+  i = add i one
+  two = add one one
+  kk = add k k
+  xx = add x x
+  done = eq i n
+  br done .out .loop
+.out:
+  print kk
+  print xx
+  print two
+}
+")
+        .unwrap();
+        let cfg = Cfg::from_function(&f);
+        let facts = solve::<ConstProp>(&cfg);
+        let at_loop: HashMap<String, Lattice> = facts.in_sets[&cfg.block("loop").unwrap()].iter().cloned().collect();
+        assert_eq!(at_loop["k"], Lattice::Constant(Literal::Int(3)));
+        assert_eq!(at_loop["x"], Lattice::Overdefined);
+        assert_eq!(at_loop["i"], Lattice::Overdefined);
+        assert_eq!(at_loop["n"], Lattice::Overdefined);
+        assert_eq!(at_loop.get("kk"), Some(&Lattice::Constant(Literal::Int(6))));
+
+        let g = constant_propagation(&f);
+        let consts: Vec<String> = g.instr.iter().filter_map(|i| match i {
+            Instruction::Const { dest, values, .. } => Some(format!("{} {}", dest, values)),
+            _ => None,
+        }).collect();
+        assert!(consts.contains(&"kk 6".to_string()));
+        assert!(consts.contains(&"two 2".to_string()));
+        assert!(!consts.iter().any(|c| c.starts_with("xx") || c == "i 1" || c.starts_with("c ")));
+        assert_eq!(g.instr.len(), f.instr.len());
+    }
+
+    #[test]
+    fn test_division_by_zero_is_kept() {
+        let f = parse_function("
+@main {
+  a: int = const 1
+  z: int = const 0
+  q = div a z
+  b = eq a a
+  br b .x .y
+.x:
+  print q
+.y:
+}
+")
+        .unwrap();
+        let g = constant_propagation(&f);
+        assert!(g.instr.contains(&Instruction::Div { dest: "q".into(), op1: "a".into(), op2: "z".into() }));
+        assert!(g.instr.contains(&Instruction::Jmp { label: "x".into() }));
+        // .y is still reached by falling through from .x
+        assert!(g.instr.contains(&Instruction::Label { label: "y".into() }));
+    }
+
+    #[test]
+    fn test_sccp_folds_branch_and_phi() {
+        let f = parse_function("
+@main {
+  v0: bool = const true
+  br v0 .then_blk .else_blk
+.then_blk:
+  v1: int = const 10
+  jmp .merge_blk
+.else_blk:
+  v2: int = const 20
+  jmp .merge_blk
+.merge_blk:
+  v3 = phi .then_blk v1 .else_blk v2
+  print v3
+}
+")
+        .unwrap();
+        let (g, report) = sccp(&f);
+        assert_eq!(report.unreachable_blocks, vec!["else_blk"]);
+        assert_eq!(report.unreachable_edges, vec![
+            ("block0".to_string(), "else_blk".to_string()),
+            ("else_blk".to_string(), "merge_blk".to_string()),
+        ]);
+        assert_eq!(g.to_string(), "@main {
+  v0: bool = const true
+  jmp .then_blk
+.then_blk:
+  v1: int = const 10
+  jmp .merge_blk
+.merge_blk:
+  v3: int = const 10
+  print v3
+}
+");
+    }
+
+    #[test]
+    fn test_sccp_loop_stays_constant() {
+        // `x` is only changed on a path that a branch on `x` itself rules out,
+        // which the dataflow version can't see
+        let f = parse_function("
+@main {
+.entry:
+  one: int = const 1
+  two: int = const 2
+  zero: int = const 0
+  ten: int = const 10
+.head:
+  x = phi .entry one .body x .other two
+  i = phi .entry zero .body j .other zero
+  c = eq x one
+  br c .body .other
+.body:
+  j = add i one
+  done = eq j ten
+  br done .exit .head
+.other:
+  jmp .head
+.exit:
+  print x
+  ret i
+}
+")
+        .unwrap();
+        let cfg = Cfg::from_function(&f);
+        let analysis = sccp_analysis(&cfg);
+        assert_eq!(analysis.value(&"x".to_string()), Lattice::Constant(Literal::Int(1)));
+        assert_eq!(analysis.value(&"c".to_string()), Lattice::Constant(Literal::Bool(true)));
+        assert_eq!(analysis.value(&"i".to_string()), Lattice::Overdefined);
+        assert!(!analysis.blocks.contains(&cfg.block("other").unwrap()));
+
+        let (g, report) = sccp(&f);
+        assert_eq!(report.unreachable_blocks, vec!["other"]);
+        assert!(g.instr.contains(&Instruction::Const { dest: "x".into(), typ: Types::Int, values: Literal::Int(1) }));
+        assert!(g.instr.contains(&Instruction::Phi {
+            dest: "i".into(),
+            args: vec![("entry".into(), "zero".into()), ("body".into(), "j".into())]
+        }));
+        assert!(!g.instr.contains(&Instruction::Label { label: "other".into() }));
+        let before = crate::interp::interpret(&f, crate::interp::DEFAULT_STEP_LIMIT).unwrap();
+        let after = crate::interp::interpret(&g, crate::interp::DEFAULT_STEP_LIMIT).unwrap();
+        assert_eq!((before.output, before.ret), (after.output, after.ret));
+
+        let plain = constant_propagation(&f);
+        assert!(plain.instr.iter().any(|i| matches!(i, Instruction::Phi { dest, .. } if dest == "x")));
+    }
+
+    #[test]
+    fn test_sccp_prunes_phi_arguments() {
+        let f = parse_function("
+@main(a: int) {
+.entry:
+  t: bool = const false
+  br t .left .right
+.left:
+  jmp .join
+.right:
+  jmp .join
+.join:
+  x = phi .left t .right a
+  ret x
+}
+")
+        .unwrap();
+        let (g, report) = sccp(&f);
+        assert_eq!(report.unreachable_blocks, vec!["left"]);
+        assert!(g.instr.contains(&Instruction::Phi { dest: "x".into(), args: vec![("right".into(), "a".into())] }));
+        assert!(!g.instr.contains(&Instruction::Label { label: "left".into() }));
+    }
+}
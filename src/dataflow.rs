@@ -1,13 +1,11 @@
-use std::collections::HashMap;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::hash::Hash;
+use std::marker::PhantomData;
 
 use crate::cfg::*;
 //use crate::lvn::*;
-use petgraph::Direction;
-use petgraph::{graph::DiGraph, graph::NodeIndex};
-use crate::lvn::get_dest;
-use crate::types::*;
+use petgraph::graph::NodeIndex;
+use crate::lvn::{get_dest, get_used_var};
 
 // Now to use for dataflow analysis
 
@@ -19,90 +17,184 @@ pub struct Definition{
     pub block: String,
     pub instr_index: usize,
 }
-pub struct ReachingDefintions{
-    pub in_sets: HashMap<NodeIndex, HashSet<Definition>>,
-    pub out_sets: HashMap<NodeIndex, HashSet<Definition>>
+
+impl std::fmt::Display for Definition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}:{})", self.var, self.block, self.instr_index)
+    }
+}
+
+/// IN and OUT of every block, in the direction of the CFG edges (for a
+/// backward analysis OUT is what the block's successors need), as computed
+/// by the analysis `A`.
+pub struct DataflowResult<A: AbstractDataflow> {
+    pub in_sets: HashMap<NodeIndex, HashSet<A::Domain>>,
+    pub out_sets: HashMap<NodeIndex, HashSet<A::Domain>>,
+    analysis: PhantomData<A>,
 }
 
+impl<A: AbstractDataflow> DataflowResult<A> {
+    /// The fact just before instruction `index` of block `node` runs, found by
+    /// replaying `A::transfer` over part of the block.
+    pub fn before(&self, cfg: &Cfg, node: NodeIndex, index: usize) -> HashSet<A::Domain> {
+        match A::direction() {
+            GDirection::Forward => self.replay(cfg, node, 0..index),
+            GDirection::Backward => self.replay(cfg, node, index..cfg[node].instructions.len()),
+        }
+    }
+
+    /// The fact just after instruction `index` of block `node` has run.
+    pub fn after(&self, cfg: &Cfg, node: NodeIndex, index: usize) -> HashSet<A::Domain> {
+        match A::direction() {
+            GDirection::Forward => self.replay(cfg, node, 0..index + 1),
+            GDirection::Backward => self.replay(cfg, node, index + 1..cfg[node].instructions.len()),
+        }
+    }
 
+    // `transfer` over `range` of the block, from the side the analysis
+    // starts at, told where the range starts in the block
+    fn replay(&self, cfg: &Cfg, node: NodeIndex, range: std::ops::Range<usize>) -> HashSet<A::Domain> {
+        let block = &cfg[node];
+        let offset = range.start;
+        let part = BasicBlock { name: block.name.clone(), instructions: block.instructions[range].to_vec() };
+        let start = match A::direction() {
+            GDirection::Forward => &self.in_sets[&node],
+            GDirection::Backward => &self.out_sets[&node],
+        };
+        A::transfer(&part, offset, start.clone())
+    }
+}
 
-//first case reaching definition
-pub fn reaching_definitions(cfg: &DiGraph<BasicBlock,()>) -> ReachingDefintions {
+pub type ReachingDefintions = DataflowResult<ReachingDefs>;
 
-    let mut in_sets:HashMap<NodeIndex, HashSet<Definition>> = HashMap::new();
-    let mut out_sets:HashMap<NodeIndex, HashSet<Definition>> = HashMap::new();
+/// Definitions that may reach the start and end of each block.
+pub struct ReachingDefs;
 
-    // Compute GEN and KILL for each block
-    let mut gen_sets: HashMap<NodeIndex, HashSet<Definition>> = HashMap::new();
-    let mut kill_sets: HashMap<NodeIndex, HashSet<Definition>> = HashMap::new();
+impl AbstractDataflow for ReachingDefs {
+    type Domain = Definition;
 
-    for node_idx in cfg.node_indices(){
-        let (gens,kills) = gen_and_kill(&cfg[node_idx]);
-        gen_sets.insert(node_idx, gens.clone());
-        kill_sets.insert(node_idx, kills);
-        out_sets.insert(node_idx, gens);
+    fn direction() -> GDirection {
+        GDirection::Forward
     }
 
-    let mut worklist: Vec<NodeIndex> = cfg.node_indices().collect();
+    fn bottom() -> HashSet<Definition> {
+        HashSet::new()
+    }
 
-    // while worklist is not empty we pop things out
-    while let Some(b) =  worklist.pop(){
-        
-        // get predecessors
-        let mut new_in = HashSet::new();
-        for pred in cfg.neighbors_directed(b, petgraph::Direction::Incoming){
-            new_in.extend(out_sets[&pred].iter().cloned());
-        }
-
-        let old_out = out_sets[&b].clone();
-        let gens = &gen_sets[&b];
-        let kills = &kill_sets[&b];
-        //merge meanining gen[b] U (in[b] - kill[b])
-        let mut new_out = gens.clone();
-        for def in &new_in{
-            if !kills.contains(def) {
-                new_out.insert(def.clone());
-            }
-        }
-        in_sets.insert(b, new_in);
-        out_sets.insert(b, new_out.clone());
+    fn merge(in_sets: Vec<HashSet<Definition>>) -> HashSet<Definition> {
+        in_sets.into_iter().flatten().collect()
+    }
+
+    // gen[b] U (in[b] - kill[b]), kill[b] being every other def of a variable b defines
+    fn transfer(block: &BasicBlock, offset: usize, in_set: HashSet<Definition>) -> HashSet<Definition> {
+        let gens = gen_defs(block, offset);
+        let defined: HashSet<&String> = gens.iter().map(|def| &def.var).collect();
+        let mut out: HashSet<Definition> = in_set.into_iter().filter(|def| !defined.contains(&def.var)).collect();
+        out.extend(gens);
+        out
+    }
+}
 
-        if new_out != old_out {
-            for succ in cfg.neighbors(b) {
-                if !worklist.contains(&succ){
-                    worklist.push(succ);
+pub fn reaching_definitions(cfg: &Cfg) -> ReachingDefintions {
+    solve::<ReachingDefs>(cfg)
+}
+
+/// A read of `var` by instruction `instr_index` of `block`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Use {
+    pub var: String,
+    pub block: String,
+    pub instr_index: usize,
+}
+
+/// Def-use and use-def chains from reaching definitions. Reads of function
+/// arguments (or of undefined variables) have no definitions, and an unused
+/// definition has no uses; both still get an (empty) entry.
+#[derive(Clone, Debug, Default)]
+pub struct DefUseChains {
+    pub uses: HashMap<Definition, Vec<Use>>,
+    pub defs: HashMap<Use, Vec<Definition>>,
+}
+
+pub fn def_use_chains(cfg: &Cfg) -> DefUseChains {
+    let rd = reaching_definitions(cfg);
+    let mut chains = DefUseChains::default();
+
+    for node in cfg.node_indices() {
+        let block = &cfg[node];
+        // replayed one instruction at a time rather than with `before`,
+        // which would redo the whole prefix for every instruction
+        let mut reaching = rd.in_sets[&node].clone();
+        for (i, instr) in block.instructions.iter().enumerate() {
+            for var in get_used_var(instr) {
+                let use_ = Use { var, block: block.name.clone(), instr_index: i };
+                if chains.defs.contains_key(&use_) {
+                    continue; // `add x x`
+                }
+                let mut defs: Vec<Definition> = reaching.iter().filter(|d| d.var == use_.var).cloned().collect();
+                defs.sort_by(|a, b| (&a.block, a.instr_index).cmp(&(&b.block, b.instr_index)));
+                for def in &defs {
+                    chains.uses.entry(def.clone()).or_default().push(use_.clone());
                 }
+                chains.defs.insert(use_, defs);
+            }
+            if let Some(dest) = get_dest(instr) {
+                reaching.retain(|d| d.var != *dest);
+                let def = Definition { var: dest.clone(), block: block.name.clone(), instr_index: i };
+                chains.uses.entry(def.clone()).or_default();
+                reaching.insert(def);
             }
         }
     }
-    
-
-    ReachingDefintions { in_sets: in_sets, out_sets: out_sets }
+    chains
 }
 
+/// Variables that may be read before being written again, on entry to and
+/// exit from each block.
+pub struct LiveVars;
 
-//lets compute the gen and kill part of code
-fn gen_and_kill(block: &BasicBlock) -> (HashSet<Definition>, HashSet<Definition>){
+impl AbstractDataflow for LiveVars {
+    type Domain = String;
 
-    let mut defs_by_var: HashMap<String, Definition> = HashMap::new();
-    let mut kills = HashSet::new();
+    fn direction() -> GDirection {
+        GDirection::Backward
+    }
 
-    for (i,instr) in block.instructions.iter().enumerate(){
-        if let Some(dest) = get_dest(instr){
-        let def = Definition {
-            var: dest.clone(),
-            block: block.name.clone(),
-            instr_index: i,
-        };
-        // If variable was already defined, the old def is killed
-        if let Some(old_def) = defs_by_var.insert(dest.clone(), def) {
-            kills.insert(old_def);
+    fn bottom() -> HashSet<String> {
+        HashSet::new()
+    }
+
+    fn merge(in_sets: Vec<HashSet<String>>) -> HashSet<String> {
+        in_sets.into_iter().flatten().collect()
+    }
+
+    // use[b] U (out[b] - def[b]), walking the block backwards
+    fn transfer(block: &BasicBlock, _offset: usize, mut live: HashSet<String>) -> HashSet<String> {
+        for instr in block.instructions.iter().rev() {
+            if let Some(dest) = get_dest(instr) {
+                live.remove(dest);
             }
+            live.extend(get_used_var(instr));
         }
+        live
     }
-    let gens: HashSet<Definition> = defs_by_var.values().cloned().collect();
+}
+
+pub fn live_variables(cfg: &Cfg) -> DataflowResult<LiveVars> {
+    solve::<LiveVars>(cfg)
+}
 
-    (gens,kills)
+// the last definition of each variable in the block, whose first
+// instruction is at `offset`
+fn gen_defs(block: &BasicBlock, offset: usize) -> HashSet<Definition> {
+    let mut defs_by_var: HashMap<&String, Definition> = HashMap::new();
+    for (i, instr) in block.instructions.iter().enumerate() {
+        if let Some(dest) = get_dest(instr) {
+            let def = Definition { var: dest.clone(), block: block.name.clone(), instr_index: offset + i };
+            defs_by_var.insert(dest, def);
+        }
+    }
+    defs_by_var.into_values().collect()
 }
 
 
@@ -120,65 +212,98 @@ pub trait AbstractDataflow {
     /// Bottom element - initial/empty value
     fn bottom() -> HashSet<Self::Domain>;
 
+    /// What holds where the analysis starts: on entry to the entry block of
+    /// a forward analysis (where the function's arguments are known), at the
+    /// exit of a backward one.
+    fn boundary(_cfg: &Cfg) -> HashSet<Self::Domain> {
+        Self::bottom()
+    }
+
     //merge combines either preds or succs depending on direction 
     fn merge(in_sets: Vec<HashSet<Self::Domain>>) -> HashSet<Self::Domain>;
 
     //transfer moving from out to in here its like the gen[b] U (in[b] - kill[b]) for example reaching defs
-    fn transfer(block: &BasicBlock, in_set: HashSet<Self::Domain>) -> HashSet<Self::Domain>;
+    /// `block` may be only part of a CFG block (see `DataflowResult::before`),
+    /// its first instruction being at `offset` in the real one.
+    fn transfer(block: &BasicBlock, offset: usize, in_set: HashSet<Self::Domain>) -> HashSet<Self::Domain>;
 }
 
+/// Worklist solver for any `AbstractDataflow`. Blocks start at `bottom` and
+/// are visited in reverse postorder (postorder for backward analyses), a
+/// block is revisited whenever what flows into it changes.
+///
+/// For a backward analysis `transfer` is given a block's OUT and returns its
+/// IN, and `merge` combines the INs of the successors. The boundary value is
+/// merged in at the entry (forward) or flows out of the exit (backward).
+/// Blocks that are not reachable get a result as well.
+pub fn solve<A: AbstractDataflow>(cfg: &Cfg) -> DataflowResult<A> {
+    let forward = matches!(A::direction(), GDirection::Forward);
+    let mut order = if forward { cfg.reverse_postorder() } else { cfg.postorder() };
+    let reachable: HashSet<NodeIndex> = order.iter().copied().collect();
+    order.extend(cfg.node_indices().filter(|n| !reachable.contains(n)));
+
+    // `before` is what flows into a block, `after` what its transfer gives
+    let mut before: HashMap<NodeIndex, HashSet<A::Domain>> = HashMap::new();
+    let mut after: HashMap<NodeIndex, HashSet<A::Domain>> =
+        cfg.node_indices().map(|n| (n, A::bottom())).collect();
+
+    let mut worklist: VecDeque<NodeIndex> = order.into_iter().collect();
+    let mut queued: HashSet<NodeIndex> = worklist.iter().copied().collect();
+
+    while let Some(b) = worklist.pop_front() {
+        queued.remove(&b);
+
+        let sources: Vec<NodeIndex> = if forward { cfg.predecessors(b).collect() } else { cfg.successors(b).collect() };
+        let mut inputs: Vec<HashSet<A::Domain>> = sources.iter().map(|s| after[s].clone()).collect();
+        if b == if forward { cfg.entry } else { cfg.exit } {
+            inputs.push(A::boundary(cfg));
+        }
+        let input = A::merge(inputs);
+        let output = A::transfer(&cfg[b], 0, input.clone());
+        before.insert(b, input);
+
+        if after[&b] != output {
+            after.insert(b, output);
+            let targets: Vec<NodeIndex> = if forward { cfg.successors(b).collect() } else { cfg.predecessors(b).collect() };
+            for t in targets {
+                if queued.insert(t) {
+                    worklist.push_back(t);
+                }
+            }
+        }
+    }
+
+    if forward {
+        DataflowResult { in_sets: before, out_sets: after, analysis: PhantomData }
+    } else {
+        DataflowResult { in_sets: after, out_sets: before, analysis: PhantomData }
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::generate::{for_each_seed, GenConfig};
+    use crate::text::parse_function;
 
     #[test]
 fn test_reaching_definitions() {
-    let f = Function {
-        name: "Main".to_string(),
-        instr: vec![
-            // ---- block0 ----
-            Instruction::Const {
-                dest: "v0".to_string(),
-                typ: Types::Bool,
-                values: Literal::Bool(true),
-            },
-            Instruction::Br {
-                cond: "v0".to_string(),
-                then_label: "then_blk".to_string(),
-                else_label: "else_blk".to_string(),
-            },
-
-            // ---- then_blk (block1) ----
-            Instruction::Label{label: "then_blk".to_string()},
-            Instruction::Const {
-                dest: "v1".to_string(),
-                typ: Types::Int,
-                values: Literal::Int(10),
-            },
-            Instruction::Jmp {
-                label: "merge_blk".to_string(),
-            },
-
-            // ---- else_blk (block2) ----
-            Instruction::Label{label:"else_blk".to_string()},
-            Instruction::Const {
-                dest: "v2".to_string(),
-                typ: Types::Int,
-                values: Literal::Int(20),
-            },
-            Instruction::Jmp {
-                label: "merge_blk".to_string(),
-            },
-
-            // ---- merge_blk (block3) ----
-            Instruction::Label{label: "merge_blk".to_string()},
-            Instruction::Ret {
-                value: Some("v1".to_string()),
-            },
-        ],
-    };
-
-    let blocks = build_blocks(&f);
-    let cfg = build_cfg(&blocks);
+    let f = parse_function("
+@main {
+  v0: bool = const true
+  br v0 .then_blk .else_blk
+.then_blk:
+  v1: int = const 10
+  jmp .merge_blk
+.else_blk:
+  v2: int = const 20
+  jmp .merge_blk
+.merge_blk:
+  ret v1
+}
+").unwrap();
+
+    let cfg = Cfg::from_function(&f);
     let rd = reaching_definitions(&cfg);
 
     println!("--- REACHING DEFINITIONS ANALYSIS ---\n");
@@ -212,4 +337,278 @@ fn test_reaching_definitions() {
     }
 }
 
-}
\ No newline at end of file
+
+    fn defs(set: &HashSet<Definition>) -> Vec<String> {
+        let mut defs: Vec<String> = set.iter().map(|d| d.to_string()).collect();
+        defs.sort();
+        defs
+    }
+
+    #[test]
+    fn test_reaching_definitions_kill_across_blocks() {
+        let f = parse_function("
+@main {
+  x: int = const 1
+  c: bool = const true
+.loop:
+  br c .body .out
+.body:
+  x: int = const 2
+  c: bool = const false
+  jmp .loop
+.out:
+  print x
+}
+")
+        .unwrap();
+        let cfg = Cfg::from_function(&f);
+        let rd = reaching_definitions(&cfg);
+        let (body, out) = (cfg.block("body").unwrap(), cfg.block("out").unwrap());
+
+        assert_eq!(defs(&rd.in_sets[&cfg.block("loop").unwrap()]), vec![
+            "c (block0:1)", "c (body:2)", "x (block0:0)", "x (body:1)",
+        ]);
+        // the body redefines both, nothing from the entry gets through it
+        assert_eq!(defs(&rd.out_sets[&body]), vec!["c (body:2)", "x (body:1)"]);
+        assert_eq!(rd.in_sets[&out], rd.out_sets[&out]);
+    }
+
+    // the blocks on some path from a block to the exit, itself included
+    struct Downstream;
+
+    impl AbstractDataflow for Downstream {
+        type Domain = String;
+
+        fn direction() -> GDirection {
+            GDirection::Backward
+        }
+
+        fn bottom() -> HashSet<String> {
+            HashSet::new()
+        }
+
+        fn boundary(_cfg: &Cfg) -> HashSet<String> {
+            HashSet::from(["end".to_string()])
+        }
+
+        fn merge(in_sets: Vec<HashSet<String>>) -> HashSet<String> {
+            in_sets.into_iter().flatten().collect()
+        }
+
+        fn transfer(block: &BasicBlock, _offset: usize, mut in_set: HashSet<String>) -> HashSet<String> {
+            in_set.insert(block.name.clone());
+            in_set
+        }
+    }
+
+    #[test]
+    fn test_backward_solve() {
+        let f = parse_function("
+@main {
+  c: bool = const true
+  br c .left .right
+.left:
+  jmp .left
+.right:
+  ret
+.dead:
+  ret
+}
+")
+        .unwrap();
+        let cfg = Cfg::from_function(&f);
+        let result = solve::<Downstream>(&cfg);
+        let sorted = |set: &HashSet<String>| {
+            let mut names: Vec<String> = set.iter().cloned().collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(sorted(&result.in_sets[&cfg.entry]), vec!["block0", "end", "exit", "left", "right"]);
+        assert_eq!(sorted(&result.out_sets[&cfg.entry]), vec!["end", "exit", "left", "right"]);
+        // an infinite loop never reaches the boundary
+        assert_eq!(sorted(&result.in_sets[&cfg.block("left").unwrap()]), vec!["left"]);
+        // unreachable blocks are solved too
+        assert_eq!(sorted(&result.in_sets[&cfg.block("dead").unwrap()]), vec!["dead", "end", "exit"]);
+        assert_eq!(sorted(&result.out_sets[&cfg.exit]), vec!["end"]);
+    }
+
+    // upcoming reads of each variable, as `(var, instruction index)` in the
+    // block they happen in
+    struct NextUses;
+
+    impl AbstractDataflow for NextUses {
+        type Domain = (String, usize);
+
+        fn direction() -> GDirection {
+            GDirection::Backward
+        }
+
+        fn bottom() -> HashSet<(String, usize)> {
+            HashSet::new()
+        }
+
+        fn merge(in_sets: Vec<HashSet<(String, usize)>>) -> HashSet<(String, usize)> {
+            in_sets.into_iter().flatten().collect()
+        }
+
+        fn transfer(block: &BasicBlock, offset: usize, mut uses: HashSet<(String, usize)>) -> HashSet<(String, usize)> {
+            for (i, instr) in block.instructions.iter().enumerate().rev() {
+                if let Some(dest) = get_dest(instr) {
+                    uses.retain(|(var, _)| var != dest);
+                }
+                uses.extend(get_used_var(instr).into_iter().map(|var| (var, offset + i)));
+            }
+            uses
+        }
+    }
+
+    #[test]
+    fn test_backward_facts_keep_instruction_indices() {
+        let f = parse_function("
+@main(a: int) {
+  b = add a a
+  print b
+  a = id b
+  print a
+}
+")
+        .unwrap();
+        let cfg = Cfg::from_function(&f);
+        let result = solve::<NextUses>(&cfg);
+        let sorted = |set: HashSet<(String, usize)>| {
+            let mut uses: Vec<(String, usize)> = set.into_iter().collect();
+            uses.sort();
+            uses
+        };
+        let entry = cfg.entry;
+
+        assert_eq!(sorted(result.before(&cfg, entry, 0)), vec![("a".to_string(), 0)]);
+        assert_eq!(sorted(result.after(&cfg, entry, 1)), vec![("b".to_string(), 2)]);
+        assert_eq!(sorted(result.before(&cfg, entry, 3)), vec![("a".to_string(), 3)]);
+        assert_eq!(result.before(&cfg, entry, 0), result.in_sets[&entry]);
+    }
+
+    #[test]
+    fn test_facts_inside_a_block() {
+        let f = parse_function("
+@main {
+  x: int = const 1
+  y = add x x
+  x = add y x
+  print x
+}
+")
+        .unwrap();
+        let cfg = Cfg::from_function(&f);
+        let rd = reaching_definitions(&cfg);
+        let entry = cfg.entry;
+
+        assert!(rd.before(&cfg, entry, 0).is_empty());
+        assert_eq!(defs(&rd.after(&cfg, entry, 0)), vec!["x (block0:0)"]);
+        assert_eq!(defs(&rd.before(&cfg, entry, 2)), vec!["x (block0:0)", "y (block0:1)"]);
+        assert_eq!(defs(&rd.after(&cfg, entry, 2)), vec!["x (block0:2)", "y (block0:1)"]);
+        assert_eq!(rd.after(&cfg, entry, 3), rd.out_sets[&entry]);
+    }
+
+    #[test]
+    fn test_def_use_chains() {
+        let f = parse_function("
+@main(n: int) {
+  x: int = const 0
+.loop:
+  done = eq x n
+  br done .out .body
+.body:
+  one: int = const 1
+  x = add x one
+  jmp .loop
+.out:
+  print x
+}
+")
+        .unwrap();
+        let cfg = Cfg::from_function(&f);
+        let chains = def_use_chains(&cfg);
+        let def = |var: &str, block: &str, i| Definition { var: var.into(), block: block.into(), instr_index: i };
+        let use_ = |var: &str, block: &str, i| Use { var: var.into(), block: block.into(), instr_index: i };
+
+        // `x` in the loop test is either the initial value or the increment
+        assert_eq!(chains.defs[&use_("x", "loop", 1)], vec![def("x", "block0", 0), def("x", "body", 2)]);
+        assert_eq!(chains.defs[&use_("x", "body", 2)], vec![def("x", "block0", 0), def("x", "body", 2)]);
+        assert!(chains.defs[&use_("n", "loop", 1)].is_empty());
+        assert_eq!(chains.uses[&def("one", "body", 1)], vec![use_("one", "body", 2)]);
+        let mut uses = chains.uses[&def("x", "body", 2)].clone();
+        uses.sort_by(|a, b| (&a.block, a.instr_index).cmp(&(&b.block, b.instr_index)));
+        assert_eq!(uses, vec![use_("x", "body", 2), use_("x", "loop", 1), use_("x", "out", 1)]);
+        assert!(chains.uses[&def("done", "loop", 1)].len() == 1);
+    }
+
+    fn vars(set: &HashSet<String>) -> Vec<&str> {
+        let mut vars: Vec<&str> = set.iter().map(String::as_str).collect();
+        vars.sort();
+        vars
+    }
+
+    #[test]
+    fn test_live_variables() {
+        let f = parse_function("
+@main(n: int) {
+  i: int = const 0
+  dead: int = const 5
+.loop:
+  done = eq i n
+  br done .out .body
+.body:
+  one: int = const 1
+  i = add i one
+  jmp .loop
+.out:
+  print i
+}
+")
+        .unwrap();
+        let cfg = Cfg::from_function(&f);
+        let live = live_variables(&cfg);
+        let (body, out) = (cfg.block("body").unwrap(), cfg.block("out").unwrap());
+
+        assert_eq!(vars(&live.in_sets[&cfg.entry]), vec!["n"]);
+        assert_eq!(vars(&live.out_sets[&cfg.entry]), vec!["i", "n"]);
+        assert_eq!(vars(&live.in_sets[&body]), vec!["i", "n"]);
+        assert_eq!(vars(&live.in_sets[&out]), vec!["i"]);
+        assert!(live.out_sets[&out].is_empty());
+
+        // inside a block
+        assert_eq!(vars(&live.after(&cfg, cfg.entry, 0)), vec!["i", "n"]);
+        assert_eq!(vars(&live.before(&cfg, body, 2)), vec!["i", "n", "one"]);
+        assert_eq!(vars(&live.after(&cfg, body, 2)), vec!["i", "n"]);
+        assert_eq!(live.before(&cfg, body, 0), live.in_sets[&body]);
+    }
+
+    #[test]
+    fn fuzz_def_use_chains() {
+        for_each_seed(100, &GenConfig::default(), |seed, f| {
+            let cfg = Cfg::from_function(f);
+            let chains = def_use_chains(&cfg);
+            let rd = reaching_definitions(&cfg);
+
+            for (use_, defs) in &chains.defs {
+                // the generator only reads variables defined on every path
+                assert!(!defs.is_empty(), "seed {}: nothing reaches {:?}", seed, use_);
+                for def in defs {
+                    assert!(chains.uses[def].contains(use_), "seed {}", seed);
+                }
+                let node = cfg.block(&use_.block).unwrap();
+                let before = rd.before(&cfg, node, use_.instr_index);
+                let mut expected: Vec<&Definition> = before.iter().filter(|d| d.var == use_.var).collect();
+                expected.sort_by(|a, b| (&a.block, a.instr_index).cmp(&(&b.block, b.instr_index)));
+                assert_eq!(defs.iter().collect::<Vec<_>>(), expected, "seed {}", seed);
+            }
+            for (def, uses) in &chains.uses {
+                for use_ in uses {
+                    assert!(chains.defs[use_].contains(def), "seed {}", seed);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,389 @@
+use std::fmt;
+
+use crate::cfg::build_blocks;
+use crate::constprop::{constant_propagation, sccp};
+use crate::global::*;
+use crate::interp::*;
+use crate::lvn::*;
+use crate::types::*;
+
+// Differential testing: run a program through the interpreter before and after
+// a pass and require the same printed output and return value.
+
+pub type BlockPass = fn(&[Instruction]) -> Vec<Instruction>;
+
+/// Every block-local pass in `lvn`, by name.
+pub const LOCAL_PASSES: &[(&str, BlockPass)] = &[
+    ("lvn", lvn),
+    ("constant_fold", constant_fold),
+    ("dead_elimination_unused", dead_elimination_unused),
+    ("dead_elimination_redefined", dead_elimination_redefined),
+    ("final_local_opt", final_local_opt),
+];
+
+pub type FunctionPass = fn(&Function) -> Function;
+
+/// Every pass over a whole function, by name.
+pub const GLOBAL_PASSES: &[(&str, FunctionPass)] = &[
+    ("global_dce", global_dce),
+    ("constant_propagation", constant_propagation),
+    ("sccp", |f| sccp(f).0),
+];
+
+#[derive(Clone, Debug)]
+pub struct Mismatch {
+    pub program: String,
+    pub pass: String,
+    pub before: Result<Outcome, InterpError>,
+    pub after: Result<Outcome, InterpError>,
+    pub optimized: Function,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "`{}` changed the behaviour of @{}", self.pass, self.program)?;
+        writeln!(f, "  before: {:?}", self.before)?;
+        writeln!(f, "  after:  {:?}", self.after)?;
+        write!(f, "{}", self.optimized)
+    }
+}
+
+/// Run `pass` over every basic block of `f` and stitch the blocks back together.
+pub fn apply_per_block(f: &Function, pass: BlockPass) -> Function {
+    let instr = build_blocks(f).iter().flat_map(|block| pass(block)).collect();
+    Function { name: f.name.clone(), args: f.args.clone(), ret_type: f.ret_type.clone(), instr }
+}
+
+// output and return value must agree, the step count is allowed to change
+fn same_behaviour(before: &Result<Outcome, InterpError>, after: &Result<Outcome, InterpError>) -> bool {
+    match (before, after) {
+        (Ok(b), Ok(a)) => b.output == a.output && b.ret == a.ret,
+        _ => false,
+    }
+}
+
+/// `None` when `transform` kept the behaviour of `f`.
+pub fn check_transform(f: &Function, pass: &str, transform: impl Fn(&Function) -> Function) -> Option<Mismatch> {
+    let optimized = transform(f);
+    let before = interpret(f, DEFAULT_STEP_LIMIT);
+    let after = interpret(&optimized, DEFAULT_STEP_LIMIT);
+
+    if same_behaviour(&before, &after) {
+        None
+    } else {
+        Some(Mismatch { program: f.name.clone(), pass: pass.to_string(), before, after, optimized })
+    }
+}
+
+/// Check every local pass on every program of `corpus`, returning all mismatches.
+pub fn check_local_passes(corpus: &[Function]) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+    for f in corpus {
+        for &(name, pass) in LOCAL_PASSES {
+            if let Some(m) = check_transform(f, name, |f| apply_per_block(f, pass)) {
+                mismatches.push(m);
+            }
+        }
+    }
+    mismatches
+}
+
+/// Check every global pass on every program of `corpus`, returning all mismatches.
+pub fn check_global_passes(corpus: &[Function]) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+    for f in corpus {
+        for &(name, pass) in GLOBAL_PASSES {
+            if let Some(m) = check_transform(f, name, pass) {
+                mismatches.push(m);
+            }
+        }
+    }
+    mismatches
+}
+
+// =====================================
+//             TESTS
+// =====================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::{Cfg, Layout};
+    use crate::generate::{for_each_seed, GenConfig};
+    use crate::text::parse_function;
+
+    // Programs whose behaviour does not depend on values flowing between blocks
+    // through a block-local pass: straight-line code, plus control flow where
+    // each block only uses what it defines.
+    const CORPUS: &[&str] = &[
+        "
+@cse_and_copyprop {
+  a: int = const 1
+  b: int = const 2
+  sum1 = add a b
+  sum2 = add a b
+  prod = mul sum1 sum2
+  print prod
+}
+",
+        "
+@copy_chain {
+  a: int = const 10
+  b = id a
+  c = id b
+  d = id c
+  print d
+}
+",
+        "
+@redefined_operand {
+  a: int = const 4
+  b: int = const 2
+  sum1 = add a b
+  sum2 = add b a
+  prod1 = mul sum1 sum2
+  sum1: int = const 0
+  sum2: int = const 0
+  sum3 = add a b
+  prod2 = mul sum3 sum3
+  print prod2
+  print prod1
+  print sum1
+  print sum3
+}
+",
+        "
+@dead_code {
+  a: int = const 5
+  b: int = const 10
+  b: int = const 6
+  c = add a b
+  a: int = const 7
+  d = mul c a
+  e: int = const 100
+  f: int = const 50
+  f: int = const 75
+  print d
+}
+",
+        "
+@only_redefined {
+  a: int = const 5
+  a: int = const 10
+  a: int = const 50
+  print a
+}
+",
+        "
+@stale_constant {
+  x: int = const 3
+  y: int = const 4
+  x = add x y
+  z = add x x
+  print z
+  x = mul y y
+  w = add x y
+  print w
+}
+",
+        "
+@copy_then_overwrite {
+  a: int = const 1
+  b = id a
+  a: int = const 2
+  c = add a b
+  print c
+  print b
+}
+",
+        "
+@stale_constant_via_id {
+  p: int = const 5
+  x: int = const 3
+  x = id p
+  y = add x x
+  print y
+}
+",
+        "
+@eq_and_move {
+  a: int = const 7
+  b: int = const 7
+  e1 = eq a b
+  e2 = eq b a
+  m = move e1
+  print m
+  print e2
+  ret a
+}
+",
+        "
+@full_instruction_set {
+  a: int = const 17
+  b: int = const 5
+  z: int = const 0
+  c = sub a b
+  d = sub b a
+  q = div a b
+  r = rem a b
+  s = shl r b
+  t = bitxor b a
+  u = bitxor a b
+  l = lt a b
+  g = gt b a
+  n = not l
+  o = or n g
+  p = and o l
+  print c
+  print d
+  print q
+  print s
+  print t
+  print u
+  print o
+  print p
+  zz = mul z b
+  y = ne zz z
+  print y
+}
+",
+        "
+@float_kernel {
+  x: float = const 0.1
+  y: float = const 0.2
+  pz: float = const 0.0
+  nz: float = const -0.0
+  s1 = fadd x y
+  s2 = fadd y x
+  p = fmul s1 s2
+  q = fmul nz x
+  r = fdiv x pz
+  n = fsub r r
+  same = feq n n
+  zeros = feq pz nz
+  less = flt q pz
+  print p
+  print q
+  print r
+  print same
+  print zeros
+  print less
+  ret n
+}
+",
+        "
+@memory {
+  n: int = const 2
+  p: ptr<int> = alloc n
+  one: int = const 1
+  q = ptradd p one
+  a: int = const 7
+  b: int = const 9
+  store p a
+  store q a
+  x = load p
+  store p b
+  y = load p
+  z = load q
+  s = add x y
+  print x
+  print y
+  print z
+  free p
+  ret s
+}
+",
+        "
+@local_diamond {
+  c: int = const 1
+  d: int = const 1
+  v0 = eq c d
+  br v0 .then_blk .else_blk
+.then_blk:
+  x: int = const 10
+  y = add x x
+  print y
+  jmp .merge_blk
+.else_blk:
+  x: int = const 20
+  print x
+  jmp .merge_blk
+.merge_blk:
+  z: int = const 3
+  print z
+  ret z
+}
+",
+    ];
+
+    fn corpus() -> Vec<Function> {
+        CORPUS.iter().map(|src| parse_function(src).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_local_passes_preserve_behaviour() {
+        let mismatches = check_local_passes(&corpus());
+        for m in &mismatches {
+            println!("{}", m);
+        }
+        assert!(mismatches.is_empty(), "{} pass/program pairs miscompiled", mismatches.len());
+    }
+
+    #[test]
+    fn test_linearize_preserves_behaviour() {
+        for f in &corpus() {
+            for layout in [Layout::Original, Layout::ReversePostorder, Layout::Traces] {
+                let m = check_transform(f, &format!("{:?}", layout), |f| Cfg::from_function(f).linearize(layout));
+                assert!(m.is_none(), "{}", m.unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_detects_cross_block_dce() {
+        // `v1` is only used in a successor block, so block-local DCE deletes its definition
+        let f = parse_function(
+            "
+@cross_block {
+  v0: bool = const true
+  br v0 .then_blk .else_blk
+.then_blk:
+  v1: int = const 10
+  jmp .merge_blk
+.else_blk:
+  v1: int = const 20
+  jmp .merge_blk
+.merge_blk:
+  print v1
+}
+",
+        )
+        .unwrap();
+
+        let m = check_transform(&f, "dead_elimination_unused", |f| apply_per_block(f, dead_elimination_unused))
+            .expect("block-local DCE should miscompile this");
+        assert!(matches!(m.after, Err(InterpError::UndefinedVariable { .. })));
+
+        assert!(check_transform(&f, "lvn", |f| apply_per_block(f, lvn)).is_none());
+        assert!(check_transform(&f, "global_dce", global_dce).is_none());
+    }
+
+    #[test]
+    fn test_global_passes_preserve_behaviour() {
+        let mismatches = check_global_passes(&corpus());
+        for m in &mismatches {
+            println!("{}", m);
+        }
+        assert!(mismatches.is_empty(), "{} pass/program pairs miscompiled", mismatches.len());
+    }
+
+    #[test]
+    fn fuzz_global_passes() {
+        for_each_seed(250, &GenConfig::default(), |seed, f| {
+            for &(name, pass) in GLOBAL_PASSES {
+                if let Some(m) = check_transform(f, name, pass) {
+                    panic!("seed {}: {}", seed, m);
+                }
+            }
+        });
+    }
+}
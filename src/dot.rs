@@ -0,0 +1,226 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+
+use petgraph::graph::NodeIndex;
+
+use crate::cfg::*;
+use crate::types::*;
+
+// Graphviz output for a `Cfg`, render with `dot -Tsvg cfg.dot -o cfg.svg`.
+// CFG edges are solid; overlays are drawn dashed (dominator tree) or dotted
+// (dominance frontiers) and do not take part in the layout.
+
+/// Builds the DOT text for a `Cfg`, with any of the overlays added.
+pub struct Dot<'a> {
+    cfg: &'a Cfg,
+    idom: Option<&'a HashMap<NodeIndex, Option<NodeIndex>>>,
+    frontiers: Option<&'a HashMap<NodeIndex, HashSet<NodeIndex>>>,
+    loop_headers: Option<&'a HashSet<NodeIndex>>,
+    // name and per block lines, sorted so the output is stable
+    sets: Vec<(String, HashMap<NodeIndex, Vec<String>>)>,
+}
+
+impl<'a> Dot<'a> {
+    pub fn new(cfg: &'a Cfg) -> Self {
+        Dot { cfg, idom: None, frontiers: None, loop_headers: None, sets: Vec::new() }
+    }
+
+    /// An edge from every immediate dominator to the blocks it immediately
+    /// dominates, as given by `build_dominator_tree`.
+    pub fn dominator_tree(mut self, idom: &'a HashMap<NodeIndex, Option<NodeIndex>>) -> Self {
+        self.idom = Some(idom);
+        self
+    }
+
+    pub fn frontiers(mut self, df: &'a HashMap<NodeIndex, HashSet<NodeIndex>>) -> Self {
+        self.frontiers = Some(df);
+        self
+    }
+
+    /// Loop headers get a double border.
+    pub fn loop_headers(mut self, headers: &'a HashSet<NodeIndex>) -> Self {
+        self.loop_headers = Some(headers);
+        self
+    }
+
+    /// A per block fact of a dataflow result, shown under the instructions as
+    /// `name: {a, b}`. Call once for IN and once for OUT.
+    pub fn sets<T: fmt::Display>(mut self, name: &str, sets: &HashMap<NodeIndex, HashSet<T>>) -> Self {
+        let lines = sets
+            .iter()
+            .map(|(&node, set)| {
+                let mut items: Vec<String> = set.iter().map(|item| item.to_string()).collect();
+                items.sort();
+                (node, items)
+            })
+            .collect();
+        self.sets.push((name.to_string(), lines));
+        self
+    }
+
+    pub fn render(&self) -> String {
+        let cfg = self.cfg;
+        let mut out = String::new();
+        // writing to a String can't fail
+        let _ = writeln!(out, "digraph \"{}\" {{", escape(&cfg.name));
+        let _ = writeln!(out, "  node [shape=box, fontname=\"monospace\"];");
+
+        for node in cfg.node_indices() {
+            let _ = writeln!(out, "  n{} [{}];", node.index(), self.node_attributes(node));
+        }
+        for edge in cfg.raw_edges() {
+            let _ = writeln!(out, "  n{} -> n{};", edge.source().index(), edge.target().index());
+        }
+
+        if let Some(idom) = self.idom {
+            for (parent, child) in sorted(idom.iter().filter_map(|(&n, &p)| Some((p?, n)))) {
+                let _ = writeln!(
+                    out,
+                    "  n{} -> n{} [style=dashed, color=blue, constraint=false];",
+                    parent.index(),
+                    child.index()
+                );
+            }
+        }
+        if let Some(df) = self.frontiers {
+            let pairs = df.iter().flat_map(|(&n, set)| set.iter().map(move |&f| (n, f)));
+            for (node, frontier) in sorted(pairs) {
+                let _ = writeln!(
+                    out,
+                    "  n{} -> n{} [style=dotted, color=red, constraint=false];",
+                    node.index(),
+                    frontier.index()
+                );
+            }
+        }
+
+        out.push_str("}\n");
+        out
+    }
+
+    fn node_attributes(&self, node: NodeIndex) -> String {
+        let block = &self.cfg[node];
+        // `\l` ends a left aligned line
+        let mut label = format!("{}:\\l", escape(&block.name));
+        for instr in &block.instructions {
+            if !matches!(instr, Instruction::Label { .. }) {
+                let _ = write!(label, "  {}\\l", escape(&instr.to_string()));
+            }
+        }
+        for (name, sets) in &self.sets {
+            let items = sets.get(&node).map(|items| items.join(", ")).unwrap_or_default();
+            let _ = write!(label, "{}: {{{}}}\\l", escape(name), escape(&items));
+        }
+
+        let mut attributes = format!("label=\"{}\"", label);
+        if node == self.cfg.exit {
+            attributes.push_str(", shape=ellipse");
+        }
+        if self.loop_headers.is_some_and(|headers| headers.contains(&node)) {
+            attributes.push_str(", peripheries=2");
+        }
+        attributes
+    }
+}
+
+fn sorted(pairs: impl Iterator<Item = (NodeIndex, NodeIndex)>) -> Vec<(NodeIndex, NodeIndex)> {
+    let mut pairs: Vec<_> = pairs.collect();
+    pairs.sort();
+    pairs
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataflow::reaching_definitions;
+    use crate::global::*;
+    use crate::text::parse_function;
+
+    #[test]
+    fn test_dot_output() {
+        let f = parse_function("
+@main {
+  i: int = const 0
+.loop:
+  one: int = const 1
+  i = add i one
+  done = eq i one
+  br done .out .loop
+.out:
+  print i
+}
+")
+        .unwrap();
+        let cfg = Cfg::from_function(&f);
+        let dom = find_dominators(&cfg);
+        let idom = build_dominator_tree(&dom);
+        let headers = find_loop_headers(&cfg, &dom);
+        let rd = reaching_definitions(&cfg);
+
+        let dot = Dot::new(&cfg)
+            .dominator_tree(&idom)
+            .loop_headers(&headers)
+            .sets("in", &rd.in_sets)
+            .render();
+
+        assert!(dot.starts_with("digraph \"main\" {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains(
+            "  n1 [label=\"loop:\\l  one: int = const 1\\l  i = add i one\\l  done = eq i one\\l  br done .out .loop\\l\
+             in: {done (loop:3), i (block0:0), i (loop:2), one (loop:1)}\\l\", peripheries=2];"
+        ));
+        assert!(dot.contains("  n3 [label=\"exit:\\l"));
+        assert!(dot.contains("\", shape=ellipse];"));
+        // cfg edges, the loop's back edge included
+        for edge in ["n0 -> n1;", "n1 -> n2;", "n1 -> n1;", "n2 -> n3;"] {
+            assert!(dot.contains(edge), "{}", edge);
+        }
+        assert!(dot.contains("  n1 -> n2 [style=dashed, color=blue, constraint=false];"));
+        assert_eq!(dot.matches("->").count(), 4 + 3);
+    }
+
+    #[test]
+    fn test_dot_frontiers() {
+        let f = parse_function("
+@main {
+  c: bool = const true
+  br c .then .else
+.then:
+  jmp .merge
+.else:
+  jmp .merge
+.merge:
+  ret
+}
+")
+        .unwrap();
+        let cfg = Cfg::from_function(&f);
+        let dom = find_dominators(&cfg);
+        let idom = build_dominator_tree(&dom);
+        let df = find_dominance_frontier(&cfg, &dom, &idom);
+
+        let dot = Dot::new(&cfg).frontiers(&df).render();
+        let overlay: Vec<&str> = dot.lines().filter(|line| line.contains("dotted")).collect();
+        assert_eq!(overlay, vec![
+            "  n1 -> n3 [style=dotted, color=red, constraint=false];",
+            "  n2 -> n3 [style=dotted, color=red, constraint=false];",
+        ]);
+        assert!(!dot.contains("dashed"));
+        assert!(!dot.contains("peripheries"));
+    }
+
+    #[test]
+    fn test_dot_escapes_names() {
+        let f = Function { name: "a\"b".into(), args: vec![], ret_type: None, instr: vec![] };
+        let dot = Dot::new(&Cfg::from_function(&f)).render();
+        assert_eq!(
+            dot,
+            "digraph \"a\\\"b\" {\n  node [shape=box, fontname=\"monospace\"];\n  n0 [label=\"block0:\\l\"];\n  \
+             n1 [label=\"exit:\\l\", shape=ellipse];\n  n0 -> n1;\n}\n"
+        );
+    }
+}
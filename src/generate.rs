@@ -0,0 +1,494 @@
+use crate::types::*;
+
+// Random, well-formed and always terminating programs for fuzzing the passes.
+// Programs are built from structured pieces (straight-line code, if/else
+// diamonds, counted loops) so every use is defined on every path and every
+// loop runs a bounded number of times.
+
+/// Small seedable PRNG (SplitMix64), good enough for picking shapes.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`, `n` must be non zero.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Uniform in `lo..=hi`.
+    pub fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        lo + (self.next_u64() % (hi - lo + 1) as u64) as i64
+    }
+
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GenConfig {
+    /// How deeply diamonds and loops may nest.
+    pub max_depth: usize,
+    /// Upper bound on the statements of one straight-line sequence.
+    pub max_stmts: usize,
+    /// Upper bound on the trip count of a single loop.
+    pub max_loop_trips: i64,
+    /// Integer constants are drawn from `-max_const..=max_const`.
+    pub max_const: i64,
+    /// Length of the int array loads and stores go to, 0 for no memory operations.
+    pub array_len: i64,
+}
+
+impl Default for GenConfig {
+    fn default() -> Self {
+        GenConfig { max_depth: 3, max_stmts: 6, max_loop_trips: 4, max_const: 10, array_len: 4 }
+    }
+}
+
+impl GenConfig {
+    // the nearest config the generator can follow: a sequence has at least
+    // one statement, the bounds are not negative and `-max_const..=max_const`
+    // and `0..=max_loop_trips` can be counted in an i64
+    fn clamped(&self) -> GenConfig {
+        GenConfig {
+            max_depth: self.max_depth,
+            max_stmts: self.max_stmts.max(1),
+            max_loop_trips: self.max_loop_trips.clamp(0, i64::MAX - 1),
+            max_const: self.max_const.clamp(0, i64::MAX / 2),
+            array_len: self.array_len.max(0),
+        }
+    }
+}
+
+/// A random function following `config`, the same for the same `seed`.
+/// Settings the generator can't follow (no statements, negative bounds) are
+/// taken as the nearest ones it can.
+pub fn generate_function(name: &str, seed: u64, config: &GenConfig) -> Function {
+    let config = &config.clamped();
+    let mut g = Generator { rng: Rng::new(seed), config, instr: Vec::new(), ints: Vec::new(), bools: Vec::new(), fresh: 0 };
+
+    // seed the pools so the first operations have something to read
+    for _ in 0..2 {
+        g.new_const(Types::Int);
+        g.new_const(Types::Bool);
+    }
+    if config.array_len > 0 {
+        g.new_array();
+    }
+    g.sequence(0);
+
+    let ret = g.rng.pick(&g.ints).clone();
+    for _ in 0..2 {
+        let var = g.any_var();
+        g.instr.push(Instruction::Print { value: var });
+    }
+    if config.array_len > 0 {
+        g.instr.push(Instruction::Free { ptr: ARRAY.to_string() });
+    }
+    g.instr.push(Instruction::Ret { value: Some(ret) });
+
+    Function { name: name.to_string(), args: vec![], ret_type: Some(Types::Int), instr: g.instr }
+}
+
+/// Calls `check` with each of the first `seeds` seeds and the function
+/// generated from it, for the fuzz tests of the passes.
+#[cfg(test)]
+pub(crate) fn for_each_seed(seeds: u64, config: &GenConfig, mut check: impl FnMut(u64, &Function)) {
+    for seed in 0..seeds {
+        check(seed, &generate_function("fuzz", seed, config));
+    }
+}
+
+// the one array, allocated and fully initialized on entry and freed before `ret`
+const ARRAY: &str = "mem";
+
+struct Generator<'a> {
+    rng: Rng,
+    config: &'a GenConfig,
+    instr: Vec<Instruction>,
+    // variables defined on every path to the current point, by type
+    ints: Vec<String>,
+    bools: Vec<String>,
+    fresh: usize,
+}
+
+impl Generator<'_> {
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.fresh += 1;
+        format!("{}{}", prefix, self.fresh)
+    }
+
+    fn any_var(&mut self) -> String {
+        if self.rng.chance(50) {
+            self.rng.pick(&self.ints).clone()
+        } else {
+            self.rng.pick(&self.bools).clone()
+        }
+    }
+
+    // either a brand new variable or one already in the pool (a redefinition)
+    fn dest(&mut self, typ: &Types) -> String {
+        let pool = if *typ == Types::Int { &self.ints } else { &self.bools };
+        if !pool.is_empty() && self.rng.chance(30) {
+            return self.rng.pick(pool).clone();
+        }
+        let var = self.fresh("v");
+        if *typ == Types::Int {
+            self.ints.push(var.clone());
+        } else {
+            self.bools.push(var.clone());
+        }
+        var
+    }
+
+    fn new_const(&mut self, typ: Types) {
+        let values = match typ {
+            Types::Bool => Literal::Bool(self.rng.chance(50)),
+            _ => Literal::Int(self.rng.range(-self.config.max_const, self.config.max_const)),
+        };
+        let dest = self.dest(&typ);
+        self.instr.push(Instruction::Const { dest, typ, values });
+    }
+
+    fn new_array(&mut self) {
+        let size = self.fresh("n");
+        let len = self.config.array_len;
+        self.instr.push(Instruction::Const { dest: size.clone(), typ: Types::Int, values: Literal::Int(len) });
+        let typ = Types::Ptr(Box::new(Types::Int));
+        self.instr.push(Instruction::Alloc { dest: ARRAY.to_string(), typ, size });
+        for i in 0..len {
+            let ptr = self.element(i);
+            let value = self.rng.pick(&self.ints).clone();
+            self.instr.push(Instruction::Store { ptr, value });
+        }
+    }
+
+    // a fresh pointer to `mem[i]`, kept out of the pools
+    fn element(&mut self, i: i64) -> String {
+        let offset = self.fresh("o");
+        let ptr = self.fresh("p");
+        self.instr.push(Instruction::Const { dest: offset.clone(), typ: Types::Int, values: Literal::Int(i) });
+        self.instr.push(Instruction::PtrAdd { dest: ptr.clone(), ptr: ARRAY.to_string(), offset });
+        ptr
+    }
+
+    fn sequence(&mut self, depth: usize) {
+        let stmts = 1 + self.rng.below(self.config.max_stmts);
+        for _ in 0..stmts {
+            let nest = depth < self.config.max_depth && self.rng.chance(25);
+            if nest && self.rng.chance(50) {
+                self.diamond(depth + 1);
+            } else if nest {
+                self.counted_loop(depth + 1);
+            } else {
+                self.operation();
+            }
+        }
+    }
+
+    fn operation(&mut self) {
+        let instr = match self.rng.below(9) {
+            0 => return self.new_const(Types::Int),
+            1 => return self.new_const(Types::Bool),
+            2 | 3 => {
+                let op = *self.rng.pick(&[
+                    BinOp::Add, BinOp::Mul, BinOp::Sub, BinOp::Div, BinOp::Rem,
+                    BinOp::Shl, BinOp::Shr, BinOp::BitAnd, BinOp::BitOr, BinOp::BitXor,
+                ]);
+                let op1 = self.rng.pick(&self.ints).clone();
+                let op2 = if matches!(op, BinOp::Div | BinOp::Rem) {
+                    self.divisor()
+                } else {
+                    self.rng.pick(&self.ints).clone()
+                };
+                let dest = self.dest(&Types::Int);
+                Instruction::binary(op, dest, op1, op2)
+            }
+            4 => {
+                let (op, pool) = match self.rng.below(4) {
+                    0 => (*self.rng.pick(&[BinOp::Eq, BinOp::Ne]), if self.rng.chance(70) { &self.ints } else { &self.bools }),
+                    1 => (*self.rng.pick(&[BinOp::And, BinOp::Or]), &self.bools),
+                    _ => (*self.rng.pick(&[BinOp::Lt, BinOp::Le, BinOp::Gt, BinOp::Ge, BinOp::Eq, BinOp::Ne]), &self.ints),
+                };
+                let op1 = self.rng.pick(pool).clone();
+                let op2 = self.rng.pick(pool).clone();
+                let src = self.rng.pick(&self.bools).clone();
+                let dest = self.dest(&Types::Bool);
+                if self.rng.chance(10) {
+                    Instruction::Not { dest, src }
+                } else {
+                    Instruction::binary(op, dest, op1, op2)
+                }
+            }
+            5 => {
+                let (typ, src) = if self.rng.chance(50) {
+                    (Types::Int, self.rng.pick(&self.ints).clone())
+                } else {
+                    (Types::Bool, self.rng.pick(&self.bools).clone())
+                };
+                let dest = self.dest(&typ);
+                if self.rng.chance(80) {
+                    Instruction::Id { dest, src }
+                } else {
+                    Instruction::Move { dest, src }
+                }
+            }
+            7 if self.config.array_len > 0 => {
+                let i = self.rng.range(0, self.config.array_len - 1);
+                let ptr = self.element(i);
+                if self.rng.chance(30) {
+                    let value = self.rng.pick(&self.ints).clone();
+                    Instruction::Store { ptr, value }
+                } else if self.rng.chance(50) {
+                    // `mem[i] += v`, repeated updates of one element make
+                    // load, store, load sequences in a single block
+                    let old = self.dest(&Types::Int);
+                    self.instr.push(Instruction::Load { dest: old.clone(), ptr: ptr.clone() });
+                    let op2 = self.rng.pick(&self.ints).clone();
+                    let value = self.fresh("v");
+                    self.instr.push(Instruction::Add { dest: value.clone(), op1: old, op2 });
+                    Instruction::Store { ptr, value }
+                } else {
+                    let dest = self.dest(&Types::Int);
+                    Instruction::Load { dest, ptr }
+                }
+            }
+            _ => Instruction::Print { value: self.any_var() },
+        };
+        self.instr.push(instr);
+    }
+
+    // a fresh non zero constant, kept out of the pools, so `div`/`rem` never trap
+    fn divisor(&mut self) -> String {
+        let mut value = self.rng.range(1, self.config.max_const.max(1));
+        if self.rng.chance(50) {
+            value = -value;
+        }
+        let dest = self.fresh("d");
+        self.instr.push(Instruction::Const { dest: dest.clone(), typ: Types::Int, values: Literal::Int(value) });
+        dest
+    }
+
+    // br c .then .else / then: ... jmp .end / else: ... [jmp .end] / end:
+    fn diamond(&mut self, depth: usize) {
+        let id = self.fresh("");
+        let (then_label, else_label, end_label) =
+            (format!("then{}", id), format!("else{}", id), format!("end{}", id));
+        let cond = self.rng.pick(&self.bools).clone();
+        self.instr.push(Instruction::Br { cond, then_label: then_label.clone(), else_label: else_label.clone() });
+
+        for label in [then_label, else_label] {
+            let falls_into_end = label.starts_with("else") && self.rng.chance(50);
+            let saved = (self.ints.clone(), self.bools.clone());
+            self.instr.push(Instruction::Label { label });
+            self.sequence(depth);
+            if !falls_into_end {
+                self.instr.push(Instruction::Jmp { label: end_label.clone() });
+            }
+            // only what was defined before the branch is defined after it
+            (self.ints, self.bools) = saved;
+        }
+        self.instr.push(Instruction::Label { label: end_label });
+    }
+
+    // counter runs 0..trips, the loop variables are kept out of the pools so the body cannot touch them
+    fn counted_loop(&mut self, depth: usize) {
+        let id = self.fresh("");
+        let (counter, limit, step, done) =
+            (format!("i{}", id), format!("n{}", id), format!("one{}", id), format!("done{}", id));
+        let (head, body, exit) = (format!("loop{}", id), format!("body{}", id), format!("exit{}", id));
+        let trips = self.rng.range(0, self.config.max_loop_trips);
+
+        self.instr.push(Instruction::Const { dest: counter.clone(), typ: Types::Int, values: Literal::Int(0) });
+        self.instr.push(Instruction::Const { dest: limit.clone(), typ: Types::Int, values: Literal::Int(trips) });
+        self.instr.push(Instruction::Const { dest: step.clone(), typ: Types::Int, values: Literal::Int(1) });
+        if self.rng.chance(50) {
+            self.instr.push(Instruction::Jmp { label: head.clone() });
+        }
+        self.instr.push(Instruction::Label { label: head.clone() });
+        self.instr.push(Instruction::Eq { dest: done.clone(), op1: counter.clone(), op2: limit });
+        self.instr.push(Instruction::Br { cond: done, then_label: exit.clone(), else_label: body.clone() });
+
+        let saved = (self.ints.clone(), self.bools.clone());
+        self.instr.push(Instruction::Label { label: body });
+        self.sequence(depth);
+        self.instr.push(Instruction::Add { dest: counter.clone(), op1: counter, op2: step });
+        self.instr.push(Instruction::Jmp { label: head });
+        (self.ints, self.bools) = saved;
+
+        self.instr.push(Instruction::Label { label: exit });
+    }
+}
+
+// =====================================
+//             TESTS
+// =====================================
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use petgraph::graph::NodeIndex;
+
+    use super::*;
+    use crate::cfg::*;
+    use crate::difftest::*;
+    use crate::global::*;
+    use crate::interp::*;
+    use crate::lvn::*;
+    use crate::text::parse_function;
+    use crate::verify::verify;
+
+    const SEEDS: u64 = 500;
+
+    #[test]
+    fn test_deterministic_and_parsable() {
+        let config = GenConfig::default();
+        for_each_seed(50, &config, |seed, f| {
+            assert_eq!(f, &generate_function("fuzz", seed, &config));
+            assert_eq!(&parse_function(&f.to_string()).unwrap(), f);
+        });
+    }
+
+    #[test]
+    fn test_degenerate_configs() {
+        let zeroed = GenConfig { max_depth: 0, max_stmts: 0, max_loop_trips: 0, max_const: 0, array_len: 0 };
+        let negative = GenConfig { max_loop_trips: -3, max_const: -5, array_len: -1, ..zeroed.clone() };
+        let huge = GenConfig { max_loop_trips: i64::MAX, max_const: i64::MAX, ..GenConfig::default() };
+        for config in [zeroed, negative, huge] {
+            for_each_seed(20, &config, |seed, f| {
+                assert_eq!(verify(f), Ok(()), "seed {} ({:?})\n{}", seed, config, f);
+            });
+        }
+    }
+
+    #[test]
+    fn test_generated_programs_run() {
+        for_each_seed(SEEDS, &GenConfig::default(), |seed, f| {
+            assert_eq!(verify(f), Ok(()), "seed {}\n{}", seed, f);
+            let out = interpret(f, DEFAULT_STEP_LIMIT);
+            assert!(out.is_ok(), "seed {}: {:?}\n{}", seed, out, f);
+        });
+    }
+
+    #[test]
+    fn fuzz_local_passes() {
+        // only the passes that are sound on a single block of a larger function
+        let passes: [(&str, BlockPass); 3] = [
+            ("lvn", lvn),
+            ("constant_fold", constant_fold),
+            ("dead_elimination_redefined", dead_elimination_redefined),
+        ];
+        // a single cell makes a load, store, load of the same memory in one block likely
+        let crowded = GenConfig { max_stmts: 12, array_len: 1, ..GenConfig::default() };
+        for config in [GenConfig::default(), crowded] {
+            for_each_seed(SEEDS, &config, |seed, f| {
+                for (name, pass) in passes {
+                    if let Some(m) = check_transform(f, name, |f| apply_per_block(f, pass)) {
+                        panic!("seed {} ({:?}): {}", seed, config, m);
+                    }
+                }
+            });
+        }
+    }
+
+    // the textbook fixpoint over the reachable blocks, unreachable ones get
+    // no dominators
+    fn naive_dominators(cfg: &Cfg) -> HashMap<NodeIndex, HashSet<NodeIndex>> {
+        let reachable: HashSet<NodeIndex> = cfg.postorder().into_iter().collect();
+        let mut dom: HashMap<NodeIndex, HashSet<NodeIndex>> = cfg.node_indices().map(|n| (n, HashSet::new())).collect();
+        for &node in &reachable {
+            dom.insert(node, reachable.clone());
+        }
+        dom.insert(cfg.entry, HashSet::from([cfg.entry]));
+        loop {
+            let old = dom.clone();
+            for node in cfg.node_indices().filter(|&n| n != cfg.entry && reachable.contains(&n)) {
+                let mut preds = cfg.predecessors(node).filter(|p| reachable.contains(p));
+                let Some(first) = preds.next() else {
+                    continue;
+                };
+                let mut new = preds.fold(dom[&first].clone(), |acc, p| acc.intersection(&dom[&p]).copied().collect());
+                new.insert(node);
+                dom.insert(node, new);
+            }
+            if dom == old {
+                return dom;
+            }
+        }
+    }
+
+    #[test]
+    fn fuzz_cfg_and_dominators() {
+        let config = GenConfig { max_depth: 4, max_stmts: 8, ..GenConfig::default() };
+        let mut fall_throughs = 0;
+        for_each_seed(SEEDS, &config, |seed, f| {
+            let blocks = build_blocks(f);
+            let cfg = Cfg::from_function(f);
+            assert_eq!(cfg.node_count(), blocks.len() + 1);
+
+            // an edge per jump target, or to the next block (or the exit)
+            let edges = blocks.iter().map(|block| match block.last() {
+                Some(Instruction::Br { .. }) => 2,
+                _ => 1,
+            });
+            assert_eq!(cfg.edge_count(), edges.sum::<usize>(), "seed {}", seed);
+            fall_throughs += blocks.iter().filter(|block| !is_terminator(block.last().unwrap())).count();
+            assert_eq!(&cfg.to_function(), f, "seed {}", seed);
+            for layout in [Layout::ReversePostorder, Layout::Traces] {
+                let m = check_transform(f, &format!("{:?}", layout), |f| Cfg::from_function(f).linearize(layout));
+                assert!(m.is_none(), "seed {}: {}", seed, m.unwrap());
+            }
+
+            let dom = find_dominators(&cfg);
+            let idom = build_dominator_tree(&dom);
+            let entry = cfg.entry;
+            assert_eq!(dom, naive_dominators(&cfg), "seed {}", seed);
+            let doms = Dominators::new(&cfg);
+            assert_eq!(doms.tree(), idom, "seed {}", seed);
+            assert_eq!(doms.frontiers(&cfg), find_dominance_frontier(&cfg, &dom, &idom), "seed {}", seed);
+            for a in cfg.node_indices() {
+                for b in cfg.node_indices() {
+                    assert_eq!(doms.dominates(a, b), dom[&b].contains(&a), "seed {}", seed);
+                }
+            }
+            for node in cfg.node_indices() {
+                if !doms.is_reachable(node) {
+                    assert!(dom[&node].is_empty() && !idom.contains_key(&node), "seed {}", seed);
+                    continue;
+                }
+                assert!(dom[&node].contains(&entry), "seed {}: entry must dominate everything reachable", seed);
+                assert!(dom[&node].contains(&node));
+                match idom[&node] {
+                    None => assert_eq!(node, entry),
+                    Some(parent) => {
+                        assert!(dom[&node].contains(&parent));
+                        // the idom is dominated by every other strict dominator
+                        for d in dom[&node].iter().filter(|&&d| d != node) {
+                            assert!(dom[&parent].contains(d), "seed {}", seed);
+                        }
+                    }
+                }
+            }
+        });
+        // the generator leaves out some jumps into labels
+        assert!(fall_throughs > 0);
+    }
+}
@@ -1,62 +1,233 @@
 use std::collections::{HashMap, HashSet};
-use std::hash::Hash;
 
 use crate::cfg::*;
-use crate::dataflow::Definition;
-use petgraph::Direction;
-use petgraph::graph::Node;
-use petgraph::{graph::DiGraph, graph::NodeIndex};
+use crate::dataflow::live_variables;
+use crate::lvn::{get_dest, get_used_var, may_trap_in_block};
 use crate::types::*;
+use petgraph::Direction;
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::visit::DfsPostOrder;
 
 
-pub fn find_dominators(cfg: &DiGraph<BasicBlock, ()>) -> HashMap<NodeIndex, HashSet<NodeIndex>> {
-    let mut dom: HashMap<NodeIndex, HashSet<NodeIndex>> = HashMap::new();
+/// Why dominators could not be computed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DominatorError {
+    EmptyGraph,
+    EntryNotInGraph(NodeIndex),
+}
 
-    // Initialize: all nodes dominate all nodes
-    let all_nodes: HashSet<NodeIndex> = cfg.node_indices().collect();
-    for node in cfg.node_indices() {
-        dom.insert(node, all_nodes.clone());
+impl std::fmt::Display for DominatorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DominatorError::EmptyGraph => write!(f, "the graph has no blocks"),
+            DominatorError::EntryNotInGraph(entry) => write!(f, "entry n{} is not in the graph", entry.index()),
+        }
     }
+}
 
-    let entry = cfg.node_indices().next().unwrap();
-    // for entry node we only insert itself
-    dom.insert(entry, {
-        let mut s = HashSet::new();
-        s.insert(entry);
-        s
-    });
+/// Dominators of the blocks reachable from the entry, kept as the immediate
+/// dominator of each block (Cooper, Harvey and Kennedy, "A Simple, Fast
+/// Dominance Algorithm"). The dominator tree is numbered in pre and post
+/// order, so `dominates` is two comparisons. Blocks the entry can't reach
+/// have no dominators at all, see `is_reachable`.
+#[derive(Clone, Debug)]
+pub struct Dominators {
+    entry: NodeIndex,
+    // by node index, `None` for the entry and unreachable blocks
+    idom: Vec<Option<NodeIndex>>,
+    children: Vec<Vec<NodeIndex>>,
+    // by node index, `usize::MAX` for unreachable blocks
+    pre: Vec<usize>,
+    post: Vec<usize>,
+}
 
+impl Dominators {
+    /// Dominators from the entry of `cfg`, which always has one.
+    pub fn new(cfg: &Cfg) -> Dominators {
+        Dominators::with_entry(cfg, cfg.entry).expect("a cfg has its entry block")
+    }
 
-    loop {
-        let old_dom = dom.clone();
-        for vertex in cfg.node_indices(){
-            let preds: Vec<NodeIndex> = cfg.neighbors_directed(vertex, petgraph::Direction::Incoming).collect();
-            //let mut dom_pred: HashSet<NodeIndex> = HashSet::new();
-            if !preds.is_empty(){
-                let mut new_dom = dom[&preds[0]].clone(); 
-                for pred in &preds[1..]{
-                    new_dom = new_dom.intersection(&dom[pred]).copied().collect();
+    /// Dominators of any graph from a block of the caller's choice, which
+    /// need not be the first one nor free of predecessors.
+    pub fn with_entry<N, E>(graph: &DiGraph<N, E>, entry: NodeIndex) -> Result<Dominators, DominatorError> {
+        let n = graph.node_count();
+        if n == 0 {
+            return Err(DominatorError::EmptyGraph);
+        }
+        if entry.index() >= n {
+            return Err(DominatorError::EntryNotInGraph(entry));
+        }
+        let mut dfs = DfsPostOrder::new(graph, entry);
+        let mut rpo = Vec::new();
+        while let Some(node) = dfs.next(graph) {
+            rpo.push(node);
+        }
+        rpo.reverse();
+        // position in postorder, what the two fingers compare
+        let mut order = vec![usize::MAX; n];
+        for (i, node) in rpo.iter().rev().enumerate() {
+            order[node.index()] = i;
+        }
+
+        let mut idom: Vec<Option<NodeIndex>> = vec![None; n];
+        idom[entry.index()] = Some(entry);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &node in &rpo[1..] {
+                let mut preds = graph
+                    .neighbors_directed(node, Direction::Incoming)
+                    .filter(|p| idom[p.index()].is_some());
+                let Some(first) = preds.next() else {
+                    continue;
+                };
+                let new_idom = preds.fold(first, |a, b| intersect(&idom, &order, a, b));
+                if idom[node.index()] != Some(new_idom) {
+                    idom[node.index()] = Some(new_idom);
+                    changed = true;
                 }
+            }
+        }
+        idom[entry.index()] = None;
 
-                new_dom.insert(vertex);
-                dom.insert(vertex, new_dom);
+        let mut children = vec![Vec::new(); n];
+        for &node in &rpo {
+            if let Some(parent) = idom[node.index()] {
+                children[parent.index()].push(node);
+            }
+        }
 
+        // pre and post order numbers in the dominator tree, without recursion
+        let (mut pre, mut post) = (vec![usize::MAX; n], vec![usize::MAX; n]);
+        let (mut pre_clock, mut post_clock) = (0, 0);
+        let mut stack = vec![(entry, false)];
+        while let Some((node, done)) = stack.pop() {
+            if done {
+                post[node.index()] = post_clock;
+                post_clock += 1;
+                continue;
             }
+            pre[node.index()] = pre_clock;
+            pre_clock += 1;
+            stack.push((node, true));
+            stack.extend(children[node.index()].iter().rev().map(|&child| (child, false)));
         }
-        if old_dom == dom {
-            break;
+
+        Ok(Dominators { entry, idom, children, pre, post })
+    }
+
+    pub fn entry(&self) -> NodeIndex {
+        self.entry
+    }
+
+    pub fn is_reachable(&self, node: NodeIndex) -> bool {
+        self.pre[node.index()] != usize::MAX
+    }
+
+    /// The blocks the entry can't reach, by index.
+    pub fn unreachable(&self) -> Vec<NodeIndex> {
+        (0..self.pre.len()).map(NodeIndex::new).filter(|&n| !self.is_reachable(n)).collect()
+    }
+
+    /// `None` for the entry and for blocks it can't reach.
+    pub fn idom(&self, node: NodeIndex) -> Option<NodeIndex> {
+        self.idom[node.index()]
+    }
+
+    /// The blocks `node` immediately dominates, in reverse postorder.
+    pub fn children(&self, node: NodeIndex) -> &[NodeIndex] {
+        &self.children[node.index()]
+    }
+
+    /// Whether every path from the entry to `b` goes through `a`. A block
+    /// dominates itself; nothing dominates or is dominated by an unreachable
+    /// block.
+    pub fn dominates(&self, a: NodeIndex, b: NodeIndex) -> bool {
+        self.is_reachable(a)
+            && self.is_reachable(b)
+            && self.pre[a.index()] <= self.pre[b.index()]
+            && self.post[b.index()] <= self.post[a.index()]
+    }
+
+    pub fn strictly_dominates(&self, a: NodeIndex, b: NodeIndex) -> bool {
+        a != b && self.dominates(a, b)
+    }
+
+    /// `node` and its dominators up to the entry, empty if `node` is unreachable.
+    pub fn dominators(&self, node: NodeIndex) -> impl Iterator<Item = NodeIndex> + '_ {
+        let start = self.is_reachable(node).then_some(node);
+        std::iter::successors(start, |&n| self.idom(n))
+    }
+
+    /// The immediate dominator of every reachable block, in the form of
+    /// `build_dominator_tree`; unreachable blocks are left out.
+    pub fn tree(&self) -> HashMap<NodeIndex, Option<NodeIndex>> {
+        (0..self.idom.len())
+            .map(NodeIndex::new)
+            .filter(|&n| self.is_reachable(n))
+            .map(|n| (n, self.idom(n)))
+            .collect()
+    }
+
+    /// The dominators of every block as sets, in the form of
+    /// `find_dominators`; empty for unreachable blocks.
+    pub fn sets(&self) -> HashMap<NodeIndex, HashSet<NodeIndex>> {
+        (0..self.idom.len()).map(NodeIndex::new).map(|n| (n, self.dominators(n).collect())).collect()
+    }
+
+    /// The dominance frontier of every block (see `find_dominance_frontier`),
+    /// empty for unreachable ones.
+    pub fn frontiers<N, E>(&self, graph: &DiGraph<N, E>) -> HashMap<NodeIndex, HashSet<NodeIndex>> {
+        let mut df: HashMap<NodeIndex, HashSet<NodeIndex>> = graph.node_indices().map(|n| (n, HashSet::new())).collect();
+        for node in graph.node_indices().filter(|&n| self.is_reachable(n)) {
+            for pred in graph.neighbors_directed(node, Direction::Incoming).filter(|&p| self.is_reachable(p)) {
+                // up from the predecessor to the immediate dominator of node
+                let mut runner = Some(pred);
+                while let Some(r) = runner
+                    && runner != self.idom(node)
+                {
+                    df.get_mut(&r).unwrap().insert(node);
+                    runner = self.idom(r);
+                }
+            }
+        }
+        df
+    }
+}
+
+// the closest common dominator of `a` and `b`, walking up from whichever is
+// lower in postorder
+fn intersect(idom: &[Option<NodeIndex>], order: &[usize], mut a: NodeIndex, mut b: NodeIndex) -> NodeIndex {
+    while a != b {
+        while order[a.index()] < order[b.index()] {
+            a = idom[a.index()].expect("processed blocks have an idom");
+        }
+        while order[b.index()] < order[a.index()] {
+            b = idom[b.index()].expect("processed blocks have an idom");
         }
     }
-    dom
+    a
+}
+
+/// Every block's set of dominators, itself included. A view of `Dominators`
+/// kept for callers that want sets; blocks the entry can't reach get an
+/// empty set, as nothing dominates them.
+pub fn find_dominators(cfg: &Cfg) -> HashMap<NodeIndex, HashSet<NodeIndex>> {
+    Dominators::new(cfg).sets()
 }
 
 
+/// The immediate dominator of every block in `dom`. Blocks with no
+/// dominators are unreachable and left out, so `None` only marks the entry.
 pub fn build_dominator_tree(dom: &HashMap<NodeIndex, HashSet<NodeIndex>>) 
     -> HashMap<NodeIndex, Option<NodeIndex>> 
 {
     let mut idom_tree: HashMap<NodeIndex, Option<NodeIndex>> = HashMap::new();
     
     for (&node, dominators) in dom {
+        if dominators.is_empty() {
+            continue;
+        }
         // Entry node has no immediate dominator
         if dominators.len() == 1 {
             idom_tree.insert(node, None);
@@ -82,8 +253,11 @@ pub fn build_dominator_tree(dom: &HashMap<NodeIndex, HashSet<NodeIndex>>)
     idom_tree
 }
 
-fn find_dominance_frontier(
-    cfg: &DiGraph<BasicBlock,()>, 
+/// For each block, the blocks just outside the region it dominates: where a
+/// path from it first meets a path that avoids it. A loop header whose back
+/// edge comes from itself is in its own frontier.
+pub fn find_dominance_frontier(
+    cfg: &Cfg,
     dom: &HashMap<NodeIndex, HashSet<NodeIndex>>,
     idom: &HashMap<NodeIndex, Option<NodeIndex>>) -> HashMap<NodeIndex, HashSet<NodeIndex>> {
     let mut df : HashMap<NodeIndex, HashSet<NodeIndex>> = HashMap::new();
@@ -91,88 +265,102 @@ fn find_dominance_frontier(
     for node in cfg.node_indices(){
         df.insert(node, HashSet::new()); 
     }
-    for node in cfg.node_indices(){
-        //ignore entry
+    // unreachable blocks have no dominators and are in no frontier
+    for node in cfg.node_indices().filter(|n| !dom[n].is_empty()) {
+        let preds: Vec<NodeIndex> = cfg.neighbors_directed(node, Direction::Incoming).collect();
 
-        //
-        let mut preds: Vec<NodeIndex> = cfg.neighbors_directed(node, Direction::Incoming).collect();
+        for pred in preds.into_iter().filter(|p| !dom[p].is_empty()) {
+            let mut runner = pred;
 
-        if preds.len() >= 2 {
+            // walk up from the predecessor until reaching a strict dominator of
+            // node, every block passed dominates pred but not strictly node
+            while runner == node || !dom[&node].contains(&runner) {
+                df.entry(runner).or_insert_with(HashSet::new).insert(node);
 
-            for pred in preds {
-                let mut runner = pred;
+                match idom[&runner]{
+                    Some(parent) => runner = parent,
+                    None => break,
+                }
+            }
+        }
+    }
 
-                while !dom[&node].contains(&runner) {
-                    // here dominator set of pred of y doesnt dominate y, so y can be in its DF
-                    df.entry(runner).or_insert_with(HashSet::new).insert(node);
+    df
+}
 
+/// Targets of back edges, edges whose target dominates their source. Every
+/// natural loop has exactly one such header.
+pub fn find_loop_headers(cfg: &Cfg, dom: &HashMap<NodeIndex, HashSet<NodeIndex>>) -> HashSet<NodeIndex> {
+    cfg.raw_edges()
+        .iter()
+        .filter(|edge| dom[&edge.source()].contains(&edge.target()))
+        .map(|edge| edge.target())
+        .collect()
+}
 
-                    // look at immediate dom of pred or runner i woould say
-                    match idom[&runner]{
-                        Some(parent) => runner = parent,
-                        None => break,
-                    }
+/// Removes definitions that are not live after them and have no side
+/// effects nor may trap, across the whole function. Deleting one can make what it read
+/// dead in turn, so this runs until nothing changes.
+pub fn global_dce(f: &Function) -> Function {
+    let mut f = f.clone();
+    loop {
+        let mut cfg = Cfg::from_function(&f);
+        let live = live_variables(&cfg);
+        let mut removed = false;
+
+        for node in cfg.node_indices().collect::<Vec<_>>() {
+            let mut live_now = live.out_sets[&node].clone();
+            let traps = may_trap_in_block(&cfg[node].instructions);
+            let mut kept = Vec::new();
+            for (instr, traps) in cfg[node].instructions.iter().zip(traps).rev() {
+                if let Some(dest) = get_dest(instr)
+                    && !live_now.contains(dest)
+                    && !instr.has_side_effects()
+                    && !traps
+                {
+                    removed = true;
+                    continue;
                 }
-
+                if let Some(dest) = get_dest(instr) {
+                    live_now.remove(dest);
+                }
+                live_now.extend(get_used_var(instr));
+                kept.push(instr.clone());
             }
+            kept.reverse();
+            cfg[node].instructions = kept;
         }
-    }
 
-    df
+        if !removed {
+            return f;
+        }
+        f = cfg.to_function();
+    }
 }
 
+#[cfg(test)]
 mod tests{
     use super::*;
+    use crate::text::parse_function;
 
     #[test]
     fn test_dominators() {
-        let f = Function {
-            name: "Main".to_string(),
-            instr: vec![
-                // ---- block0 ----
-                Instruction::Const {
-                    dest: "v0".to_string(),
-                    typ: Types::Bool,
-                    values: Literal::Bool(true),
-                },
-                Instruction::Br {
-                    cond: "v0".to_string(),
-                    then_label: "then_blk".to_string(),
-                    else_label: "else_blk".to_string(),
-                },
-    
-                // ---- then_blk (block1) ----
-                Instruction::Label{label: "then_blk".to_string()},
-                Instruction::Const {
-                    dest: "v1".to_string(),
-                    typ: Types::Int,
-                    values: Literal::Int(10),
-                },
-                Instruction::Jmp {
-                    label: "merge_blk".to_string(),
-                },
-    
-                // ---- else_blk (block2) ----
-                Instruction::Label{label:"else_blk".to_string()},
-                Instruction::Const {
-                    dest: "v2".to_string(),
-                    typ: Types::Int,
-                    values: Literal::Int(20),
-                },
-                Instruction::Jmp {
-                    label: "merge_blk".to_string(),
-                },
-    
-                // ---- merge_blk (block3) ----
-                Instruction::Label{label: "merge_blk".to_string()},
-                Instruction::Ret {
-                    value: Some("v1".to_string()),
-                },
-            ],
-        };
+        let f = parse_function("
+@main {
+  v0: bool = const true
+  br v0 .then_blk .else_blk
+.then_blk:
+  v1: int = const 10
+  jmp .merge_blk
+.else_blk:
+  v2: int = const 20
+  jmp .merge_blk
+.merge_blk:
+  ret v1
+}
+").unwrap();
     
-        let blocks = build_blocks(&f);
-        let cfg = build_cfg(&blocks);
+        let cfg = Cfg::from_function(&f);
         let dom = find_dominators(&cfg);
     
         println!("--- DOMINATORS ---\n");
@@ -197,46 +385,22 @@ mod tests{
 
     #[test]
 fn test_dominator_tree() {
-    let f = Function {
-        name: "Main".to_string(),
-        instr: vec![
-            Instruction::Const {
-                dest: "v0".to_string(),
-                typ: Types::Bool,
-                values: Literal::Bool(true),
-            },
-            Instruction::Br {
-                cond: "v0".to_string(),
-                then_label: "then_blk".to_string(),
-                else_label: "else_blk".to_string(),
-            },
-            Instruction::Label{label: "then_blk".to_string()},
-            Instruction::Const {
-                dest: "v1".to_string(),
-                typ: Types::Int,
-                values: Literal::Int(10),
-            },
-            Instruction::Jmp {
-                label: "merge_blk".to_string(),
-            },
-            Instruction::Label{label:"else_blk".to_string()},
-            Instruction::Const {
-                dest: "v2".to_string(),
-                typ: Types::Int,
-                values: Literal::Int(20),
-            },
-            Instruction::Jmp {
-                label: "merge_blk".to_string(),
-            },
-            Instruction::Label{label: "merge_blk".to_string()},
-            Instruction::Ret {
-                value: Some("v1".to_string()),
-            },
-        ],
-    };
-
-    let blocks = build_blocks(&f);
-    let cfg = build_cfg(&blocks);
+    let f = parse_function("
+@main {
+  v0: bool = const true
+  br v0 .then_blk .else_blk
+.then_blk:
+  v1: int = const 10
+  jmp .merge_blk
+.else_blk:
+  v2: int = const 20
+  jmp .merge_blk
+.merge_blk:
+  ret v1
+}
+").unwrap();
+
+    let cfg = Cfg::from_function(&f);
     let dom = find_dominators(&cfg);
     let idom = build_dominator_tree(&dom);
 
@@ -259,53 +423,22 @@ fn test_dominator_tree() {
 
 #[test]
 fn test_dominance_frontier() {
-    let f = Function {
-        name: "Main".to_string(),
-        instr: vec![
-            // ---- block0 ----
-            Instruction::Const {
-                dest: "v0".to_string(),
-                typ: Types::Bool,
-                values: Literal::Bool(true),
-            },
-            Instruction::Br {
-                cond: "v0".to_string(),
-                then_label: "then_blk".to_string(),
-                else_label: "else_blk".to_string(),
-            },
-
-            // ---- then_blk ----
-            Instruction::Label{label: "then_blk".to_string()},
-            Instruction::Const {
-                dest: "v1".to_string(),
-                typ: Types::Int,
-                values: Literal::Int(10),
-            },
-            Instruction::Jmp {
-                label: "merge_blk".to_string(),
-            },
-
-            // ---- else_blk ----
-            Instruction::Label{label:"else_blk".to_string()},
-            Instruction::Const {
-                dest: "v2".to_string(),
-                typ: Types::Int,
-                values: Literal::Int(20),
-            },
-            Instruction::Jmp {
-                label: "merge_blk".to_string(),
-            },
-
-            // ---- merge_blk ----
-            Instruction::Label{label: "merge_blk".to_string()},
-            Instruction::Ret {
-                value: Some("v1".to_string()),
-            },
-        ],
-    };
-
-    let blocks = build_blocks(&f);
-    let cfg = build_cfg(&blocks);
+    let f = parse_function("
+@main {
+  v0: bool = const true
+  br v0 .then_blk .else_blk
+.then_blk:
+  v1: int = const 10
+  jmp .merge_blk
+.else_blk:
+  v2: int = const 20
+  jmp .merge_blk
+.merge_blk:
+  ret v1
+}
+").unwrap();
+
+    let cfg = Cfg::from_function(&f);
     let dom = find_dominators(&cfg);
     let idom = build_dominator_tree(&dom);
     let df = find_dominance_frontier(&cfg, &dom, &idom);
@@ -330,6 +463,297 @@ fn test_dominance_frontier() {
         }
         println!();
     }
+
+    let merge = cfg.block("merge_blk").unwrap();
+    assert_eq!(df[&cfg.block("then_blk").unwrap()], HashSet::from([merge]));
+    assert_eq!(df[&cfg.block("else_blk").unwrap()], HashSet::from([merge]));
+    assert!(df[&cfg.entry].is_empty());
+}
+
+#[test]
+fn test_dominance_frontier_of_loops() {
+    let f = parse_function("
+@main {
+  i: int = const 0
+.spin:
+  i = add i i
+  c = eq i i
+  br c .spin .head
+.head:
+  br c .body .out
+.body:
+  jmp .head
+.out:
+}
+").unwrap();
+
+    let cfg = Cfg::from_function(&f);
+    let dom = find_dominators(&cfg);
+    let idom = build_dominator_tree(&dom);
+    let df = find_dominance_frontier(&cfg, &dom, &idom);
+    let block = |name: &str| cfg.block(name).unwrap();
+
+    // a block looping to itself, and a loop through another block
+    assert_eq!(df[&block("spin")], HashSet::from([block("spin")]));
+    assert_eq!(df[&block("head")], HashSet::from([block("head")]));
+    assert_eq!(df[&block("body")], HashSet::from([block("head")]));
+    assert!(df[&block("out")].is_empty());
+}
+
+
+#[test]
+fn test_dominators_struct() {
+    let f = parse_function("
+@main(c: bool) {
+  br c .a .b
+.a:
+  jmp .m
+.b:
+  br c .m .b
+.m:
+  ret
+}
+").unwrap();
+
+    let cfg = Cfg::from_function(&f);
+    let doms = Dominators::new(&cfg);
+    let block = |name: &str| cfg.block(name).unwrap();
+    let (entry, a, b, m) = (cfg.entry, block("a"), block("b"), block("m"));
+
+    assert_eq!(doms.entry(), entry);
+    assert_eq!(doms.idom(entry), None);
+    assert_eq!(doms.idom(a), Some(entry));
+    assert_eq!(doms.idom(b), Some(entry));
+    assert_eq!(doms.idom(m), Some(entry));
+    assert_eq!(doms.idom(cfg.exit), Some(m));
+    assert!(doms.dominates(entry, m) && doms.dominates(m, m) && !doms.strictly_dominates(m, m));
+    assert!(!doms.dominates(a, m) && !doms.dominates(b, m));
+    assert_eq!(doms.dominators(cfg.exit).collect::<Vec<_>>(), vec![cfg.exit, m, entry]);
+    assert_eq!(doms.frontiers(&cfg)[&b], HashSet::from([b, m]));
+}
+
+#[test]
+fn test_dominators_of_a_long_function() {
+    // thousands of diamonds one after another, far too many blocks for a
+    // set per block
+    let diamonds = 5000;
+    let mut src = String::from("@main(c: bool) {\n");
+    for i in 0..diamonds {
+        src += &format!("  br c .t{i} .e{i}\n.t{i}:\n  jmp .j{i}\n.e{i}:\n  jmp .j{i}\n.j{i}:\n");
+    }
+    src += "  ret\n}\n";
+    let cfg = Cfg::from_function(&parse_function(&src).unwrap());
+    let doms = Dominators::new(&cfg);
+
+    let last = cfg.block(&format!("j{}", diamonds - 1)).unwrap();
+    assert!(doms.dominates(cfg.entry, last));
+    assert!(doms.dominates(cfg.block("j0").unwrap(), last));
+    assert!(!doms.dominates(cfg.block("t0").unwrap(), last));
+    assert_eq!(doms.idom(last), cfg.block(&format!("j{}", diamonds - 2)));
+    assert_eq!(doms.dominators(last).count(), diamonds + 1);
+}
+
+#[test]
+fn test_dominators_of_an_irreducible_loop() {
+    // a and b form a loop entered at both blocks, neither dominates the other
+    let f = parse_function("
+@main(c: bool) {
+  br c .a .b
+.a:
+  br c .b .out
+.b:
+  br c .a .out
+.out:
+  ret
+}
+").unwrap();
+
+    let cfg = Cfg::from_function(&f);
+    let block = |name: &str| cfg.block(name).unwrap();
+    let (entry, a, b, out) = (cfg.entry, block("a"), block("b"), block("out"));
+    let doms = Dominators::new(&cfg);
+    for node in [a, b, out] {
+        assert_eq!(doms.idom(node), Some(entry));
+    }
+    assert!(!doms.dominates(a, b) && !doms.dominates(b, a));
+    let df = doms.frontiers(&cfg);
+    assert_eq!(df[&a], HashSet::from([b, out]));
+    assert_eq!(df[&b], HashSet::from([a, out]));
+
+    // no edge has a target dominating its source
+    let dom = find_dominators(&cfg);
+    assert!(find_loop_headers(&cfg, &dom).is_empty());
+    assert_eq!(find_dominance_frontier(&cfg, &dom, &build_dominator_tree(&dom)), df);
+}
+
+#[test]
+fn test_dominators_skip_unreachable_blocks() {
+    // dead and its loop are cut off from the entry, dead jumps into live code
+    let f = parse_function("
+@main {
+  jmp .live
+.dead:
+  jmp .spin
+.spin:
+  jmp .dead
+.live:
+  ret
+}
+").unwrap();
+
+    let cfg = Cfg::from_function(&f);
+    let block = |name: &str| cfg.block(name).unwrap();
+    let (entry, dead, spin, live) = (cfg.entry, block("dead"), block("spin"), block("live"));
+    let doms = Dominators::new(&cfg);
+    assert_eq!(doms.unreachable(), vec![dead, spin]);
+    for node in [dead, spin] {
+        assert!(!doms.is_reachable(node));
+        assert_eq!(doms.idom(node), None);
+        assert!(!doms.dominates(node, node) && !doms.dominates(entry, node));
+        assert_eq!(doms.dominators(node).count(), 0);
+        assert!(doms.frontiers(&cfg)[&node].is_empty());
+    }
+    assert_eq!(doms.idom(live), Some(entry));
+
+    // the set views agree: no dominators, no idom, no back edge
+    let dom = find_dominators(&cfg);
+    assert!(dom[&dead].is_empty() && dom[&spin].is_empty());
+    let idom = build_dominator_tree(&dom);
+    assert_eq!(idom, doms.tree());
+    assert!(!idom.contains_key(&dead) && !idom.contains_key(&spin));
+    assert_eq!(idom.values().filter(|p| p.is_none()).count(), 1);
+    assert!(find_loop_headers(&cfg, &dom).is_empty());
+    assert!(find_dominance_frontier(&cfg, &dom, &idom).values().all(|df| df.is_empty()));
+}
+
+#[test]
+fn test_dominators_from_any_entry() {
+    // two components, and an entry that is neither the first node nor free
+    // of predecessors: 0 -> 1, 2 -> 3 -> 4 -> 2, 3 -> 5 -> 4
+    let mut graph: DiGraph<(), ()> = DiGraph::new();
+    let n: Vec<NodeIndex> = (0..6).map(|_| graph.add_node(())).collect();
+    for (from, to) in [(0, 1), (2, 3), (3, 4), (4, 2), (3, 5), (5, 4)] {
+        graph.add_edge(n[from], n[to], ());
+    }
+
+    let doms = Dominators::with_entry(&graph, n[2]).unwrap();
+    assert_eq!(doms.entry(), n[2]);
+    assert_eq!(doms.unreachable(), vec![n[0], n[1]]);
+    assert_eq!(doms.idom(n[2]), None);
+    assert_eq!(doms.idom(n[3]), Some(n[2]));
+    assert_eq!(doms.idom(n[4]), Some(n[3]));
+    assert_eq!(doms.idom(n[5]), Some(n[3]));
+    assert_eq!(doms.dominators(n[4]).collect::<Vec<_>>(), vec![n[4], n[3], n[2]]);
+    assert_eq!(doms.frontiers(&graph)[&n[5]], HashSet::from([n[4]]));
+    assert_eq!(doms.frontiers(&graph)[&n[4]], HashSet::from([n[2]]));
+
+    // from the other component everything else is unreachable
+    let doms = Dominators::with_entry(&graph, n[0]).unwrap();
+    assert_eq!(doms.idom(n[1]), Some(n[0]));
+    assert_eq!(doms.unreachable(), n[2..].to_vec());
 }
 
-}
\ No newline at end of file
+#[test]
+fn test_dominators_errors() {
+    let empty: DiGraph<(), ()> = DiGraph::new();
+    let err = Dominators::with_entry(&empty, NodeIndex::new(0)).unwrap_err();
+    assert_eq!(err, DominatorError::EmptyGraph);
+    assert_eq!(err.to_string(), "the graph has no blocks");
+
+    let mut graph: DiGraph<(), ()> = DiGraph::new();
+    graph.add_node(());
+    let err = Dominators::with_entry(&graph, NodeIndex::new(3)).unwrap_err();
+    assert_eq!(err, DominatorError::EntryNotInGraph(NodeIndex::new(3)));
+    assert_eq!(err.to_string(), "entry n3 is not in the graph");
+
+    let doms = Dominators::with_entry(&graph, NodeIndex::new(0)).unwrap();
+    assert!(doms.dominates(NodeIndex::new(0), NodeIndex::new(0)));
+}
+
+#[test]
+fn test_global_dce() {
+    let f = parse_function("
+@main(n: int) {
+  x: int = const 1
+  unused: int = const 2
+  c = lt x n
+  br c .then .else
+.then:
+  x: int = const 3
+  t = add x x
+  jmp .merge
+.else:
+  x: int = const 4
+  call @log x
+.merge:
+  print x
+  y = add x unused
+}
+")
+    .unwrap();
+    let g = global_dce(&f);
+    // `t` is never read and `unused` only fed the dead `y`
+    assert_eq!(g.to_string(), "@main(n: int) {
+  x: int = const 1
+  c = lt x n
+  br c .then .else
+.then:
+  x: int = const 3
+  jmp .merge
+.else:
+  x: int = const 4
+  call @log x
+.merge:
+  print x
+}
+");
+    assert_eq!(global_dce(&g), g);
+}
+
+#[test]
+fn test_global_dce_keeps_loop_values() {
+    let f = parse_function("
+@main {
+  i: int = const 0
+  n: int = const 3
+  one: int = const 1
+.loop:
+  done = eq i n
+  br done .out .body
+.body:
+  i = add i one
+  jmp .loop
+.out:
+}
+")
+    .unwrap();
+    // nothing is printed but the loop condition reads every variable
+    assert_eq!(global_dce(&f), f);
+}
+
+#[test]
+fn test_global_dce_keeps_traps() {
+    let f = parse_function("
+@main(z: int) {
+  a: int = const 1
+  q = div a z
+  two: int = const 2
+  r = rem a two
+  p: ptr<int> = alloc a
+  x = load p
+  free p
+}
+")
+    .unwrap();
+    // an unused division by an unknown divisor and an unused load both stay,
+    // a remainder by a non-zero constant cannot fail and goes
+    let g = global_dce(&f);
+    assert!(g.instr.contains(&Instruction::Div { dest: "q".into(), op1: "a".into(), op2: "z".into() }));
+    assert!(g.instr.contains(&Instruction::Load { dest: "x".into(), ptr: "p".into() }));
+    assert!(!g.instr.iter().any(|instr| matches!(instr, Instruction::Rem { .. })));
+
+    let zero = parse_function("@main {\n  a: int = const 1\n  z: int = const 0\n  q = div a z\n}").unwrap();
+    assert_eq!(global_dce(&zero), zero);
+    assert!(crate::interp::interpret(&global_dce(&zero), crate::interp::DEFAULT_STEP_LIMIT).is_err());
+}
+}
@@ -0,0 +1,874 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::types::*;
+
+// Reference interpreter, used as the ground truth for what a Function means.
+
+pub const DEFAULT_STEP_LIMIT: usize = 1_000_000;
+/// Calls nest at most this deep, so runaway recursion fails fast.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+/// The most elements one `alloc` may ask for, so a huge size fails with
+/// `BadAllocation` instead of taking the interpreter down.
+pub const MAX_ALLOC_ELEMENTS: i64 = 1 << 24;
+
+#[derive(Clone, Debug)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    /// `offset` elements into allocation number `alloc`, which holds `typ` values.
+    Pointer { alloc: usize, offset: i64, typ: Types },
+}
+
+// bit-exact like `Literal`, so a run that returns NaN matches itself
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Pointer { alloc: a, offset: x, typ: s }, Value::Pointer { alloc: b, offset: y, typ: t }) => {
+                a == b && x == y && s == t
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{:?}", v),
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::Pointer { alloc, offset, .. } => write!(f, "<ptr {}+{}>", alloc, offset),
+        }
+    }
+}
+
+impl Value {
+    pub fn typ(&self) -> Types {
+        match self {
+            Value::Int(_) => Types::Int,
+            Value::Float(_) => Types::Float,
+            Value::Bool(_) => Types::Bool,
+            Value::Pointer { typ, .. } => Types::Ptr(Box::new(typ.clone())),
+        }
+    }
+}
+
+impl From<&Literal> for Value {
+    fn from(lit: &Literal) -> Self {
+        match lit {
+            Literal::Int(v) => Value::Int(*v),
+            Literal::Float(v) => Value::Float(*v),
+            Literal::Bool(v) => Value::Bool(*v),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InterpError {
+    UndefinedVariable { var: String, index: usize },
+    TypeMismatch { index: usize, expected: Types, found: Value },
+    MissingLabel { label: String, index: usize },
+    DivisionByZero { index: usize },
+    UnknownFunction { func: String, index: usize },
+    ArgumentCount { func: String, index: usize, expected: usize, found: usize },
+    /// A call at `index` wants a value but `func` returned without one.
+    MissingReturnValue { func: String, index: usize },
+    /// `alloc` of zero, a negative number or more than `MAX_ALLOC_ELEMENTS`
+    /// elements, or of more than the memory available.
+    BadAllocation { index: usize, size: i64 },
+    OutOfBounds { index: usize, offset: i64, size: usize },
+    /// Access to (or a second `free` of) memory that was already freed.
+    UseAfterFree { index: usize },
+    /// `free` of a pointer that does not point at the start of its allocation.
+    InvalidFree { index: usize, offset: i64 },
+    UninitializedLoad { index: usize },
+    /// A phi with no argument for the block control came from (`None` when
+    /// no label was passed before it).
+    NoPhiArgument { index: usize, from: Option<String> },
+    StepLimitExceeded { limit: usize },
+    CallDepthExceeded { limit: usize },
+}
+
+impl fmt::Display for InterpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpError::UndefinedVariable { var, index } => {
+                write!(f, "instruction {}: undefined variable `{}`", index, var)
+            }
+            InterpError::TypeMismatch { index, expected, found } => {
+                write!(f, "instruction {}: expected a {} value, found `{}`", index, expected, found)
+            }
+            InterpError::MissingLabel { label, index } => write!(f, "instruction {}: no label `.{}`", index, label),
+            InterpError::DivisionByZero { index } => write!(f, "instruction {}: division by zero", index),
+            InterpError::UnknownFunction { func, index } => write!(f, "instruction {}: no function `@{}`", index, func),
+            InterpError::ArgumentCount { func, index, expected, found } => {
+                write!(f, "instruction {}: `@{}` takes {} argument(s), found {}", index, func, expected, found)
+            }
+            InterpError::MissingReturnValue { func, index } => {
+                write!(f, "instruction {}: `@{}` returned no value", index, func)
+            }
+            InterpError::BadAllocation { index, size } => {
+                write!(f, "instruction {}: cannot allocate {} elements", index, size)
+            }
+            InterpError::OutOfBounds { index, offset, size } => {
+                write!(f, "instruction {}: offset {} is out of bounds for {} elements", index, offset, size)
+            }
+            InterpError::UseAfterFree { index } => write!(f, "instruction {}: memory used after `free`", index),
+            InterpError::InvalidFree { index, offset } => {
+                write!(f, "instruction {}: `free` of a pointer {} elements into its allocation", index, offset)
+            }
+            InterpError::UninitializedLoad { index } => {
+                write!(f, "instruction {}: load of memory that was never stored to", index)
+            }
+            InterpError::NoPhiArgument { index, from: Some(label) } => {
+                write!(f, "instruction {}: phi has no argument for `.{}`", index, label)
+            }
+            InterpError::NoPhiArgument { index, from: None } => {
+                write!(f, "instruction {}: phi reached without passing a label", index)
+            }
+            InterpError::StepLimitExceeded { limit } => write!(f, "step limit of {} exceeded", limit),
+            InterpError::CallDepthExceeded { limit } => write!(f, "calls nested deeper than {}", limit),
+        }
+    }
+}
+
+impl std::error::Error for InterpError {}
+
+/// What a run produced: printed lines, the returned value and how many
+/// instructions were executed (labels are free).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outcome {
+    pub output: Vec<String>,
+    pub ret: Option<Value>,
+    pub steps: usize,
+}
+
+/// Run `f` on its own, it can only call itself.
+pub fn interpret(f: &Function, step_limit: usize) -> Result<Outcome, InterpError> {
+    let machine = Machine::new(HashMap::from([(f.name.as_str(), f)]), step_limit);
+    machine.run(f)
+}
+
+/// Run `@main` of `program`.
+pub fn interpret_program(program: &Program, step_limit: usize) -> Result<Outcome, InterpError> {
+    let main = program
+        .function("main")
+        .ok_or_else(|| InterpError::UnknownFunction { func: "main".to_string(), index: 0 })?;
+    let functions = program.functions.iter().map(|f| (f.name.as_str(), f)).collect();
+    Machine::new(functions, step_limit).run(main)
+}
+
+// Calls push a frame instead of recursing, so deep recursion in the program
+// does not need a deep Rust stack.
+struct Machine<'a> {
+    functions: HashMap<&'a str, &'a Function>,
+    // every allocation ever made, `None` once freed, `None` cells were never stored to
+    heap: Vec<Option<Vec<Option<Value>>>>,
+    output: Vec<String>,
+    steps: usize,
+    step_limit: usize,
+}
+
+struct Frame<'a> {
+    func: &'a Function,
+    labels: HashMap<&'a str, usize>,
+    env: HashMap<String, Value>,
+    pc: usize,
+    // the last label passed and the one before it, which phis select on
+    label: Option<&'a str>,
+    previous_label: Option<&'a str>,
+}
+
+impl<'a> Frame<'a> {
+    fn new(func: &'a Function, env: HashMap<String, Value>) -> Self {
+        let mut labels = HashMap::new();
+        for (i, instr) in func.instr.iter().enumerate() {
+            if let Instruction::Label { label } = instr {
+                labels.entry(label.as_str()).or_insert(i);
+            }
+        }
+        Frame { func, labels, env, pc: 0, label: None, previous_label: None }
+    }
+}
+
+impl<'a> Machine<'a> {
+    fn new(functions: HashMap<&'a str, &'a Function>, step_limit: usize) -> Self {
+        Machine { functions, heap: Vec::new(), output: Vec::new(), steps: 0, step_limit }
+    }
+
+    fn run(mut self, entry: &'a Function) -> Result<Outcome, InterpError> {
+        let mut stack = vec![Frame::new(entry, HashMap::new())];
+        loop {
+            let frame = stack.last_mut().expect("the entry frame is popped last");
+            let ret = match self.step(frame)? {
+                Step::Continue => continue,
+                Step::Call(callee, env) => {
+                    if stack.len() == MAX_CALL_DEPTH {
+                        return Err(InterpError::CallDepthExceeded { limit: MAX_CALL_DEPTH });
+                    }
+                    stack.push(Frame::new(callee, env));
+                    continue;
+                }
+                Step::Return(ret) => ret,
+            };
+
+            stack.pop();
+            let Some(caller) = stack.last_mut() else {
+                return Ok(Outcome { output: self.output, ret, steps: self.steps });
+            };
+            // the caller is still at its `call`
+            if let Instruction::Call { dest: Some((dest, typ)), func, .. } = &caller.func.instr[caller.pc] {
+                let value = ret.ok_or_else(|| InterpError::MissingReturnValue { func: func.clone(), index: caller.pc })?;
+                if value.typ() != *typ {
+                    return Err(InterpError::TypeMismatch { index: caller.pc, expected: typ.clone(), found: value });
+                }
+                caller.env.insert(dest.clone(), value);
+            }
+            caller.pc += 1;
+        }
+    }
+
+    // execute the instruction at `frame.pc`
+    fn step(&mut self, frame: &mut Frame<'a>) -> Result<Step<'a>, InterpError> {
+        let pc = frame.pc;
+        let Some(instr) = frame.func.instr.get(pc) else {
+            // falling off the end is an implicit `ret`
+            return Ok(Step::Return(None));
+        };
+        if let Instruction::Label { label } = instr {
+            frame.previous_label = frame.label.replace(label);
+            frame.pc += 1;
+            return Ok(Step::Continue);
+        }
+
+        self.steps += 1;
+        if self.steps > self.step_limit {
+            return Err(InterpError::StepLimitExceeded { limit: self.step_limit });
+        }
+
+        let env = &mut frame.env;
+        let labels = &frame.labels;
+        let read = |env: &HashMap<String, Value>, var: &String| {
+            env.get(var).cloned().ok_or_else(|| InterpError::UndefinedVariable { var: var.clone(), index: pc })
+        };
+        let jump = |label: &String| {
+            labels
+                .get(label.as_str())
+                .copied()
+                .ok_or_else(|| InterpError::MissingLabel { label: label.clone(), index: pc })
+        };
+
+        let mut next = pc + 1;
+        match instr {
+            Instruction::Const { dest, values, .. } => {
+                env.insert(dest.clone(), Value::from(values));
+            }
+            _ if let Some((op, dest, op1, op2)) = instr.as_binary() => {
+                let v = eval_binary(pc, op, read(env, op1)?, read(env, op2)?)?;
+                env.insert(dest.clone(), v);
+            }
+            Instruction::Not { dest, src } => {
+                let v = match read(env, src)? {
+                    Value::Bool(b) => !b,
+                    found => return Err(InterpError::TypeMismatch { index: pc, expected: Types::Bool, found }),
+                };
+                env.insert(dest.clone(), Value::Bool(v));
+            }
+            Instruction::Id { dest, src } | Instruction::Move { dest, src } => {
+                let v = read(env, src)?;
+                env.insert(dest.clone(), v);
+            }
+            Instruction::Print { value } => {
+                self.output.push(read(env, value)?.to_string());
+            }
+            Instruction::Jmp { label } => {
+                next = jump(label)?;
+            }
+            Instruction::Br { cond, then_label, else_label } => {
+                next = match read(env, cond)? {
+                    Value::Bool(true) => jump(then_label)?,
+                    Value::Bool(false) => jump(else_label)?,
+                    found => return Err(InterpError::TypeMismatch { index: pc, expected: Types::Bool, found }),
+                };
+            }
+            Instruction::Ret { value } => {
+                let value = value.as_ref().map(|v| read(env, v)).transpose()?;
+                // functions without a declared type may still return anything
+                if let (Some(value), Some(typ)) = (&value, &frame.func.ret_type)
+                    && value.typ() != *typ
+                {
+                    return Err(InterpError::TypeMismatch { index: pc, expected: typ.clone(), found: value.clone() });
+                }
+                return Ok(Step::Return(value));
+            }
+            Instruction::Call { func, args, .. } => {
+                let callee = *self
+                    .functions
+                    .get(func.as_str())
+                    .ok_or_else(|| InterpError::UnknownFunction { func: func.clone(), index: pc })?;
+                if callee.args.len() != args.len() {
+                    let (expected, found) = (callee.args.len(), args.len());
+                    return Err(InterpError::ArgumentCount { func: func.clone(), index: pc, expected, found });
+                }
+                let mut callee_env = HashMap::new();
+                for (param, arg) in callee.args.iter().zip(args) {
+                    let value = read(env, arg)?;
+                    if value.typ() != param.typ {
+                        return Err(InterpError::TypeMismatch { index: pc, expected: param.typ.clone(), found: value });
+                    }
+                    callee_env.insert(param.name.clone(), value);
+                }
+                // `pc` stays on the call until the callee returns
+                return Ok(Step::Call(callee, callee_env));
+            }
+            Instruction::Alloc { dest, typ, size } => {
+                let size = match read(env, size)? {
+                    Value::Int(n) if n > 0 && n <= MAX_ALLOC_ELEMENTS => n,
+                    Value::Int(n) => return Err(InterpError::BadAllocation { index: pc, size: n }),
+                    found => return Err(InterpError::TypeMismatch { index: pc, expected: Types::Int, found }),
+                };
+                // the text and Bril loaders only build `alloc` with a pointer type
+                let Types::Ptr(elem) = typ else {
+                    let expected = Types::Ptr(Box::new(typ.clone()));
+                    return Err(InterpError::TypeMismatch { index: pc, expected, found: Value::Int(size) });
+                };
+                let mut cells = Vec::new();
+                if cells.try_reserve_exact(size as usize).is_err() {
+                    return Err(InterpError::BadAllocation { index: pc, size });
+                }
+                cells.resize(size as usize, None);
+                self.heap.push(Some(cells));
+                env.insert(dest.clone(), Value::Pointer { alloc: self.heap.len() - 1, offset: 0, typ: (**elem).clone() });
+            }
+            Instruction::Free { ptr } => {
+                let (alloc, offset, _) = pointer(pc, read(env, ptr)?)?;
+                if self.heap[alloc].is_none() {
+                    return Err(InterpError::UseAfterFree { index: pc });
+                }
+                if offset != 0 {
+                    return Err(InterpError::InvalidFree { index: pc, offset });
+                }
+                self.heap[alloc] = None;
+            }
+            Instruction::Load { dest, ptr } => {
+                let v = self.cell(pc, read(env, ptr)?)?.clone().ok_or(InterpError::UninitializedLoad { index: pc })?;
+                env.insert(dest.clone(), v);
+            }
+            Instruction::Store { ptr, value } => {
+                let (ptr, value) = (read(env, ptr)?, read(env, value)?);
+                let (_, _, typ) = pointer(pc, ptr.clone())?;
+                if value.typ() != typ {
+                    return Err(InterpError::TypeMismatch { index: pc, expected: typ, found: value });
+                }
+                *self.cell(pc, ptr)? = Some(value);
+            }
+            Instruction::PtrAdd { dest, ptr, offset } => {
+                let (alloc, base, typ) = pointer(pc, read(env, ptr)?)?;
+                let offset = match read(env, offset)? {
+                    Value::Int(n) => n,
+                    found => return Err(InterpError::TypeMismatch { index: pc, expected: Types::Int, found }),
+                };
+                env.insert(dest.clone(), Value::Pointer { alloc, offset: base.wrapping_add(offset), typ });
+            }
+            Instruction::Phi { .. } => {
+                // all the phis at the top of a block read before any of them writes
+                let phis: Vec<&Instruction> =
+                    frame.func.instr[pc..].iter().take_while(|i| matches!(i, Instruction::Phi { .. })).collect();
+                let mut values = Vec::new();
+                for (i, phi) in phis.iter().enumerate() {
+                    let Instruction::Phi { dest, args } = phi else { unreachable!() };
+                    let from = frame.previous_label;
+                    let (_, var) = args
+                        .iter()
+                        .find(|(label, _)| Some(label.as_str()) == from)
+                        .ok_or_else(|| InterpError::NoPhiArgument { index: pc + i, from: from.map(str::to_string) })?;
+                    // an undefined argument leaves the phi undefined
+                    values.push((dest, env.get(var).cloned()));
+                }
+                for (dest, value) in values {
+                    match value {
+                        Some(value) => env.insert(dest.clone(), value),
+                        None => env.remove(dest),
+                    };
+                }
+                self.steps += phis.len() - 1;
+                next = pc + phis.len();
+            }
+            Instruction::Label { .. } => unreachable!("labels are skipped above"),
+            _ => unreachable!("every two operand instruction is matched by `as_binary`"),
+        }
+        frame.pc = next;
+        Ok(Step::Continue)
+    }
+
+    // the memory `ptr` points at, checked for use after free and bounds
+    fn cell(&mut self, index: usize, ptr: Value) -> Result<&mut Option<Value>, InterpError> {
+        let (alloc, offset, _) = pointer(index, ptr)?;
+        let cells = self.heap[alloc].as_mut().ok_or(InterpError::UseAfterFree { index })?;
+        let size = cells.len();
+        usize::try_from(offset)
+            .ok()
+            .and_then(|i| cells.get_mut(i))
+            .ok_or(InterpError::OutOfBounds { index, offset, size })
+    }
+}
+
+fn pointer(index: usize, value: Value) -> Result<(usize, i64, Types), InterpError> {
+    match value {
+        Value::Pointer { alloc, offset, typ } => Ok((alloc, offset, typ)),
+        // the element type is unknown here, `int` stands in for it
+        found => Err(InterpError::TypeMismatch { index, expected: Types::Ptr(Box::new(Types::Int)), found }),
+    }
+}
+
+enum Step<'a> {
+    Continue,
+    Call(&'a Function, HashMap<String, Value>),
+    Return(Option<Value>),
+}
+
+// Integer arithmetic wraps on overflow, shift amounts are taken modulo 64.
+// Float operations are plain IEEE 754 (NaN compares unequal, `x / 0.0` is an
+// infinity or NaN rather than an error).
+fn eval_binary(index: usize, op: BinOp, a: Value, b: Value) -> Result<Value, InterpError> {
+    let ints = |a: Value, b: Value| match (a, b) {
+        (Value::Int(x), Value::Int(y)) => Ok((x, y)),
+        (Value::Int(_), found) | (found, _) => Err(InterpError::TypeMismatch { index, expected: Types::Int, found }),
+    };
+    let floats = |a: Value, b: Value| match (a, b) {
+        (Value::Float(x), Value::Float(y)) => Ok((x, y)),
+        (Value::Float(_), found) | (found, _) => Err(InterpError::TypeMismatch { index, expected: Types::Float, found }),
+    };
+    let bools = |a: Value, b: Value| match (a, b) {
+        (Value::Bool(x), Value::Bool(y)) => Ok((x, y)),
+        (Value::Bool(_), found) | (found, _) => Err(InterpError::TypeMismatch { index, expected: Types::Bool, found }),
+    };
+    let divisor = |y: i64| match y {
+        0 => Err(InterpError::DivisionByZero { index }),
+        y => Ok(y),
+    };
+
+    Ok(match op {
+        BinOp::Eq | BinOp::Ne => {
+            let mismatch = |expected: Types, found: Value| Err(InterpError::TypeMismatch { index, expected, found });
+            let same = match (&a, &b) {
+                (Value::Int(x), Value::Int(y)) => x == y,
+                (Value::Float(x), Value::Float(y)) => x == y,
+                (Value::Bool(x), Value::Bool(y)) => x == y,
+                (Value::Int(_), _) => return mismatch(Types::Int, b),
+                (Value::Float(_), _) => return mismatch(Types::Float, b),
+                (Value::Bool(_), _) => return mismatch(Types::Bool, b),
+                (Value::Pointer { .. }, _) => return mismatch(Types::Int, a),
+            };
+            Value::Bool(same == (op == BinOp::Eq))
+        }
+        BinOp::And => bools(a, b).map(|(x, y)| Value::Bool(x && y))?,
+        BinOp::Or => bools(a, b).map(|(x, y)| Value::Bool(x || y))?,
+        BinOp::FAdd => floats(a, b).map(|(x, y)| Value::Float(x + y))?,
+        BinOp::FSub => floats(a, b).map(|(x, y)| Value::Float(x - y))?,
+        BinOp::FMul => floats(a, b).map(|(x, y)| Value::Float(x * y))?,
+        BinOp::FDiv => floats(a, b).map(|(x, y)| Value::Float(x / y))?,
+        BinOp::FEq => floats(a, b).map(|(x, y)| Value::Bool(x == y))?,
+        BinOp::FLt => floats(a, b).map(|(x, y)| Value::Bool(x < y))?,
+        BinOp::FLe => floats(a, b).map(|(x, y)| Value::Bool(x <= y))?,
+        BinOp::FGt => floats(a, b).map(|(x, y)| Value::Bool(x > y))?,
+        BinOp::FGe => floats(a, b).map(|(x, y)| Value::Bool(x >= y))?,
+        BinOp::Add => ints(a, b).map(|(x, y)| Value::Int(x.wrapping_add(y)))?,
+        BinOp::Mul => ints(a, b).map(|(x, y)| Value::Int(x.wrapping_mul(y)))?,
+        BinOp::Sub => ints(a, b).map(|(x, y)| Value::Int(x.wrapping_sub(y)))?,
+        BinOp::Div => {
+            let (x, y) = ints(a, b)?;
+            Value::Int(x.wrapping_div(divisor(y)?))
+        }
+        BinOp::Rem => {
+            let (x, y) = ints(a, b)?;
+            Value::Int(x.wrapping_rem(divisor(y)?))
+        }
+        BinOp::Shl => ints(a, b).map(|(x, y)| Value::Int(x.wrapping_shl(y as u32)))?,
+        BinOp::Shr => ints(a, b).map(|(x, y)| Value::Int(x.wrapping_shr(y as u32)))?,
+        BinOp::BitAnd => ints(a, b).map(|(x, y)| Value::Int(x & y))?,
+        BinOp::BitOr => ints(a, b).map(|(x, y)| Value::Int(x | y))?,
+        BinOp::BitXor => ints(a, b).map(|(x, y)| Value::Int(x ^ y))?,
+        BinOp::Lt => ints(a, b).map(|(x, y)| Value::Bool(x < y))?,
+        BinOp::Le => ints(a, b).map(|(x, y)| Value::Bool(x <= y))?,
+        BinOp::Gt => ints(a, b).map(|(x, y)| Value::Bool(x > y))?,
+        BinOp::Ge => ints(a, b).map(|(x, y)| Value::Bool(x >= y))?,
+    })
+}
+
+// =====================================
+//             TESTS
+// =====================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::{parse_function, parse_program};
+
+    fn run(src: &str) -> Result<Outcome, InterpError> {
+        interpret(&parse_function(src).unwrap(), DEFAULT_STEP_LIMIT)
+    }
+
+    #[test]
+    fn test_diamond() {
+        let out = run("
+@main {
+  v0: bool = const true
+  br v0 .then_blk .else_blk
+.then_blk:
+  v1: int = const 10
+  jmp .merge_blk
+.else_blk:
+  v1: int = const 20
+  jmp .merge_blk
+.merge_blk:
+  print v1
+  ret v1
+}
+")
+        .unwrap();
+
+        assert_eq!(out.output, vec!["10"]);
+        assert_eq!(out.ret, Some(Value::Int(10)));
+        assert_eq!(out.steps, 6);
+    }
+
+    #[test]
+    fn test_loop_and_arithmetic() {
+        // sum of 0..5, plus a bool print
+        let out = run("
+@main {
+  i: int = const 0
+  n: int = const 5
+  one: int = const 1
+  sum: int = const 0
+.loop:
+  done = eq i n
+  br done .exit .body
+.body:
+  sum = add sum i
+  i = add i one
+  jmp .loop
+.exit:
+  sq = mul sum sum
+  print sq
+  print done
+}
+")
+        .unwrap();
+
+        assert_eq!(out.output, vec!["100", "true"]);
+        assert_eq!(out.ret, None);
+        // 4 setup + 5 iterations of 5 + final eq/br + 3 at exit
+        assert_eq!(out.steps, 4 + 5 * 5 + 2 + 3);
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        assert_eq!(
+            run("@f {\n  print x\n}").unwrap_err(),
+            InterpError::UndefinedVariable { var: "x".into(), index: 0 }
+        );
+
+        assert_eq!(
+            run("@f {\n  b: bool = const true\n  c = add b b\n}").unwrap_err(),
+            InterpError::TypeMismatch { index: 1, expected: Types::Int, found: Value::Bool(true) }
+        );
+
+        assert_eq!(
+            run("@f {\n  a: int = const 1\n  br a .x .x\n.x:\n}").unwrap_err(),
+            InterpError::TypeMismatch { index: 1, expected: Types::Bool, found: Value::Int(1) }
+        );
+
+        assert_eq!(
+            run("@f {\n  jmp .nowhere\n}").unwrap_err(),
+            InterpError::MissingLabel { label: "nowhere".into(), index: 0 }
+        );
+
+        let f = parse_function("@f {\n.top:\n  jmp .top\n}").unwrap();
+        assert_eq!(interpret(&f, 100).unwrap_err(), InterpError::StepLimitExceeded { limit: 100 });
+    }
+
+    #[test]
+    fn test_full_instruction_set() {
+        let out = run("
+@f {
+  a: int = const 17
+  b: int = const 5
+  m: int = const -17
+  c = sub a b
+  d = div a b
+  e = rem a b
+  f = div m b
+  g = rem m b
+  h = shl b b
+  i = shr m b
+  j = bitand a b
+  k = bitor a b
+  l = bitxor a b
+  print c
+  print d
+  print e
+  print f
+  print g
+  print h
+  print i
+  print j
+  print k
+  print l
+  lt = lt a b
+  le = le b b
+  gt = gt a b
+  ge = ge m b
+  ne = ne a b
+  x = and lt le
+  y = or lt le
+  z = not y
+  print lt
+  print le
+  print gt
+  print ge
+  print ne
+  print x
+  print y
+  print z
+}
+")
+        .unwrap();
+
+        assert_eq!(
+            out.output,
+            vec![
+                "12", "3", "2", "-3", "-2", "160", "-1", "1", "21", "20", //
+                "false", "true", "true", "false", "true", "false", "true", "false",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let f = "@f {\n  a: int = const 1\n  z: int = const 0\n  b = rem a z\n}";
+        assert_eq!(run(f).unwrap_err(), InterpError::DivisionByZero { index: 2 });
+    }
+
+    #[test]
+    fn test_float_arithmetic() {
+        let out = run("
+@f {
+  a: float = const 1.5
+  b: float = const -0.0
+  z: float = const 0.0
+  c = fmul a b
+  d = fdiv a z
+  n = fsub d d
+  e = feq n n
+  l = flt b z
+  s = feq b z
+  print c
+  print d
+  print n
+  print e
+  print l
+  print s
+  ret n
+}
+")
+        .unwrap();
+
+        assert_eq!(out.output, vec!["-0.0", "inf", "NaN", "false", "false", "true"]);
+        assert!(matches!(out.ret, Some(Value::Float(v)) if v.is_nan()));
+
+        assert_eq!(
+            run("@f {\n  a: int = const 1\n  b = fadd a a\n}").unwrap_err(),
+            InterpError::TypeMismatch { index: 1, expected: Types::Float, found: Value::Int(1) }
+        );
+    }
+
+    #[test]
+    fn test_calls() {
+        let program = parse_program("
+@main {
+  n: int = const 5
+  f: int = call @fact n
+  print f
+  call @show f n
+}
+
+@fact(n: int): int {
+  one: int = const 1
+  base = le n one
+  br base .done .rec
+.done:
+  ret one
+.rec:
+  m = sub n one
+  r: int = call @fact m
+  p = mul n r
+  ret p
+}
+
+@show(a: int, b: int) {
+  print a
+  print b
+}
+")
+        .unwrap();
+
+        let out = interpret_program(&program, DEFAULT_STEP_LIMIT).unwrap();
+        assert_eq!(out.output, vec!["120", "120", "5"]);
+        assert_eq!(out.ret, None);
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let run_main = |src: &str| interpret_program(&parse_program(src).unwrap(), DEFAULT_STEP_LIMIT);
+
+        assert_eq!(
+            run_main("@main {\n  call @nowhere\n}").unwrap_err(),
+            InterpError::UnknownFunction { func: "nowhere".into(), index: 0 }
+        );
+        assert_eq!(
+            run_main("@main {\n  a: int = const 1\n  call @f a a\n}\n@f(x: int) {\n}").unwrap_err(),
+            InterpError::ArgumentCount { func: "f".into(), index: 1, expected: 1, found: 2 }
+        );
+        assert_eq!(
+            run_main("@main {\n  a: bool = const true\n  call @f a\n}\n@f(x: int) {\n}").unwrap_err(),
+            InterpError::TypeMismatch { index: 1, expected: Types::Int, found: Value::Bool(true) }
+        );
+        assert_eq!(
+            run_main("@main {\n  a: int = call @f\n}\n@f {\n  ret\n}").unwrap_err(),
+            InterpError::MissingReturnValue { func: "f".into(), index: 0 }
+        );
+        assert_eq!(
+            run_main("@main {\n  a: int = call @f\n}\n@f: bool {\n  b: int = const 1\n  ret b\n}").unwrap_err(),
+            InterpError::TypeMismatch { index: 1, expected: Types::Bool, found: Value::Int(1) }
+        );
+        assert_eq!(
+            run_main("@main {\n  a: bool = call @f\n}\n@f {\n  b: int = const 1\n  ret b\n}").unwrap_err(),
+            InterpError::TypeMismatch { index: 0, expected: Types::Bool, found: Value::Int(1) }
+        );
+        assert_eq!(
+            run_main("@main {\n  call @main\n}").unwrap_err(),
+            InterpError::CallDepthExceeded { limit: MAX_CALL_DEPTH }
+        );
+        assert_eq!(
+            interpret_program(&Program::default(), DEFAULT_STEP_LIMIT).unwrap_err(),
+            InterpError::UnknownFunction { func: "main".into(), index: 0 }
+        );
+    }
+
+    #[test]
+    fn test_memory() {
+        let out = run("@f {
+  n: int = const 3
+  p: ptr<int> = alloc n
+  i: int = const 0
+  one: int = const 1
+.fill:
+  done = eq i n
+  br done .sum .body
+.body:
+  q = ptradd p i
+  sq = mul i i
+  store q sq
+  i = add i one
+  jmp .fill
+.sum:
+  two: int = const 2
+  last = ptradd p two
+  v = load last
+  print last
+  free p
+  ret v
+}").unwrap();
+        assert_eq!(out.output, vec!["<ptr 0+2>"]);
+        assert_eq!(out.ret, Some(Value::Int(4)));
+    }
+
+    #[test]
+    fn test_memory_errors() {
+        let prelude = "  n: int = const 2\n  p: ptr<int> = alloc n\n";
+        let run_with = |body: &str| run(&format!("@f {{\n{}{}}}", prelude, body)).unwrap_err();
+
+        assert_eq!(run_with("  q = ptradd p n\n  x = load q\n"), InterpError::OutOfBounds { index: 3, offset: 2, size: 2 });
+        assert_eq!(run_with("  m: int = const -1\n  q = ptradd p m\n  store q n\n"), InterpError::OutOfBounds { index: 4, offset: -1, size: 2 });
+        assert_eq!(run_with("  store p n\n  free p\n  x = load p\n"), InterpError::UseAfterFree { index: 4 });
+        assert_eq!(run_with("  free p\n  free p\n"), InterpError::UseAfterFree { index: 3 });
+        assert_eq!(run_with("  one: int = const 1\n  q = ptradd p one\n  free q\n"), InterpError::InvalidFree { index: 4, offset: 1 });
+        assert_eq!(run_with("  x = load p\n"), InterpError::UninitializedLoad { index: 2 });
+        assert_eq!(
+            run_with("  b: bool = const true\n  store p b\n"),
+            InterpError::TypeMismatch { index: 3, expected: Types::Int, found: Value::Bool(true) }
+        );
+        assert_eq!(
+            run("@f {\n  z: int = const 0\n  p: ptr<int> = alloc z\n}").unwrap_err(),
+            InterpError::BadAllocation { index: 1, size: 0 }
+        );
+        assert_eq!(
+            run("@f {\n  n: int = const 4611686018427387904\n  p: ptr<int> = alloc n\n}").unwrap_err(),
+            InterpError::BadAllocation { index: 1, size: 1 << 62 }
+        );
+    }
+
+    #[test]
+    fn test_wrapping_arithmetic() {
+        let out = run("@f {\n  a: int = const 9223372036854775807\n  b: int = const 1\n  c = add a b\n  ret c\n}").unwrap();
+        assert_eq!(out.ret, Some(Value::Int(i64::MIN)));
+    }
+
+    #[test]
+    fn test_phis() {
+        // a swap: both phis read the values from before the block
+        let out = run("
+@main {
+.entry:
+  a: int = const 1
+  b: int = const 2
+  n: int = const 0
+  one: int = const 1
+  three: int = const 3
+.head:
+  x = phi .entry a .body y
+  y = phi .entry b .body x
+  i = phi .entry n .body j
+  done = eq i three
+  br done .exit .body
+.body:
+  print x
+  j = add i one
+  jmp .head
+.exit:
+  ret x
+}
+")
+        .unwrap();
+        assert_eq!(out.output, vec!["1", "2", "1"]);
+        assert_eq!(out.ret, Some(Value::Int(2)));
+
+        let err = run("@f {
+.a:
+  x: int = const 1
+.b:
+  y = phi .c x
+}").unwrap_err();
+        assert_eq!(err, InterpError::NoPhiArgument { index: 3, from: Some("a".into()) });
+        let err = run("@f {
+  x: int = const 1
+  y = phi .c x
+}").unwrap_err();
+        assert_eq!(err, InterpError::NoPhiArgument { index: 1, from: None });
+
+        // an undefined argument makes the phi undefined, not an error
+        let err = run("@f {
+.a:
+.b:
+  y = phi .a x
+  print y
+}").unwrap_err();
+        assert!(matches!(err, InterpError::UndefinedVariable { .. }));
+    }
+}
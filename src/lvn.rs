@@ -6,13 +6,16 @@ use crate::types::*;
 pub enum ExprKey {
     Const(Literal),
     Id(usize),
-    Add(usize, usize),
-    Mul(usize, usize),
-    Eq(usize, usize),
+    // opcode and operand numbers, sorted when the opcode is commutative
+    Binary(BinOp, usize, usize),
+    Not(usize),
     Move(usize),
+    // pointer number, only valid until the next store, free or call
+    Load(usize),
+    PtrAdd(usize, usize),
 }
 
-pub fn lvn(block: &Vec<Instruction>) -> Vec<Instruction> {
+pub fn lvn(block: &[Instruction]) -> Vec<Instruction> {
     let mut var2num: HashMap<String, usize> = HashMap::new();
     let mut table: HashMap<ExprKey, usize> = HashMap::new();
     let mut canon_var: HashMap<usize, String> = HashMap::new();
@@ -21,7 +24,17 @@ pub fn lvn(block: &Vec<Instruction>) -> Vec<Instruction> {
     let mut new_block = Vec::new();
 
     for instr in block {
-        if let Some(expr_key) = get_var(instr, &var2num,&expr_for_num) {
+        // operands are renamed against the state *before* this instruction writes its dest
+        let canonical_instr = canonicalize_operands(instr, &var2num, &canon_var);
+        if let Some(dest) = get_dest(instr) {
+            release_canonical(dest, &mut var2num, &mut canon_var, &mut table);
+        }
+        // any pointer may alias any other, so memory may have changed under every load
+        if matches!(instr, Instruction::Store { .. } | Instruction::Free { .. } | Instruction::Call { .. }) {
+            table.retain(|key, _| !matches!(key, ExprKey::Load(_)));
+        }
+
+        if let Some(expr_key) = get_var(instr, &var2num, &expr_for_num, &table) {
             let dest_opt = get_dest(instr);
     
             if let Some(&num) = table.get(&expr_key) {
@@ -44,11 +57,10 @@ pub fn lvn(block: &Vec<Instruction>) -> Vec<Instruction> {
                     }
                 } else {
                     // no destination, just push original
-                    let canonical_instr = canonicalize_operands(instr, &var2num, &canon_var);
-                    new_block.push(canonical_instr.clone());
+                    new_block.push(canonical_instr);
                 }
             } else {
-                let idx = table.len() + 1;
+                let idx = expr_for_num.len() + 1;
                 table.insert(expr_key.clone(), idx);
                 expr_for_num.insert(idx,expr_key);
                 num2instr.insert(idx,instr.clone());
@@ -58,19 +70,26 @@ pub fn lvn(block: &Vec<Instruction>) -> Vec<Instruction> {
                     canon_var.insert(idx, dest.clone());
                 }
                 
-                let canonical_instr = canonicalize_operands(instr, &var2num, &canon_var);
                 new_block.push(canonical_instr);
             }
         } else {
-            // expr_key is None, just push instruction
-            let canonical_instr = canonicalize_operands(instr, &var2num, &canon_var);
+            // expr_key is None (an alloc, a call, ...): the result is a value
+            // nothing else computes, give it a number of its own so later
+            // instructions using it can still be numbered
+            if let Some(dest) = get_dest(instr) {
+                let idx = expr_for_num.len() + 1;
+                table.insert(ExprKey::Id(idx), idx);
+                expr_for_num.insert(idx, ExprKey::Id(idx));
+                var2num.insert(dest.clone(), idx);
+                canon_var.insert(idx, dest.clone());
+            }
             new_block.push(canonical_instr);
         }
     }
     new_block   
 }
 
-pub fn constant_fold(block: &Vec<Instruction>) -> Vec<Instruction> {
+pub fn constant_fold(block: &[Instruction]) -> Vec<Instruction> {
     let mut const_values: HashMap<String, Literal> = HashMap::new();
     let mut new_block = Vec::new();
 
@@ -83,33 +102,7 @@ pub fn constant_fold(block: &Vec<Instruction>) -> Vec<Instruction> {
         }
 
         // Try to fold operations
-        let folded = match instr {
-            Instruction::Add { dest, op1, op2, .. } => {
-                if let (Some(Literal::Int(v1)), Some(Literal::Int(v2))) = 
-                    (const_values.get(op1), const_values.get(op2)) {
-                    Some(Instruction::Const {
-                        dest: dest.clone(),
-                        typ: Types::Int,
-                        values: Literal::Int(v1 + v2),
-                    })
-                } else {
-                    None
-                }
-            }
-            Instruction::Mul { dest, op1, op2, .. } => {
-                if let (Some(Literal::Int(v1)), Some(Literal::Int(v2))) = 
-                    (const_values.get(op1), const_values.get(op2)) {
-                    Some(Instruction::Const {
-                        dest: dest.clone(),
-                        typ: Types::Int,
-                        values: Literal::Int(v1 * v2),
-                    })
-                } else {
-                    None
-                }
-            }
-            _ => None,
-        };
+        let folded = fold_instr(instr, |var| const_values.get(var).cloned());
 
         if let Some(folded_instr) = folded {
             if let Instruction::Const { dest, values, .. } = &folded_instr {
@@ -117,6 +110,10 @@ pub fn constant_fold(block: &Vec<Instruction>) -> Vec<Instruction> {
             }
             new_block.push(folded_instr);
         } else {
+            // redefined by something we could not fold, the old constant is stale
+            if let Some(dest) = get_dest(instr) {
+                const_values.remove(dest);
+            }
             new_block.push(instr.clone());
         }
     }
@@ -124,33 +121,94 @@ pub fn constant_fold(block: &Vec<Instruction>) -> Vec<Instruction> {
     new_block
 }
 
-fn canonicalize_operands(instr: &Instruction, var2num: &HashMap<String, usize>, canon_var: &HashMap<usize, String>) -> Instruction {
+/// The `Const` that `instr` computes when its operands have the constant
+/// values given by `lookup`, or `None` if it cannot be folded.
+pub fn fold_instr(instr: &Instruction, lookup: impl Fn(&String) -> Option<Literal>) -> Option<Instruction> {
+    if let Some((op, dest, op1, op2)) = instr.as_binary() {
+        let values = fold_binary(op, &lookup(op1)?, &lookup(op2)?)?;
+        let typ = binary_signature(op).1;
+        return Some(Instruction::Const { dest: dest.clone(), typ, values });
+    }
     match instr {
-        Instruction::Add { dest, op1, op2 } => {
-            let canon_op1 = get_canonical(op1, var2num, canon_var).unwrap_or(op1.clone());
-            let canon_op2 = get_canonical(op2, var2num, canon_var).unwrap_or(op2.clone());
-            Instruction::Add {
-                dest: dest.clone(),
-                op1: canon_op1,
-                op2: canon_op2,
-            }
-        }
-        Instruction::Mul { dest, op1, op2 } => {
-            let canon_op1 = get_canonical(op1, var2num, canon_var).unwrap_or(op1.clone());
-            let canon_op2 = get_canonical(op2, var2num, canon_var).unwrap_or(op2.clone());
-            Instruction::Mul {
-                dest: dest.clone(),
-                op1: canon_op1,
-                op2: canon_op2,
-            }
+        Instruction::Not { dest, src } => match lookup(src)? {
+            Literal::Bool(b) => Some(Instruction::Const { dest: dest.clone(), typ: Types::Bool, values: Literal::Bool(!b) }),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Compile-time evaluation of a two operand opcode, matching the interpreter:
+/// integer arithmetic wraps and shift amounts are taken modulo 64. Division or
+/// remainder by zero is left alone so it still traps at run time. Floats are
+/// evaluated with the same IEEE operations the interpreter uses, so NaNs and
+/// signed zeros come out bit for bit; no algebraic identities are applied
+/// (`x * 0.0` is not `0.0`, `x == x` is not `true`).
+pub fn fold_binary(op: BinOp, a: &Literal, b: &Literal) -> Option<Literal> {
+    let ints = || match (a, b) {
+        (Literal::Int(x), Literal::Int(y)) => Some((*x, *y)),
+        _ => None,
+    };
+    let floats = || match (a, b) {
+        (Literal::Float(x), Literal::Float(y)) => Some((*x, *y)),
+        _ => None,
+    };
+    let bools = || match (a, b) {
+        (Literal::Bool(x), Literal::Bool(y)) => Some((*x, *y)),
+        _ => None,
+    };
+    let divisor = |y: i64| (y != 0).then_some(y);
+
+    match op {
+        BinOp::Eq | BinOp::Ne => {
+            let same = match (a, b) {
+                (Literal::Int(x), Literal::Int(y)) => x == y,
+                (Literal::Float(x), Literal::Float(y)) => x == y,
+                (Literal::Bool(x), Literal::Bool(y)) => x == y,
+                _ => return None,
+            };
+            Some(Literal::Bool(same == (op == BinOp::Eq)))
         }
-        Instruction::Eq { dest, op1, op2 } => {
-            let canon_op1 = get_canonical(op1, var2num, canon_var).unwrap_or(op1.clone());
-            let canon_op2 = get_canonical(op2, var2num, canon_var).unwrap_or(op2.clone());
-            Instruction::Eq {
+        BinOp::Add => ints().map(|(x, y)| Literal::Int(x.wrapping_add(y))),
+        BinOp::Mul => ints().map(|(x, y)| Literal::Int(x.wrapping_mul(y))),
+        BinOp::Sub => ints().map(|(x, y)| Literal::Int(x.wrapping_sub(y))),
+        BinOp::Div => ints().and_then(|(x, y)| Some(Literal::Int(x.wrapping_div(divisor(y)?)))),
+        BinOp::Rem => ints().and_then(|(x, y)| Some(Literal::Int(x.wrapping_rem(divisor(y)?)))),
+        BinOp::Shl => ints().map(|(x, y)| Literal::Int(x.wrapping_shl(y as u32))),
+        BinOp::Shr => ints().map(|(x, y)| Literal::Int(x.wrapping_shr(y as u32))),
+        BinOp::BitAnd => ints().map(|(x, y)| Literal::Int(x & y)),
+        BinOp::BitOr => ints().map(|(x, y)| Literal::Int(x | y)),
+        BinOp::BitXor => ints().map(|(x, y)| Literal::Int(x ^ y)),
+        BinOp::Lt => ints().map(|(x, y)| Literal::Bool(x < y)),
+        BinOp::Le => ints().map(|(x, y)| Literal::Bool(x <= y)),
+        BinOp::Gt => ints().map(|(x, y)| Literal::Bool(x > y)),
+        BinOp::Ge => ints().map(|(x, y)| Literal::Bool(x >= y)),
+        BinOp::FAdd => floats().map(|(x, y)| Literal::Float(x + y)),
+        BinOp::FSub => floats().map(|(x, y)| Literal::Float(x - y)),
+        BinOp::FMul => floats().map(|(x, y)| Literal::Float(x * y)),
+        BinOp::FDiv => floats().map(|(x, y)| Literal::Float(x / y)),
+        BinOp::FEq => floats().map(|(x, y)| Literal::Bool(x == y)),
+        BinOp::FLt => floats().map(|(x, y)| Literal::Bool(x < y)),
+        BinOp::FLe => floats().map(|(x, y)| Literal::Bool(x <= y)),
+        BinOp::FGt => floats().map(|(x, y)| Literal::Bool(x > y)),
+        BinOp::FGe => floats().map(|(x, y)| Literal::Bool(x >= y)),
+        BinOp::And => bools().map(|(x, y)| Literal::Bool(x && y)),
+        BinOp::Or => bools().map(|(x, y)| Literal::Bool(x || y)),
+    }
+}
+
+fn canonicalize_operands(instr: &Instruction, var2num: &HashMap<String, usize>, canon_var: &HashMap<usize, String>) -> Instruction {
+    if let Some((op, dest, op1, op2)) = instr.as_binary() {
+        let canon_op1 = get_canonical(op1, var2num, canon_var).unwrap_or(op1.clone());
+        let canon_op2 = get_canonical(op2, var2num, canon_var).unwrap_or(op2.clone());
+        return Instruction::binary(op, dest.clone(), canon_op1, canon_op2);
+    }
+    match instr {
+        Instruction::Not { dest, src } => {
+            let canon_src = get_canonical(src, var2num, canon_var).unwrap_or(src.clone());
+            Instruction::Not {
                 dest: dest.clone(),
-                op1: canon_op1,
-                op2: canon_op2,
+                src: canon_src,
             }
         }
         Instruction::Move { dest, src } => {
@@ -173,11 +231,64 @@ fn canonicalize_operands(instr: &Instruction, var2num: &HashMap<String, usize>,
                 value: canon_value,
             }
         }
+        Instruction::Call { dest, func, args } => Instruction::Call {
+            dest: dest.clone(),
+            func: func.clone(),
+            args: args.iter().map(|arg| get_canonical(arg, var2num, canon_var).unwrap_or(arg.clone())).collect(),
+        },
+        Instruction::Alloc { dest, typ, size } => Instruction::Alloc {
+            dest: dest.clone(),
+            typ: typ.clone(),
+            size: get_canonical(size, var2num, canon_var).unwrap_or(size.clone()),
+        },
+        Instruction::Free { ptr } => Instruction::Free {
+            ptr: get_canonical(ptr, var2num, canon_var).unwrap_or(ptr.clone()),
+        },
+        Instruction::Load { dest, ptr } => Instruction::Load {
+            dest: dest.clone(),
+            ptr: get_canonical(ptr, var2num, canon_var).unwrap_or(ptr.clone()),
+        },
+        Instruction::Store { ptr, value } => Instruction::Store {
+            ptr: get_canonical(ptr, var2num, canon_var).unwrap_or(ptr.clone()),
+            value: get_canonical(value, var2num, canon_var).unwrap_or(value.clone()),
+        },
+        Instruction::PtrAdd { dest, ptr, offset } => Instruction::PtrAdd {
+            dest: dest.clone(),
+            ptr: get_canonical(ptr, var2num, canon_var).unwrap_or(ptr.clone()),
+            offset: get_canonical(offset, var2num, canon_var).unwrap_or(offset.clone()),
+        },
         // Other instructions (Const, Jmp, etc.) have no operands to canonicalize
         other => other.clone(),
     }
 }
 
+// `dest` is about to be overwritten. If it was the variable holding some value
+// number, hand that role to another variable with the same number, otherwise
+// forget the value so nothing gets rewritten to read the clobbered name.
+fn release_canonical(dest: &String, var2num: &mut HashMap<String, usize>, canon_var: &mut HashMap<usize, String>, table: &mut HashMap<ExprKey, usize>) {
+    let Some(old) = var2num.remove(dest) else {
+        return;
+    };
+    if canon_var.get(&old) != Some(dest) {
+        return;
+    }
+
+    let heir = var2num.iter()
+        .filter(|&(_, &num)| num == old)
+        .map(|(var, _)| var)
+        .min()
+        .cloned();
+    match heir {
+        Some(var) => {
+            canon_var.insert(old, var);
+        }
+        None => {
+            canon_var.remove(&old);
+            table.retain(|_, num| *num != old);
+        }
+    }
+}
+
 // helper for canonical 
 fn get_canonical(var: &String, var2num: &HashMap<String, usize>, canon_var: &HashMap<usize, String>) -> Option<String> {
     var2num.get(var).and_then(|&num| canon_var.get(&num).cloned())
@@ -185,13 +296,17 @@ fn get_canonical(var: &String, var2num: &HashMap<String, usize>, canon_var: &Has
 
 // helper 
 pub fn get_dest(instr: &Instruction) -> Option<&String> {
+    if let Some((_, dest, _, _)) = instr.as_binary() {
+        return Some(dest);
+    }
     match instr {
-        Instruction::Id { dest, src } => Some(dest),
+        Instruction::Id { dest, .. } => Some(dest),
         Instruction::Const { dest, .. } => Some(dest),
-        Instruction::Add { dest, .. } => Some(dest),
-        Instruction::Mul { dest, .. } => Some(dest),
-        Instruction::Eq { dest, .. } => Some(dest),
+        Instruction::Not { dest, .. } => Some(dest),
         Instruction::Move { dest, .. } => Some(dest),
+        Instruction::Call { dest: Some((dest, _)), .. } => Some(dest),
+        Instruction::Alloc { dest, .. } | Instruction::Load { dest, .. } | Instruction::PtrAdd { dest, .. } => Some(dest),
+        Instruction::Phi { dest, .. } => Some(dest),
         Instruction::Jmp { .. } => None, // no dest here
         _ => None
     }
@@ -199,13 +314,22 @@ pub fn get_dest(instr: &Instruction) -> Option<&String> {
 
 
 
-fn get_var(instr: &Instruction, var2num: &HashMap<String, usize>, expr_for_num: &HashMap<usize,ExprKey>) -> Option<ExprKey> {
+fn get_var(instr: &Instruction, var2num: &HashMap<String, usize>, expr_for_num: &HashMap<usize,ExprKey>, table: &HashMap<ExprKey, usize>) -> Option<ExprKey> {
+    if let Some((op, _, op1, op2)) = instr.as_binary() {
+        let (&i1, &i2) = (var2num.get(op1)?, var2num.get(op2)?);
+        let mut idxs = [i1, i2];
+        if is_commutative(op) {
+            idxs.sort();
+        }
+        return Some(ExprKey::Binary(op, idxs[0], idxs[1]));
+    }
     match instr {
         Instruction::Const { values, .. } => Some(ExprKey::Const(values.clone())),
 
         Instruction::Id { src, .. } | Instruction::Move { src, .. } => {
             if let Some(&idx) = var2num.get(src) {
-                if let Some(expr_key) = expr_for_num.get(&idx) {
+                // a key dropped from the table (a load after a store) no longer names this value
+                if let Some(expr_key) = expr_for_num.get(&idx).filter(|key| table.get(*key) == Some(&idx)) {
                     Some(expr_key.clone())
                 } else {
                     Some(ExprKey::Id(idx))
@@ -215,50 +339,23 @@ fn get_var(instr: &Instruction, var2num: &HashMap<String, usize>, expr_for_num:
             }
         }
 
-        Instruction::Add { op1, op2, .. } => {
-            if let (Some(&i1), Some(&i2)) = (var2num.get(op1), var2num.get(op2)) {
-                let mut idxs = vec![i1, i2];
-                idxs.sort();
-                Some(ExprKey::Add(idxs[0], idxs[1]))
-            } else {
-                None
-            }
-        }
-
-        Instruction::Mul { op1, op2, .. } => {
-            if let (Some(&i1), Some(&i2)) = (var2num.get(op1), var2num.get(op2)) {
-                let mut idxs = vec![i1, i2];
-                idxs.sort();
-                Some(ExprKey::Mul(idxs[0], idxs[1]))
-            } else {
-                None
-            }
-        }
-
-        Instruction::Eq { op1, op2, .. } => {
-            if let (Some(&i1), Some(&i2)) = (var2num.get(op1), var2num.get(op2)) {
-                Some(ExprKey::Eq(i1, i2))
-            } else {
-                None
-            }
-        }
-
-        Instruction::Print { value } => {
-            var2num.get(value).map(|&idx| ExprKey::Id(idx))
-        }
+        Instruction::Not { src, .. } => var2num.get(src).map(|&idx| ExprKey::Not(idx)),
+        Instruction::Load { ptr, .. } => var2num.get(ptr).map(|&idx| ExprKey::Load(idx)),
+        Instruction::PtrAdd { ptr, offset, .. } => Some(ExprKey::PtrAdd(*var2num.get(ptr)?, *var2num.get(offset)?)),
 
         _ => None,
     }
 }
 
-fn get_used_var(instr : &Instruction) -> Vec<String> {
+pub fn get_used_var(instr : &Instruction) -> Vec<String> {
+    if let Some((_, _, op1, op2)) = instr.as_binary() {
+        return vec![op1.clone(), op2.clone()];
+    }
     match instr {
-        Instruction::Add { dest, op1, op2 } => vec![op1.clone(), op2.clone()],
-        Instruction::Const { dest, typ, values } => vec![],
-        Instruction::Eq { dest, op1, op2 } => vec![op1.clone(), op2.clone()],
-        Instruction::Mul { dest, op1, op2 } => vec![op1.clone(), op2.clone()],
-        Instruction::Move { dest, src } => vec![src.clone()],
-        Instruction::Id { dest, src } => vec![src.clone()],
+        Instruction::Const { .. } => vec![],
+        Instruction::Not { src, .. } => vec![src.clone()],
+        Instruction::Move { src, .. } => vec![src.clone()],
+        Instruction::Id { src, .. } => vec![src.clone()],
         Instruction::Print { value } => vec![value.clone()],
         Instruction::Ret { value } => {
             if let Some(v) = value {
@@ -268,11 +365,40 @@ fn get_used_var(instr : &Instruction) -> Vec<String> {
             }
         }
         Instruction::Br { cond, .. } => vec![cond.clone()],
+        Instruction::Call { args, .. } => args.clone(),
+        Instruction::Alloc { size, .. } => vec![size.clone()],
+        Instruction::Free { ptr } | Instruction::Load { ptr, .. } => vec![ptr.clone()],
+        Instruction::Store { ptr, value } => vec![ptr.clone(), value.clone()],
+        Instruction::PtrAdd { ptr, offset, .. } => vec![ptr.clone(), offset.clone()],
+        // read at the end of each predecessor, but listed with the phi
+        Instruction::Phi { args, .. } => args.iter().map(|(_, var)| var.clone()).collect(),
         _ => vec![] 
     }
 }
 
-fn dead_elimination_unused(block: &Vec<Instruction>) -> Vec<Instruction> {
+/// `may_trap` for every instruction of `block`, knowing the constants
+/// defined earlier in the block. The DCE passes share this, so they agree on
+/// what must stay.
+pub fn may_trap_in_block(block: &[Instruction]) -> Vec<bool> {
+    let mut consts: HashMap<&String, Literal> = HashMap::new();
+    let mut traps = Vec::with_capacity(block.len());
+    for instr in block {
+        traps.push(instr.may_trap(|var| consts.get(var).cloned()));
+        match instr {
+            Instruction::Const { dest, values, .. } => {
+                consts.insert(dest, values.clone());
+            }
+            _ => {
+                if let Some(dest) = get_dest(instr) {
+                    consts.remove(dest);
+                }
+            }
+        }
+    }
+    traps
+}
+
+pub fn dead_elimination_unused(block: &[Instruction]) -> Vec<Instruction> {
     let mut used_vars: HashSet<String> = HashSet::new();
     
     // Collect all variables that are USED
@@ -284,9 +410,12 @@ fn dead_elimination_unused(block: &Vec<Instruction>) -> Vec<Instruction> {
     }
     
     // Keep only instructions whose destination is used
+    let traps = may_trap_in_block(block);
     let mut new_block = Vec::new();
-    for instr in block {
-        if let Some(dest) = get_dest(instr) {
+    for (instr, traps) in block.iter().zip(traps) {
+        if instr.has_side_effects() || traps {
+            new_block.push(instr.clone());
+        } else if let Some(dest) = get_dest(instr) {
             if used_vars.contains(dest) {
                 new_block.push(instr.clone());
             }
@@ -300,15 +429,21 @@ fn dead_elimination_unused(block: &Vec<Instruction>) -> Vec<Instruction> {
 
 
 // put the iterative version of above func 
-fn dce_combined(block: &Vec<Instruction>) -> Vec<Instruction> {
-    let mut current_block = block.clone();
-    
-    let pass1 = dead_elimination_unused(&current_block);
-    let pass2 = dead_elimination_redefined(&pass1);
+pub fn dce_combined(block: &[Instruction]) -> Vec<Instruction> {
+    let mut current_block = block.to_vec();
+
+    loop {
+        let pass1 = dead_elimination_unused(&current_block);
+        let pass2 = dead_elimination_redefined(&pass1);
+
+        if pass2 == current_block {
+            break;
+        }
+        current_block = pass2;
+    }
     current_block
-    
 }
-fn dead_elimination_redefined(block: &Vec<Instruction>) -> Vec<Instruction> {
+pub fn dead_elimination_redefined(block: &[Instruction]) -> Vec<Instruction> {
     let mut last_def: HashMap<String, usize> = HashMap::new();  // var -> index of last def
     let mut used_instrs: HashSet<usize> = HashSet::new();  // indices of used definitions
 
@@ -334,9 +469,10 @@ fn dead_elimination_redefined(block: &Vec<Instruction>) -> Vec<Instruction> {
         used_instrs.insert(idx);
     }
 
+    let traps = may_trap_in_block(block);
     let mut new_block = Vec::new();
     for (i, instr) in block.iter().enumerate() {
-        if used_instrs.contains(&i) || get_dest(instr).is_none() {
+        if used_instrs.contains(&i) || get_dest(instr).is_none() || instr.has_side_effects() || traps[i] {
             new_block.push(instr.clone());
         }
     }
@@ -344,24 +480,10 @@ fn dead_elimination_redefined(block: &Vec<Instruction>) -> Vec<Instruction> {
     new_block
 }
 
-fn final_local_opt(block: &Vec<Instruction>) -> Vec<Instruction>{
-    let mut current_block = lvn(&block);
+pub fn final_local_opt(block: &[Instruction]) -> Vec<Instruction>{
+    let mut current_block = lvn(block);
     current_block = constant_fold(&current_block);
-
-    loop {
-
-        let before = format!("{:?}", current_block);
-
-        current_block = dead_elimination_unused(&current_block);
-        current_block = dead_elimination_redefined(&current_block);
-
-        let after = format!("{:?}", current_block);
-
-        if before == after {
-            break;
-        }
-    }
-    current_block
+    dce_combined(&current_block)
 }
 
 
@@ -507,4 +629,158 @@ fn test_dead_elimination_iterative_comprehensive() {
     // Keeps: a=5, b=6, c=a+b, a=7, d=c*a, print d
     //assert_eq!(final_block.len(), 6);
 }
+
+    #[test]
+    fn test_dce_keeps_traps() {
+        let block = vec![
+            Instruction::Const { dest: "a".into(), typ: Types::Int, values: Literal::Int(1) },
+            Instruction::Const { dest: "z".into(), typ: Types::Int, values: Literal::Int(0) },
+            Instruction::Div { dest: "q".into(), op1: "a".into(), op2: "z".into() },
+            Instruction::Rem { dest: "r".into(), op1: "a".into(), op2: "a".into() },
+            Instruction::Load { dest: "x".into(), ptr: "p".into() },
+            Instruction::Load { dest: "x".into(), ptr: "p".into() },
+        ];
+        // the zero divisor and both loads stay, `rem` by 1 cannot fail
+        assert_eq!(may_trap_in_block(&block), vec![false, false, true, false, true, true]);
+        let expected = vec![block[0].clone(), block[1].clone(), block[2].clone(), block[4].clone(), block[5].clone()];
+        assert_eq!(dce_combined(&block), expected);
+    }
+
+    #[test]
+    fn test_commutative_operands() {
+        let block = vec![
+            Instruction::Const { dest: "a".into(), typ: Types::Int, values: Literal::Int(4) },
+            Instruction::Const { dest: "b".into(), typ: Types::Int, values: Literal::Int(2) },
+            Instruction::BitXor { dest: "x1".into(), op1: "a".into(), op2: "b".into() },
+            Instruction::BitXor { dest: "x2".into(), op1: "b".into(), op2: "a".into() }, // CSE -> Id x1
+            Instruction::Sub { dest: "s1".into(), op1: "a".into(), op2: "b".into() },
+            Instruction::Sub { dest: "s2".into(), op1: "b".into(), op2: "a".into() }, // not the same value
+            Instruction::Lt { dest: "l1".into(), op1: "a".into(), op2: "b".into() },
+            Instruction::Lt { dest: "l2".into(), op1: "b".into(), op2: "a".into() }, // not the same value
+            Instruction::Const { dest: "x".into(), typ: Types::Float, values: Literal::Float(f64::NAN) },
+            Instruction::Const { dest: "y".into(), typ: Types::Float, values: Literal::Float(1.0) },
+            Instruction::FAdd { dest: "f1".into(), op1: "x".into(), op2: "y".into() },
+            Instruction::FAdd { dest: "f2".into(), op1: "y".into(), op2: "x".into() }, // may be another NaN
+            Instruction::FEq { dest: "e1".into(), op1: "x".into(), op2: "y".into() },
+            Instruction::FEq { dest: "e2".into(), op1: "y".into(), op2: "x".into() }, // CSE -> Id e1
+        ];
+
+        let out = lvn(&block);
+        assert_eq!(out[3], Instruction::Id { dest: "x2".into(), src: "x1".into() });
+        assert_eq!(out[5], block[5]);
+        assert_eq!(out[7], block[7]);
+        assert_eq!(out[11], block[11]);
+        assert_eq!(out[13], Instruction::Id { dest: "e2".into(), src: "e1".into() });
+    }
+
+    #[test]
+    fn test_fold_full_instruction_set() {
+        let block = vec![
+            Instruction::Const { dest: "a".into(), typ: Types::Int, values: Literal::Int(17) },
+            Instruction::Const { dest: "b".into(), typ: Types::Int, values: Literal::Int(5) },
+            Instruction::Const { dest: "z".into(), typ: Types::Int, values: Literal::Int(0) },
+            Instruction::Rem { dest: "r".into(), op1: "a".into(), op2: "b".into() },
+            Instruction::Shl { dest: "s".into(), op1: "r".into(), op2: "b".into() },
+            Instruction::Ge { dest: "g".into(), op1: "s".into(), op2: "a".into() },
+            Instruction::Not { dest: "n".into(), src: "g".into() },
+            Instruction::Or { dest: "o".into(), op1: "n".into(), op2: "g".into() },
+            Instruction::Div { dest: "d".into(), op1: "a".into(), op2: "z".into() }, // must still trap
+        ];
+
+        let out = constant_fold(&block);
+        let int = |dest: &str, v| Instruction::Const { dest: dest.into(), typ: Types::Int, values: Literal::Int(v) };
+        let bool = |dest: &str, v| Instruction::Const { dest: dest.into(), typ: Types::Bool, values: Literal::Bool(v) };
+        assert_eq!(out[3..8], [int("r", 2), int("s", 64), bool("g", true), bool("n", false), bool("o", true)]);
+        assert_eq!(out[8], block[8]);
+    }
+
+    #[test]
+    fn test_float_constants() {
+        let float = |dest: &str, v| Instruction::Const { dest: dest.into(), typ: Types::Float, values: Literal::Float(v) };
+        let bool = |dest: &str, v| Instruction::Const { dest: dest.into(), typ: Types::Bool, values: Literal::Bool(v) };
+        let block = vec![
+            float("pz", 0.0),
+            float("nz", -0.0),
+            float("nan", f64::NAN),
+            Instruction::FMul { dest: "a".into(), op1: "nz".into(), op2: "pz".into() },
+            Instruction::FEq { dest: "b".into(), op1: "nan".into(), op2: "nan".into() },
+            Instruction::FEq { dest: "c".into(), op1: "pz".into(), op2: "nz".into() },
+            float("nz2", -0.0),
+            Instruction::FAdd { dest: "s1".into(), op1: "nz".into(), op2: "nan".into() },
+            Instruction::FAdd { dest: "s2".into(), op1: "nz2".into(), op2: "nan".into() },
+            Instruction::FAdd { dest: "s3".into(), op1: "nan".into(), op2: "pz".into() },
+        ];
+
+        // -0.0 and 0.0 are different values, the two -0.0 are the same one
+        let numbered = lvn(&block);
+        assert_eq!(numbered[8], Instruction::Id { dest: "s2".into(), src: "s1".into() });
+        assert_eq!(numbered[9], block[9]);
+
+        let folded = constant_fold(&block);
+        assert_eq!(folded[3], float("a", -0.0));
+        assert!(matches!(folded[3], Instruction::Const { values: Literal::Float(v), .. } if v.is_sign_negative()));
+        assert_eq!(folded[4], bool("b", false));
+        assert_eq!(folded[5], bool("c", true));
+    }
+
+    #[test]
+    fn test_calls_are_kept() {
+        let call = |dest: &str, arg: &str| Instruction::Call {
+            dest: Some((dest.into(), Types::Int)),
+            func: "f".into(),
+            args: vec![arg.into()],
+        };
+        let block = vec![
+            Instruction::Const { dest: "a".into(), typ: Types::Int, values: Literal::Int(1) },
+            Instruction::Id { dest: "b".into(), src: "a".into() },
+            call("x", "b"),
+            call("y", "b"), // same call again, not a common subexpression
+            call("x", "a"), // overwrites an unused `x`, still runs
+            Instruction::Call { dest: None, func: "g".into(), args: vec![] },
+        ];
+
+        let numbered = lvn(&block);
+        assert_eq!(numbered[2], call("x", "a"));
+        assert_eq!(numbered[3], call("y", "a"));
+        assert_eq!(dead_elimination_unused(&block), block);
+        assert_eq!(dead_elimination_redefined(&block), block);
+        assert_eq!(get_used_var(&block[2]), vec!["b".to_string()]);
+        assert_eq!(get_dest(&block[5]), None);
+    }
+
+    #[test]
+    fn test_store_invalidates_loads() {
+        let block = crate::text::parse_instrs("
+  n: int = const 4
+  p: ptr<int> = alloc n
+  q = ptradd p n
+  r = ptradd p n
+  a = load q
+  b = load r
+  c = id a
+  store p n
+  d = load q
+  e = id c
+  f = load r
+  free p
+  store p n
+").unwrap();
+
+        let out = lvn(&block);
+        assert_eq!(out[3], Instruction::Id { dest: "r".into(), src: "q".into() });
+        assert_eq!(out[5], Instruction::Id { dest: "b".into(), src: "a".into() });
+        // after the store `q` has to be read again, the copy of the old value stays a copy
+        assert_eq!(out[8], Instruction::Load { dest: "d".into(), ptr: "q".into() });
+        assert_eq!(out[9], Instruction::Id { dest: "e".into(), src: "a".into() });
+        assert_eq!(out[10], Instruction::Id { dest: "f".into(), src: "d".into() });
+
+        // stores, frees and allocations are never dead, nor loads (this one
+        // is out of bounds); the unused copies are
+        let dce = dce_combined(&block);
+        assert!(dce.contains(&block[1]));
+        assert!(dce.contains(&block[7]));
+        assert!(dce.contains(&block[11]));
+        assert!(dce.contains(&block[4]));
+        assert!(!dce.contains(&block[6]) && !dce.contains(&block[9]));
+    }
 }
@@ -1,66 +1,51 @@
 pub mod types;
+pub mod text;
+pub mod bril;
+pub mod interp;
+pub mod difftest;
+pub mod generate;
 pub mod cfg;
 pub mod lvn;
 pub mod dataflow;
 pub mod global;
+pub mod constprop;
+pub mod dot;
+pub mod verify;
+pub mod typecheck;
+pub mod ssa;
 
-use types::*;
 use cfg::*;
-use lvn::*;
+use text::*;
 
 
 fn main() {
-    let f = Function {
-        name: "Main".to_string(),
-        instr: vec![
-            // ---- block0 ----
-            Instruction::Const {
-                dest: "v0".to_string(),
-                typ: Types::Bool,
-                values: Literal::Bool(true),
-            },
-            Instruction::Br {
-                cond: "v0".to_string(),
-                then_label: "then_blk".to_string(),
-                else_label: "else_blk".to_string(),
-            },
-    
-            // ---- then_blk (block1) ----
-            Instruction::Label{label: "then_blk".to_string()},
-            Instruction::Const {
-                dest: "v1".to_string(),
-                typ: Types::Int,
-                values: Literal::Int(10),
-            },
-            Instruction::Jmp {
-                label: "merge_blk".to_string(),
-            },
-    
-            // ---- else_blk (block2) ----
-            Instruction::Label{label:"else_blk".to_string()},
-            Instruction::Const {
-                dest: "v2".to_string(),
-                typ: Types::Int,
-                values: Literal::Int(20),
-            },
-            Instruction::Jmp {
-                label: "merge_blk".to_string(),
-            },
-    
-            // ---- merge_blk (block3) ----
-            Instruction::Label{label: "merge_blk".to_string()},
-            Instruction::Ret {
-                value: Some("v1".to_string()),
-            },
-        ],
-    };
-
-    let blocks = build_blocks(&f);
-    //let cFG = create_cfg(&blocks);
-
-    //println!("CFG IS {:?}", cFG);
-
-
-    
+    let f = parse_function("
+@main {
+  v0: bool = const true
+  br v0 .then_blk .else_blk
+.then_blk:
+  v1: int = const 10
+  jmp .merge_blk
+.else_blk:
+  v2: int = const 20
+  jmp .merge_blk
+.merge_blk:
+  ret v1
+}
+").expect("built-in example should parse");
+
+    let cfg = Cfg::from_function(&f);
+
+    // `cargo run -- --dot | dot -Tsvg -o cfg.svg`
+    if std::env::args().any(|arg| arg == "--dot") {
+        let idom = global::Dominators::new(&cfg).tree();
+        print!("{}", dot::Dot::new(&cfg).dominator_tree(&idom).render());
+        return;
+    }
+
+    print!("{}", f);
+    for node in cfg.reverse_postorder() {
+        let succs: Vec<&str> = cfg.successors(node).map(|s| cfg[s].name.as_str()).collect();
+        println!("{} -> {:?}", cfg[node].name, succs);
+    }
 }
-
@@ -0,0 +1,896 @@
+use std::collections::{HashMap, HashSet};
+
+use petgraph::graph::NodeIndex;
+
+use crate::cfg::*;
+use crate::dataflow::{live_variables, solve, AbstractDataflow, GDirection};
+use crate::global::*;
+use crate::lvn::{get_dest, get_used_var};
+use crate::types::*;
+
+// Construction of SSA form (Cytron et al.): phis go at the iterated dominance
+// frontier of the blocks defining a variable, then a walk down the dominator
+// tree gives every definition a name of its own and every use the name of the
+// definition that reaches it. `from_ssa` goes back to plain copies.
+
+/// How many phis `to_ssa` places.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SsaFlavor {
+    /// At every join a definition reaches, whether or not the value is used.
+    Minimal,
+    /// Only for variables read in some block before that block defines them,
+    /// the others can't be live across a block boundary.
+    SemiPruned,
+    /// Only where the variable is live on entry to the block.
+    Pruned,
+}
+
+/// `f` in SSA form. Definition `k` of `x` is renamed `x.k`, arguments keep
+/// their names and a use no definition reaches keeps the original name (it
+/// is undefined there, as before). Every block is given a label so phis can
+/// name their predecessors, a new entry block is added when the old one is
+/// a jump target, and blocks the entry can't reach are dropped. Phis already
+/// in `f` are renamed like any other definition, their arguments must name
+/// the predecessors by label (as in the output of `to_ssa`).
+pub fn to_ssa(f: &Function, flavor: SsaFlavor) -> Function {
+    let mut cfg = Cfg::from_function(f);
+    cfg.remove_unreachable_blocks();
+    // phis in the entry would have nothing to select on the way in
+    if cfg.predecessors(cfg.entry).next().is_some() {
+        let name = cfg.fresh_name("entry");
+        let entry = cfg.add_block(BasicBlock { name, instructions: vec![] });
+        cfg.add_edge(entry, cfg.entry);
+        cfg.entry = entry;
+    }
+    label_blocks(&mut cfg);
+
+    let doms = Dominators::new(&cfg);
+    let phis = place_phis(&cfg, &doms, flavor);
+    for (&node, vars) in &phis {
+        let at = usize::from(matches!(cfg[node].instructions.first(), Some(Instruction::Label { .. })));
+        let new = vars.iter().map(|var| Instruction::Phi { dest: var.clone(), args: vec![] });
+        cfg[node].instructions.splice(at..at, new);
+    }
+
+    Renamer::new(&cfg, &doms, phis).run(&mut cfg);
+    cfg.to_function()
+}
+
+// every block but the exit starts with a label holding its name, a block
+// with a duplicated label is never jumped to, so it can take its own name
+fn label_blocks(cfg: &mut Cfg) {
+    for node in cfg.node_indices().collect::<Vec<_>>() {
+        if node == cfg.exit {
+            continue;
+        }
+        let label = Instruction::Label { label: cfg[node].name.clone() };
+        let block = &mut cfg[node];
+        match block.instructions.first_mut() {
+            Some(first @ Instruction::Label { .. }) => *first = label,
+            _ => block.instructions.insert(0, label),
+        }
+    }
+}
+
+// the variables (in a fixed order) that get a phi in each block
+fn place_phis(
+    cfg: &Cfg,
+    doms: &Dominators,
+    flavor: SsaFlavor,
+) -> HashMap<NodeIndex, Vec<String>> {
+    let df = doms.frontiers(cfg);
+
+    let mut def_blocks: HashMap<&String, Vec<NodeIndex>> = HashMap::new();
+    for arg in &cfg.args {
+        def_blocks.entry(&arg.name).or_default().push(cfg.entry);
+    }
+    // read before being written in some block
+    let mut upward_exposed: HashSet<String> = HashSet::new();
+    for node in cfg.node_indices() {
+        let mut defined = HashSet::new();
+        for instr in &cfg[node].instructions {
+            upward_exposed.extend(get_used_var(instr).into_iter().filter(|var| !defined.contains(var)));
+            if let Some(dest) = get_dest(instr) {
+                def_blocks.entry(dest).or_default().push(node);
+                defined.insert(dest.clone());
+            }
+        }
+    }
+    let live = (flavor == SsaFlavor::Pruned).then(|| live_variables(cfg));
+
+    let mut vars: Vec<&String> = def_blocks.keys().copied().collect();
+    vars.sort();
+    let mut phis: HashMap<NodeIndex, Vec<String>> = HashMap::new();
+    for var in vars {
+        if flavor == SsaFlavor::SemiPruned && !upward_exposed.contains(var) {
+            continue;
+        }
+        let mut has_phi = HashSet::new();
+        let mut worklist = def_blocks[var].clone();
+        while let Some(node) = worklist.pop() {
+            for &join in &df[&node] {
+                if join == cfg.exit || has_phi.contains(&join) {
+                    continue;
+                }
+                if let Some(live) = &live
+                    && !live.in_sets[&join].contains(var)
+                {
+                    continue;
+                }
+                has_phi.insert(join);
+                phis.entry(join).or_default().push(var.clone());
+                // the phi is a new definition of `var`
+                worklist.push(join);
+            }
+        }
+    }
+    phis
+}
+
+struct Renamer {
+    // the original variable each phi placed at the top of a block is for,
+    // phis `f` already had come after them
+    phis: HashMap<NodeIndex, Vec<String>>,
+    children: HashMap<NodeIndex, Vec<NodeIndex>>,
+    stacks: HashMap<String, Vec<String>>,
+    counters: HashMap<String, usize>,
+    taken: HashSet<String>,
+}
+
+impl Renamer {
+    fn new(cfg: &Cfg, doms: &Dominators, phis: HashMap<NodeIndex, Vec<String>>) -> Renamer {
+        // children in block order, so names follow the order of the code
+        let children = cfg
+            .node_indices()
+            .map(|node| {
+                let mut kids = doms.children(node).to_vec();
+                kids.sort();
+                (node, kids)
+            })
+            .collect();
+
+        let mut taken: HashSet<String> = cfg.args.iter().map(|a| a.name.clone()).collect();
+        for node in cfg.node_indices() {
+            for instr in &cfg[node].instructions {
+                taken.extend(get_used_var(instr));
+                taken.extend(get_dest(instr).cloned());
+            }
+        }
+        let stacks = cfg.args.iter().map(|a| (a.name.clone(), vec![a.name.clone()])).collect();
+        Renamer { phis, children, stacks, counters: HashMap::new(), taken }
+    }
+
+    fn fresh(&mut self, var: &str) -> String {
+        let counter = self.counters.entry(var.to_string()).or_insert(0);
+        loop {
+            *counter += 1;
+            let name = format!("{}.{}", var, counter);
+            if self.taken.insert(name.clone()) {
+                return name;
+            }
+        }
+    }
+
+    fn current(&self, var: &String) -> String {
+        self.stacks.get(var).and_then(|stack| stack.last()).unwrap_or(var).clone()
+    }
+
+    // down the dominator tree without recursion, deep trees are common in
+    // generated code
+    fn run(mut self, cfg: &mut Cfg) {
+        enum Visit {
+            Enter(NodeIndex),
+            Leave(Vec<String>),
+        }
+        let mut stack = vec![Visit::Enter(cfg.entry)];
+        while let Some(visit) = stack.pop() {
+            match visit {
+                Visit::Enter(node) => {
+                    let pushed = self.rename_block(cfg, node);
+                    stack.push(Visit::Leave(pushed));
+                    for &child in self.children.get(&node).into_iter().flatten().rev() {
+                        stack.push(Visit::Enter(child));
+                    }
+                }
+                Visit::Leave(pushed) => {
+                    for var in pushed {
+                        if let Some(stack) = self.stacks.get_mut(&var) {
+                            stack.pop();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // renames the block and fills its successors' phis, returning the
+    // variables that got a new name pushed
+    fn rename_block(&mut self, cfg: &mut Cfg, node: NodeIndex) -> Vec<String> {
+        let mut pushed = Vec::new();
+        let mut instructions = std::mem::take(&mut cfg[node].instructions);
+        let phi_vars = self.phis.get(&node).cloned().unwrap_or_default();
+        let mut phi_vars = phi_vars.iter();
+
+        for instr in instructions.iter_mut() {
+            if let Instruction::Phi { dest, .. } = instr {
+                // the arguments are named by the predecessors
+                let var = phi_vars.next().unwrap_or(dest).clone();
+                *dest = self.fresh(&var);
+                self.stacks.entry(var.clone()).or_default().push(dest.clone());
+                pushed.push(var);
+                continue;
+            }
+            let renamed: HashMap<String, String> =
+                get_used_var(instr).into_iter().map(|var| (var.clone(), self.current(&var))).collect();
+            rename_uses(instr, &renamed);
+            if let Some(var) = get_dest(instr).cloned() {
+                let name = self.fresh(&var);
+                set_dest(instr, name.clone());
+                self.stacks.entry(var.clone()).or_default().push(name);
+                pushed.push(var);
+            }
+        }
+        cfg[node].instructions = instructions;
+
+        let label = cfg[node].name.clone();
+        let mut succs: Vec<NodeIndex> = cfg.successors(node).collect();
+        succs.sort();
+        succs.dedup();
+        for succ in succs {
+            let vars = self.phis.get(&succ).map_or(&[][..], |vars| &vars[..]);
+            let mut values = vars.iter().map(|var| self.current(var));
+            let phis = cfg[succ].instructions.iter_mut().filter(|i| matches!(i, Instruction::Phi { .. }));
+            for phi in phis {
+                let Instruction::Phi { args, .. } = phi else { unreachable!() };
+                match values.next() {
+                    Some(value) => args.push((label.clone(), value)),
+                    None => {
+                        for (_, var) in args.iter_mut().filter(|(from, _)| *from == label) {
+                            *var = self.current(var);
+                        }
+                    }
+                }
+            }
+        }
+        pushed
+    }
+}
+
+/// Back from SSA form to copies: every phi becomes an `id` at the end of
+/// each predecessor, all the copies for one edge acting as a single parallel
+/// copy (see `sequentialize`). Edges out of a branch into a block with phis
+/// are split first, so the copies run on that edge only (the lost copy
+/// problem).
+///
+/// A phi argument that may be undefined on its edge (`to_ssa` keeps the
+/// original name where no definition reaches) would make its copy fail where
+/// the phi just left its destination undefined, so that copy is left out, and
+/// so are the copies reading a destination it may then leave undefined. A
+/// variable defined on only some of the paths into a copy counts as undefined.
+pub fn from_ssa(f: &Function) -> Function {
+    let mut cfg = Cfg::from_function(f);
+    split_phi_edges(&mut cfg);
+
+    let mut taken: HashSet<String> = f.args.iter().map(|a| a.name.clone()).collect();
+    for instr in &f.instr {
+        taken.extend(get_used_var(instr));
+        taken.extend(get_dest(instr).cloned());
+    }
+
+    let mut copies: HashMap<NodeIndex, Vec<(String, String)>> = HashMap::new();
+    for node in cfg.node_indices() {
+        for instr in &cfg[node].instructions {
+            let Instruction::Phi { dest, args } = instr else {
+                continue;
+            };
+            for (label, src) in args {
+                if let Some(pred) = cfg.block(label) {
+                    copies.entry(pred).or_default().push((dest.clone(), src.clone()));
+                }
+            }
+        }
+    }
+    for node in cfg.node_indices().collect::<Vec<_>>() {
+        cfg[node].instructions.retain(|i| !matches!(i, Instruction::Phi { .. }));
+    }
+    drop_undefined_copies(&cfg, &taken, &mut copies);
+
+    for node in cfg.node_indices().collect::<Vec<_>>() {
+        let Some(copies) = copies.remove(&node) else {
+            continue;
+        };
+        let sequence = sequentialize(copies, |var| fresh_var(&mut taken, var));
+        insert_copies(&mut cfg[node], sequence);
+    }
+    cfg.to_function()
+}
+
+// leaves out the copies whose source may be undefined at the end of their
+// block, found with the copies in place (sequentialized, so the analysis
+// reads them in order)
+fn drop_undefined_copies(cfg: &Cfg, taken: &HashSet<String>, copies: &mut HashMap<NodeIndex, Vec<(String, String)>>) {
+    let mut with_copies = cfg.clone();
+    let mut taken = taken.clone();
+    for (&node, copies) in copies.iter() {
+        let sequence = sequentialize(copies.clone(), |var| fresh_var(&mut taken, var));
+        insert_copies(&mut with_copies[node], sequence);
+    }
+    let undefined = solve::<MaybeUndefined>(&with_copies);
+    for (&node, copies) in copies.iter_mut() {
+        let at_end = undefined.before(&with_copies, node, copy_position(&cfg[node].instructions));
+        copies.retain(|(_, src)| !at_end.contains(src));
+    }
+}
+
+// Variables that may be undefined: all but the arguments on the way in. An
+// `id` of one leaves its destination as it was, as will the copy
+// `drop_undefined_copies` leaves out for it.
+struct MaybeUndefined;
+
+impl AbstractDataflow for MaybeUndefined {
+    type Domain = String;
+
+    fn direction() -> GDirection {
+        GDirection::Forward
+    }
+
+    fn bottom() -> HashSet<String> {
+        HashSet::new()
+    }
+
+    fn boundary(cfg: &Cfg) -> HashSet<String> {
+        let mut vars = HashSet::new();
+        for node in cfg.node_indices() {
+            for instr in &cfg[node].instructions {
+                vars.extend(get_used_var(instr));
+                vars.extend(get_dest(instr).cloned());
+            }
+        }
+        for arg in &cfg.args {
+            vars.remove(&arg.name);
+        }
+        vars
+    }
+
+    fn merge(in_sets: Vec<HashSet<String>>) -> HashSet<String> {
+        in_sets.into_iter().flatten().collect()
+    }
+
+    fn transfer(block: &BasicBlock, _offset: usize, mut undefined: HashSet<String>) -> HashSet<String> {
+        for instr in &block.instructions {
+            match instr {
+                Instruction::Id { src, .. } if undefined.contains(src) => {}
+                _ => {
+                    if let Some(dest) = get_dest(instr) {
+                        undefined.remove(dest);
+                    }
+                }
+            }
+        }
+        undefined
+    }
+}
+
+// copies go before the jump or branch ending a block
+fn copy_position(block: &[Instruction]) -> usize {
+    match block.last() {
+        Some(last) if is_terminator(last) => block.len() - 1,
+        _ => block.len(),
+    }
+}
+
+fn insert_copies(block: &mut BasicBlock, sequence: Vec<Instruction>) {
+    let at = copy_position(&block.instructions);
+    block.instructions.splice(at..at, sequence);
+}
+
+// puts a block on every edge from a branch to a block with phis, the phi
+// arguments follow it
+fn split_phi_edges(cfg: &mut Cfg) {
+    let has_phis = |cfg: &Cfg, node: NodeIndex| cfg[node].instructions.iter().any(|i| matches!(i, Instruction::Phi { .. }));
+    for pred in cfg.node_indices().collect::<Vec<_>>() {
+        if !matches!(cfg[pred].instructions.last(), Some(Instruction::Br { .. })) {
+            continue;
+        }
+        let mut succs: Vec<NodeIndex> = cfg.successors(pred).filter(|&s| has_phis(cfg, s)).collect();
+        succs.sort();
+        succs.dedup();
+        for succ in succs {
+            let (from, to) = (cfg[pred].name.clone(), cfg[succ].name.clone());
+            let name = cfg.fresh_name(&format!("{}.{}", from, to));
+            let instructions = vec![Instruction::Label { label: name.clone() }, Instruction::Jmp { label: to.clone() }];
+            let split = cfg.add_block(BasicBlock { name: name.clone(), instructions });
+            cfg.remove_edge(pred, succ);
+            cfg.add_edge(pred, split);
+            cfg.add_edge(split, succ);
+
+            if let Some(Instruction::Br { then_label, else_label, .. }) = cfg[pred].instructions.last_mut() {
+                for label in [then_label, else_label] {
+                    if *label == to {
+                        *label = name.clone();
+                    }
+                }
+            }
+            for instr in cfg[succ].instructions.iter_mut() {
+                if let Instruction::Phi { args, .. } = instr {
+                    for (label, _) in args.iter_mut().filter(|(label, _)| *label == from) {
+                        *label = name.clone();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The copies `dest = id src` of `copies`, performed as if all at once, as a
+/// sequence. A copy is emitted once nothing else still reads its
+/// destination; what is left are cycles (a swap for one), each broken by
+/// saving one variable in a temporary named by `temp`. Destinations must be
+/// distinct.
+pub fn sequentialize(copies: Vec<(String, String)>, mut temp: impl FnMut(&str) -> String) -> Vec<Instruction> {
+    let mut pending: Vec<(String, String)> = copies.into_iter().filter(|(dest, src)| dest != src).collect();
+    let mut sequence = Vec::new();
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|(dest, _)| !pending.iter().any(|(_, src)| src == dest));
+        match ready {
+            Some(i) => {
+                let (dest, src) = pending.remove(i);
+                sequence.push(Instruction::Id { dest, src });
+            }
+            None => {
+                // every destination is still read: save the first one and
+                // read the copy instead
+                let saved = pending[0].0.clone();
+                let tmp = temp(&saved);
+                sequence.push(Instruction::Id { dest: tmp.clone(), src: saved.clone() });
+                for (_, src) in pending.iter_mut().filter(|(_, src)| *src == saved) {
+                    *src = tmp.clone();
+                }
+            }
+        }
+    }
+    sequence
+}
+
+// `base.tmp`, or with a number when that is taken
+fn fresh_var(taken: &mut HashSet<String>, base: &str) -> String {
+    let mut name = format!("{}.tmp", base);
+    let mut i = 0;
+    while !taken.insert(name.clone()) {
+        i += 1;
+        name = format!("{}.tmp{}", base, i);
+    }
+    name
+}
+
+/// Rewrites the variables `instr` reads through `renamed`, those missing
+/// from it are kept.
+pub fn rename_uses(instr: &mut Instruction, renamed: &HashMap<String, String>) {
+    let rename = |var: &mut String| {
+        if let Some(new) = renamed.get(var) {
+            *var = new.clone();
+        }
+    };
+    if let Some((op, dest, op1, op2)) = instr.as_binary() {
+        let (mut op1, mut op2) = (op1.clone(), op2.clone());
+        rename(&mut op1);
+        rename(&mut op2);
+        *instr = Instruction::binary(op, dest.clone(), op1, op2);
+        return;
+    }
+    match instr {
+        Instruction::Not { src, .. } | Instruction::Id { src, .. } | Instruction::Move { src, .. } => rename(src),
+        Instruction::Br { cond, .. } => rename(cond),
+        Instruction::Print { value } | Instruction::Ret { value: Some(value) } => rename(value),
+        Instruction::Call { args, .. } => args.iter_mut().for_each(rename),
+        Instruction::Alloc { size, .. } => rename(size),
+        Instruction::Free { ptr } | Instruction::Load { ptr, .. } => rename(ptr),
+        Instruction::Store { ptr, value } => {
+            rename(ptr);
+            rename(value);
+        }
+        Instruction::PtrAdd { ptr, offset, .. } => {
+            rename(ptr);
+            rename(offset);
+        }
+        Instruction::Phi { args, .. } => args.iter_mut().for_each(|(_, var)| rename(var)),
+        _ => {}
+    }
+}
+
+/// Gives the instruction defining a variable a new destination.
+pub fn set_dest(instr: &mut Instruction, name: String) {
+    if let Some((op, _, op1, op2)) = instr.as_binary() {
+        *instr = Instruction::binary(op, name, op1.clone(), op2.clone());
+        return;
+    }
+    match instr {
+        Instruction::Const { dest, .. }
+        | Instruction::Not { dest, .. }
+        | Instruction::Id { dest, .. }
+        | Instruction::Move { dest, .. }
+        | Instruction::Call { dest: Some((dest, _)), .. }
+        | Instruction::Alloc { dest, .. }
+        | Instruction::Load { dest, .. }
+        | Instruction::PtrAdd { dest, .. }
+        | Instruction::Phi { dest, .. } => *dest = name,
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constprop::sccp;
+    use crate::difftest::check_transform;
+    use crate::generate::{for_each_seed, GenConfig};
+    use crate::interp::{interpret, DEFAULT_STEP_LIMIT};
+    use crate::text::parse_function;
+    use crate::verify::verify_ssa;
+
+    fn phis(f: &Function) -> Vec<String> {
+        f.instr.iter().filter_map(|i| match i {
+            Instruction::Phi { dest, .. } => Some(dest.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    fn same_run(f: &Function, g: &Function) {
+        let (before, after) = (interpret(f, DEFAULT_STEP_LIMIT).unwrap(), interpret(g, DEFAULT_STEP_LIMIT).unwrap());
+        assert_eq!((before.output, before.ret), (after.output, after.ret), "\n{}", g);
+    }
+
+    #[test]
+    fn test_diamond() {
+        let f = parse_function("
+@main(c: bool) {
+  br c .then .else
+.then:
+  x: int = const 1
+  jmp .merge
+.else:
+  x: int = const 2
+.merge:
+  print x
+}
+")
+        .unwrap();
+        assert_eq!(to_ssa(&f, SsaFlavor::Minimal).to_string(), "@main(c: bool) {
+.block0:
+  br c .then .else
+.then:
+  x.1: int = const 1
+  jmp .merge
+.else:
+  x.2: int = const 2
+.merge:
+  x.3 = phi .then x.1 .else x.2
+  print x.3
+}
+");
+    }
+
+    #[test]
+    fn test_loop_flavors() {
+        let f = parse_function("
+@main {
+  i: int = const 0
+  n: int = const 4
+  one: int = const 1
+.loop:
+  t = add i one
+  i = id t
+  c = lt i n
+  br c .loop .out
+.out:
+  print i
+  ret i
+}
+")
+        .unwrap();
+        // `t` and `c` never leave the loop body alive
+        let minimal = to_ssa(&f, SsaFlavor::Minimal);
+        assert_eq!(phis(&minimal), vec!["c.1", "i.2", "t.1"]);
+        assert!(minimal.instr.contains(&Instruction::Phi {
+            dest: "i.2".into(),
+            args: vec![("block0".into(), "i.1".into()), ("loop".into(), "i.3".into())]
+        }));
+        // undefined on the way in, as before
+        assert!(minimal.instr.contains(&Instruction::Phi {
+            dest: "t.1".into(),
+            args: vec![("block0".into(), "t".into()), ("loop".into(), "t.2".into())]
+        }));
+        assert_eq!(phis(&to_ssa(&f, SsaFlavor::SemiPruned)), vec!["i.2"]);
+        assert_eq!(phis(&to_ssa(&f, SsaFlavor::Pruned)), vec!["i.2"]);
+
+        for flavor in [SsaFlavor::Minimal, SsaFlavor::SemiPruned, SsaFlavor::Pruned] {
+            same_run(&f, &to_ssa(&f, flavor));
+        }
+    }
+
+    #[test]
+    fn test_pruned_skips_dead_joins() {
+        let f = parse_function("
+@main(c: bool) {
+  x: int = const 0
+  br c .a .b
+.a:
+  x: int = const 1
+  jmp .m
+.b:
+  print x
+  x: int = const 2
+.m:
+  ret
+}
+")
+        .unwrap();
+        // `x` is read before being written in .b, but is dead at .m
+        assert_eq!(phis(&to_ssa(&f, SsaFlavor::Minimal)), vec!["x.4"]);
+        assert_eq!(phis(&to_ssa(&f, SsaFlavor::SemiPruned)), vec!["x.4"]);
+        assert!(phis(&to_ssa(&f, SsaFlavor::Pruned)).is_empty());
+    }
+
+    #[test]
+    fn test_entry_is_a_loop_header() {
+        let f = parse_function("
+@main(n: int) {
+.top:
+  one: int = const 1
+  n = sub n one
+  print n
+  zero: int = const 0
+  more = gt n zero
+  br more .top .done
+.done:
+  ret n
+}
+")
+        .unwrap();
+        let g = to_ssa(&f, SsaFlavor::Pruned);
+        assert_eq!(g.instr[..2], [
+            Instruction::Label { label: "entry".into() },
+            Instruction::Label { label: "top".into() },
+        ]);
+        assert_eq!(g.instr[2], Instruction::Phi {
+            dest: "n.1".into(),
+            args: vec![("entry".into(), "n".into()), ("top".into(), "n.2".into())]
+        });
+    }
+
+    // runs a sequence of copies over an environment of variable -> value
+    fn run_copies(sequence: &[Instruction], env: &mut HashMap<String, i64>) {
+        for instr in sequence {
+            let Instruction::Id { dest, src } = instr else { panic!("not a copy: {}", instr) };
+            let value = env[src];
+            env.insert(dest.clone(), value);
+        }
+    }
+
+    #[test]
+    fn test_phis_already_there() {
+        let f = parse_function("
+@main {
+  i: int = const 0
+  n: int = const 4
+  one: int = const 1
+.loop:
+  i = add i one
+  c = lt i n
+  br c .loop .out
+.out:
+  print i
+  ret i
+}
+")
+        .unwrap();
+        let ssa = to_ssa(&f, SsaFlavor::Pruned);
+        let (folded, _) = sccp(&ssa);
+        for flavor in [SsaFlavor::Minimal, SsaFlavor::SemiPruned, SsaFlavor::Pruned] {
+            let g = to_ssa(&folded, flavor);
+            assert_eq!(verify_ssa(&Cfg::from_function(&g)), Ok(()), "\n{}", g);
+            same_run(&f, &g);
+            same_run(&f, &from_ssa(&g));
+        }
+        // the old phi keeps its arguments, under the new names
+        assert!(to_ssa(&ssa, SsaFlavor::Pruned).instr.contains(&Instruction::Phi {
+            dest: "i.2.1".into(),
+            args: vec![("block0".into(), "i.1.1".into()), ("loop".into(), "i.3.2".into())]
+        }));
+    }
+
+    #[test]
+    fn test_sequentialize() {
+        let copy = |dest: &str, src: &str| (dest.to_string(), src.to_string());
+        // with the number of cycles, each needs one temporary
+        let cases = [
+            (vec![copy("a", "b"), copy("b", "c")], 0),
+            (vec![copy("a", "b"), copy("b", "a")], 1),
+            (vec![copy("a", "b"), copy("b", "c"), copy("c", "a"), copy("d", "a"), copy("e", "e")], 1),
+            (vec![copy("a", "b"), copy("b", "a"), copy("c", "d"), copy("d", "c")], 2),
+        ];
+        for (copies, cycles) in cases {
+            let mut env: HashMap<String, i64> = ["a", "b", "c", "d", "e"].iter().zip(1..).map(|(v, i)| (v.to_string(), i)).collect();
+            let expected: Vec<(String, i64)> = copies.iter().map(|(d, s)| (d.clone(), env[s])).collect();
+            let mut temps = 0;
+            let sequence = sequentialize(copies.clone(), |var| {
+                temps += 1;
+                format!("{}.tmp", var)
+            });
+            run_copies(&sequence, &mut env);
+            for (dest, value) in expected {
+                assert_eq!(env[&dest], value, "{:?}", copies);
+            }
+            assert_eq!(temps, cycles);
+            assert_eq!(sequence.len(), copies.iter().filter(|(d, s)| d != s).count() + temps);
+        }
+    }
+
+    #[test]
+    fn test_swap_problem() {
+        // the two phis swap `x` and `y` every iteration
+        let f = parse_function("
+@main {
+.entry:
+  x: int = const 1
+  y: int = const 2
+  i: int = const 0
+  one: int = const 1
+  three: int = const 3
+.head:
+  x.2 = phi .entry x .head y.2
+  y.2 = phi .entry y .head x.2
+  i.2 = phi .entry i .head j
+  j = add i.2 one
+  print x.2
+  done = eq j three
+  br done .out .head
+.out:
+  ret y.2
+}
+")
+        .unwrap();
+        let g = from_ssa(&f);
+        assert!(!g.instr.iter().any(|i| matches!(i, Instruction::Phi { .. })));
+        same_run(&f, &g);
+        assert_eq!(interpret(&g, DEFAULT_STEP_LIMIT).unwrap().output, vec!["1", "2", "1"]);
+    }
+
+    #[test]
+    fn test_lost_copy_problem() {
+        // `x.2` is read after the loop, so the copy for the back edge can't
+        // go at the end of .head, which also leaves the loop
+        let f = parse_function("
+@main {
+.entry:
+  x: int = const 1
+  one: int = const 1
+  four: int = const 4
+.head:
+  x.2 = phi .entry x .head x.3
+  x.3 = add x.2 one
+  c = lt x.3 four
+  br c .head .out
+.out:
+  print x.2
+}
+")
+        .unwrap();
+        let g = from_ssa(&f);
+        same_run(&f, &g);
+        assert_eq!(interpret(&g, DEFAULT_STEP_LIMIT).unwrap().output, vec!["3"]);
+        assert!(g.instr.contains(&Instruction::Label { label: "head.head".into() }));
+    }
+
+    #[test]
+    fn test_undefined_phi_arguments() {
+        // `p` is only set on the way through .a, the copies into `p.2` and
+        // then `p.3` would read it undefined when control comes from .b
+        let f = parse_function("
+@main {
+.entry:
+  c: bool = const false
+  br c .a .b
+.a:
+  n: int = const 1
+  p.1: ptr<int> = alloc n
+  free p.1
+  jmp .m
+.b:
+  jmp .m
+.m:
+  p.2 = phi .a p.1 .b p
+  br c .x .y
+.x:
+  jmp .n
+.y:
+  jmp .n
+.n:
+  p.3 = phi .x p.2 .y p.2
+  print c
+}
+")
+        .unwrap();
+        let g = from_ssa(&f);
+        same_run(&f, &g);
+        let copies: Vec<&Instruction> = g.instr.iter().filter(|i| matches!(i, Instruction::Id { .. })).collect();
+        assert_eq!(copies, vec![&Instruction::Id { dest: "p.2".into(), src: "p.1".into() }]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let f = parse_function("
+@main {
+  i: int = const 0
+  s: int = const 0
+  n: int = const 5
+  one: int = const 1
+.loop:
+  c = lt i n
+  br c .body .out
+.body:
+  s = add s i
+  i = add i one
+  jmp .loop
+.out:
+  print s
+  ret i
+}
+")
+        .unwrap();
+        for flavor in [SsaFlavor::Minimal, SsaFlavor::SemiPruned, SsaFlavor::Pruned] {
+            same_run(&f, &from_ssa(&to_ssa(&f, flavor)));
+        }
+    }
+
+    #[test]
+    fn test_single_definitions() {
+        let f = parse_function("
+@main {
+  a: int = const 1
+  a = add a a
+  b = id a
+  a = mul a b
+  print a
+}
+")
+        .unwrap();
+        let g = to_ssa(&f, SsaFlavor::Minimal);
+        let dests: Vec<&String> = g.instr.iter().filter_map(get_dest).collect();
+        assert_eq!(dests, vec!["a.1", "a.2", "b.1", "a.3"]);
+        assert_eq!(g.instr.last(), Some(&Instruction::Print { value: "a.3".into() }));
+    }
+
+    #[test]
+    fn fuzz_ssa() {
+        for_each_seed(100, &GenConfig::default(), |seed, f| {
+            for flavor in [SsaFlavor::Minimal, SsaFlavor::SemiPruned, SsaFlavor::Pruned] {
+                let ssa = to_ssa(f, flavor);
+                let mut defined = HashSet::new();
+                for dest in ssa.instr.iter().filter_map(get_dest) {
+                    assert!(defined.insert(dest), "seed {}: `{}` defined twice\n{}", seed, dest, ssa);
+                }
+                if let Err(errors) = verify_ssa(&Cfg::from_function(&ssa)) {
+                    panic!("seed {}: {:?}\n{}", seed, errors, ssa);
+                }
+                let (optimized, _) = sccp(&ssa);
+                if let Err(errors) = verify_ssa(&Cfg::from_function(&optimized)) {
+                    panic!("seed {}: {:?} after sccp\n{}", seed, errors, optimized);
+                }
+                if let Some(m) = check_transform(f, &format!("{:?}", flavor), |_| ssa.clone()) {
+                    panic!("seed {}: {}", seed, m);
+                }
+                // SCCP is meant for SSA form
+                if let Some(m) = check_transform(&ssa, "sccp", |_| optimized.clone()) {
+                    panic!("seed {}: {}", seed, m);
+                }
+                if let Some(m) = check_transform(f, &format!("from_ssa {:?}", flavor), |_| from_ssa(&ssa)) {
+                    panic!("seed {}: {}", seed, m);
+                }
+                if let Some(m) = check_transform(f, "sccp then from_ssa", |_| from_ssa(&optimized)) {
+                    panic!("seed {}: {}", seed, m);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,757 @@
+use std::fmt;
+
+use crate::lvn::get_dest;
+use crate::typecheck::infer_types;
+use crate::types::*;
+
+// Textual form of the IR. One instruction per line, labels start with a '.'
+// and end with ':', '#' starts a comment:
+//
+// @main {
+//   v0: bool = const true
+//   br v0 .then_blk .else_blk
+// .then_blk:
+//   v1: int = const 10
+//   jmp .merge_blk
+// ...
+// }
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn error(line: usize, message: impl Into<String>) -> ParseError {
+    ParseError { line, message: message.into() }
+}
+
+// =====================================
+//             PRINTER
+// =====================================
+
+impl fmt::Display for Types {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Types::Int => write!(f, "int"),
+            Types::Float => write!(f, "float"),
+            Types::Bool => write!(f, "bool"),
+            Types::Ptr(inner) => write!(f, "ptr<{}>", inner),
+        }
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.opcode())
+    }
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal::Int(v) => write!(f, "{}", v),
+            // a NaN other than `f64::NAN` (its sign or payload differ) is
+            // spelled by its bits, `nan:0x...`
+            Literal::Float(v) if v.is_nan() && v.to_bits() != f64::NAN.to_bits() => {
+                write!(f, "nan:{:#018x}", v.to_bits())
+            }
+            // `{:?}` always keeps a `.` or exponent and round-trips exactly (`-0.0`, `NaN`, `inf`)
+            Literal::Float(v) => write!(f, "{:?}", v),
+            Literal::Bool(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Const { dest, typ, values } => write!(f, "{}: {} = const {}", dest, typ, values),
+            _ if let Some((op, dest, op1, op2)) = self.as_binary() => {
+                write!(f, "{} = {} {} {}", dest, op, op1, op2)
+            }
+            Instruction::Not { dest, src } => write!(f, "{} = not {}", dest, src),
+            Instruction::Jmp { label } => write!(f, "jmp .{}", label),
+            Instruction::Move { dest, src } => write!(f, "{} = move {}", dest, src),
+            Instruction::Id { dest, src } => write!(f, "{} = id {}", dest, src),
+            Instruction::Label { label } => write!(f, ".{}:", label),
+            Instruction::Br { cond, then_label, else_label } => write!(f, "br {} .{} .{}", cond, then_label, else_label),
+            Instruction::Ret { value: Some(v) } => write!(f, "ret {}", v),
+            Instruction::Ret { value: None } => write!(f, "ret"),
+            Instruction::Print { value } => write!(f, "print {}", value),
+            Instruction::Call { dest, func, args } => {
+                if let Some((dest, typ)) = dest {
+                    write!(f, "{}: {} = ", dest, typ)?;
+                }
+                write!(f, "call @{}", func)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                Ok(())
+            }
+            Instruction::Alloc { dest, typ, size } => write!(f, "{}: {} = alloc {}", dest, typ, size),
+            Instruction::Free { ptr } => write!(f, "free {}", ptr),
+            Instruction::Load { dest, ptr } => write!(f, "{} = load {}", dest, ptr),
+            Instruction::Store { ptr, value } => write!(f, "store {} {}", ptr, value),
+            Instruction::PtrAdd { dest, ptr, offset } => write!(f, "{} = ptradd {} {}", dest, ptr, offset),
+            Instruction::Phi { dest, args } => {
+                write!(f, "{} = phi", dest)?;
+                for (label, var) in args {
+                    write!(f, " .{} {}", label, var)?;
+                }
+                Ok(())
+            }
+            _ => unreachable!("every two operand instruction is matched by `as_binary`"),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "@{}", self.name)?;
+        if !self.args.is_empty() {
+            let args: Vec<String> = self.args.iter().map(|a| format!("{}: {}", a.name, a.typ)).collect();
+            write!(f, "({})", args.join(", "))?;
+        }
+        if let Some(typ) = &self.ret_type {
+            write!(f, ": {}", typ)?;
+        }
+        writeln!(f, " {{")?;
+        for instr in &self.instr {
+            if let Instruction::Label { .. } = instr {
+                writeln!(f, "{}", instr)?;
+            } else {
+                writeln!(f, "  {}", instr)?;
+            }
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, func) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", func)?;
+        }
+        Ok(())
+    }
+}
+
+// =====================================
+//             PARSER
+// =====================================
+
+/// Parse a single function, header and braces included.
+pub fn parse_function(src: &str) -> Result<Function, ParseError> {
+    let mut iter = significant_lines(src).into_iter();
+    let (_, f) = parse_function_from(&mut iter)?.ok_or_else(|| error(0, "expected a function, found end of input"))?;
+    match iter.next() {
+        Some((line, text)) => Err(error(line, format!("unexpected `{}` after end of function", text))),
+        None => Ok(f),
+    }
+}
+
+/// Parse any number of functions, one after the other.
+pub fn parse_program(src: &str) -> Result<Program, ParseError> {
+    let mut iter = significant_lines(src).into_iter();
+    let mut functions = Vec::new();
+    while let Some((line, f)) = parse_function_from(&mut iter)? {
+        if functions.iter().any(|other: &Function| other.name == f.name) {
+            return Err(error(line, format!("function `{}` is defined twice", f.name)));
+        }
+        functions.push(f);
+    }
+    Ok(Program { functions })
+}
+
+// the function and the line of its header, `None` at the end of input
+fn parse_function_from<'a>(lines: &mut impl Iterator<Item = (usize, &'a str)>) -> Result<Option<(usize, Function)>, ParseError> {
+    let Some((header_line, header)) = lines.next() else {
+        return Ok(None);
+    };
+    let mut f = header
+        .strip_prefix('@')
+        .and_then(|rest| rest.strip_suffix('{'))
+        .and_then(|sig| parse_signature(header_line, sig.trim()).transpose())
+        .unwrap_or_else(|| Err(error(header_line, format!("expected `@name(args): type {{`, found `{}`", header))))?;
+
+    let mut annotations = Vec::new();
+    for (line, text) in lines {
+        if text == "}" {
+            check_annotations(&f, &annotations)?;
+            return Ok(Some((header_line, f)));
+        }
+        let (instr, annotation) = parse_annotated(line, text)?;
+        annotations.extend(annotation.map(|typ| (line, f.instr.len(), typ)));
+        f.instr.push(instr);
+    }
+    Err(error(header_line, format!("function `{}` is missing its closing `}}`", f.name)))
+}
+
+// `name`, `name: type`, `name(a: int, b: bool)` or `name(a: int): type` as a
+// function with no instructions yet, `None` when it does not have that shape at all
+fn parse_signature(line: usize, sig: &str) -> Result<Option<Function>, ParseError> {
+    let (head, ret) = match sig.rfind(')') {
+        Some(close) => (&sig[..=close], sig[close + 1..].trim()),
+        None => match sig.split_once(':') {
+            Some((head, ret)) => (head.trim(), ret.trim()),
+            None => (sig, ""),
+        },
+    };
+    let ret = ret.strip_prefix(':').map(str::trim).unwrap_or(ret);
+    let ret_type = if ret.is_empty() { None } else { Some(parse_type(line, ret)?) };
+
+    let (name, params) = match head.split_once('(') {
+        Some((name, params)) => match params.strip_suffix(')') {
+            Some(params) => (name.trim(), params.trim()),
+            None => return Ok(None),
+        },
+        None => (head, ""),
+    };
+    if !is_ident(name) {
+        return Ok(None);
+    }
+
+    let mut args = Vec::new();
+    for param in params.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (arg, typ) = param
+            .split_once(':')
+            .ok_or_else(|| error(line, format!("argument `{}` needs a type, like `{}: int`", param, param)))?;
+        let arg = parse_var(line, arg.trim())?;
+        if args.iter().any(|a: &Argument| a.name == arg) {
+            return Err(error(line, format!("argument `{}` appears twice", arg)));
+        }
+        args.push(Argument { name: arg, typ: parse_type(line, typ.trim())? });
+    }
+    Ok(Some(Function { name: name.to_string(), args, ret_type, instr: Vec::new() }))
+}
+
+/// Parse a bare instruction list (no function header), handy for block-level passes.
+pub fn parse_instrs(src: &str) -> Result<Vec<Instruction>, ParseError> {
+    let mut f = Function { name: String::new(), args: vec![], ret_type: None, instr: vec![] };
+    let mut annotations = Vec::new();
+    for (line, text) in significant_lines(src) {
+        let (instr, annotation) = parse_annotated(line, text)?;
+        annotations.extend(annotation.map(|typ| (line, f.instr.len(), typ)));
+        f.instr.push(instr);
+    }
+    check_annotations(&f, &annotations)?;
+    Ok(f.instr)
+}
+
+pub fn parse_instruction(src: &str) -> Result<Instruction, ParseError> {
+    parse_instruction_at(1, strip_comment(src).trim())
+}
+
+// (1-based line number, trimmed text) for every non blank, non comment line
+fn significant_lines(src: &str) -> Vec<(usize, &str)> {
+    src.lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, strip_comment(l).trim()))
+        .filter(|(_, l)| !l.is_empty())
+        .collect()
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(pos) => &line[..pos],
+        None => line,
+    }
+}
+
+fn parse_instruction_at(line: usize, text: &str) -> Result<Instruction, ParseError> {
+    parse_annotated(line, text).map(|(instr, _)| instr)
+}
+
+// the type annotations of copies, loads, `ptradd`s and phis, (line, index of
+// the instruction, type), must agree with the types inferred for the function
+fn check_annotations(f: &Function, annotations: &[(usize, usize, Types)]) -> Result<(), ParseError> {
+    if annotations.is_empty() {
+        return Ok(());
+    }
+    let types = infer_types(f).types;
+    for (line, index, typ) in annotations {
+        let dest = get_dest(&f.instr[*index]).expect("only value operations are annotated");
+        if let Some(found) = types.get(dest).filter(|found| *found != typ) {
+            return Err(error(*line, format!("`{}` is annotated `{}` but holds `{}`", dest, typ, found)));
+        }
+    }
+    Ok(())
+}
+
+// an instruction, and the type annotation that only the types of its
+// operands can confirm
+fn parse_annotated(line: usize, text: &str) -> Result<(Instruction, Option<Types>), ParseError> {
+    if let Some(label) = text.strip_suffix(':') {
+        return Ok((Instruction::Label { label: parse_label(line, label)? }, None));
+    }
+
+    // value operations look like `dest[: type] = op args...`
+    if let Some((lhs, rhs)) = text.split_once('=') {
+        let (dest, typ) = match lhs.split_once(':') {
+            Some((dest, typ)) => (dest.trim(), Some(parse_type(line, typ.trim())?)),
+            None => (lhs.trim(), None),
+        };
+        if !is_ident(dest) {
+            return Err(error(line, format!("invalid destination `{}`", dest)));
+        }
+        let dest = dest.to_string();
+        let words: Vec<&str> = rhs.split_whitespace().collect();
+        let (&op, args) = words.split_first().ok_or_else(|| error(line, "missing operation after `=`"))?;
+        // the operation gives its result a type of its own
+        let fixed = match op {
+            "not" => Some(Types::Bool),
+            _ => BinOp::from_opcode(op).map(|binop| binary_signature(binop).1),
+        };
+        if let (Some(typ), Some(fixed)) = (&typ, &fixed)
+            && typ != fixed
+        {
+            return Err(error(line, format!("`{}` is annotated `{}` but `{}` gives `{}`", dest, typ, op, fixed)));
+        }
+        let checked = match op {
+            "id" | "move" | "load" | "ptradd" | "phi" => typ.clone(),
+            _ => None,
+        };
+
+        let instr = match op {
+            "const" => {
+                let typ = typ.ok_or_else(|| error(line, "`const` needs a type annotation"))?;
+                let [value] = operands::<1>(line, op, args)?;
+                let values = parse_literal(line, &typ, value)?;
+                Ok(Instruction::Const { dest, typ, values })
+            }
+            "not" => {
+                let [src] = vars::<1>(line, op, args)?;
+                Ok(Instruction::Not { dest, src })
+            }
+            "id" => {
+                let [src] = vars::<1>(line, op, args)?;
+                Ok(Instruction::Id { dest, src })
+            }
+            "move" => {
+                let [src] = vars::<1>(line, op, args)?;
+                Ok(Instruction::Move { dest, src })
+            }
+            "alloc" => {
+                let typ = match typ {
+                    Some(typ @ Types::Ptr(_)) => typ,
+                    _ => return Err(error(line, "`alloc` needs a pointer type annotation, like `ptr<int>`")),
+                };
+                let [size] = vars::<1>(line, op, args)?;
+                Ok(Instruction::Alloc { dest, typ, size })
+            }
+            "load" => {
+                let [ptr] = vars::<1>(line, op, args)?;
+                Ok(Instruction::Load { dest, ptr })
+            }
+            "ptradd" => {
+                let [ptr, offset] = vars::<2>(line, op, args)?;
+                Ok(Instruction::PtrAdd { dest, ptr, offset })
+            }
+            "phi" => {
+                // `x = phi .label var .label var ...`
+                if args.len() % 2 != 0 {
+                    return Err(error(line, "`phi` takes pairs of a label and a variable"));
+                }
+                let args = args
+                    .chunks(2)
+                    .map(|pair| Ok((parse_label_ref(line, pair[0])?, parse_var(line, pair[1])?)))
+                    .collect::<Result<_, ParseError>>()?;
+                Ok(Instruction::Phi { dest, args })
+            }
+            "call" => {
+                let typ = typ.ok_or_else(|| error(line, "a `call` with a destination needs a type annotation"))?;
+                let (func, args) = parse_call(line, args)?;
+                Ok(Instruction::Call { dest: Some((dest, typ)), func, args })
+            }
+            _ => match BinOp::from_opcode(op) {
+                Some(binop) => {
+                    let [op1, op2] = vars::<2>(line, op, args)?;
+                    Ok(Instruction::binary(binop, dest, op1, op2))
+                }
+                None => Err(error(line, format!("unknown value operation `{}`", op))),
+            },
+        };
+        return Ok((instr?, checked));
+    }
+
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let (&op, args) = words.split_first().ok_or_else(|| error(line, "empty instruction"))?;
+    let instr = match op {
+        "jmp" => {
+            let [label] = operands::<1>(line, op, args)?;
+            Ok(Instruction::Jmp { label: parse_label_ref(line, label)? })
+        }
+        "br" => {
+            let [cond, then_label, else_label] = operands::<3>(line, op, args)?;
+            Ok(Instruction::Br {
+                cond: parse_var(line, cond)?,
+                then_label: parse_label_ref(line, then_label)?,
+                else_label: parse_label_ref(line, else_label)?,
+            })
+        }
+        "ret" => match args {
+            [] => Ok(Instruction::Ret { value: None }),
+            [value] => Ok(Instruction::Ret { value: Some(parse_var(line, value)?) }),
+            _ => Err(error(line, "`ret` takes at most one operand")),
+        },
+        "print" => {
+            let [value] = vars::<1>(line, op, args)?;
+            Ok(Instruction::Print { value })
+        }
+        "call" => {
+            let (func, args) = parse_call(line, args)?;
+            Ok(Instruction::Call { dest: None, func, args })
+        }
+        "free" => {
+            let [ptr] = vars::<1>(line, op, args)?;
+            Ok(Instruction::Free { ptr })
+        }
+        "store" => {
+            let [ptr, value] = vars::<2>(line, op, args)?;
+            Ok(Instruction::Store { ptr, value })
+        }
+        _ => Err(error(line, format!("unknown instruction `{}`", text))),
+    };
+    Ok((instr?, None))
+}
+
+// `@func arg...`
+fn parse_call(line: usize, words: &[&str]) -> Result<(String, Vec<String>), ParseError> {
+    let (func, args) = words.split_first().ok_or_else(|| error(line, "`call` needs a function"))?;
+    let func = match func.strip_prefix('@') {
+        Some(name) if is_ident(name) => name.to_string(),
+        _ => return Err(error(line, format!("expected a function like `@name`, found `{}`", func))),
+    };
+    let args = args.iter().map(|arg| parse_var(line, arg)).collect::<Result<_, _>>()?;
+    Ok((func, args))
+}
+
+fn operands<'a, const N: usize>(line: usize, op: &str, args: &[&'a str]) -> Result<[&'a str; N], ParseError> {
+    <[&str; N]>::try_from(args)
+        .map_err(|_| error(line, format!("`{}` expects {} operand(s), found {}", op, N, args.len())))
+}
+
+fn vars<const N: usize>(line: usize, op: &str, args: &[&str]) -> Result<[String; N], ParseError> {
+    let args = operands::<N>(line, op, args)?;
+    let mut out: [String; N] = std::array::from_fn(|_| String::new());
+    for (slot, arg) in out.iter_mut().zip(args) {
+        *slot = parse_var(line, arg)?;
+    }
+    Ok(out)
+}
+
+fn parse_var(line: usize, text: &str) -> Result<String, ParseError> {
+    if is_ident(text) {
+        Ok(text.to_string())
+    } else {
+        Err(error(line, format!("invalid variable name `{}`", text)))
+    }
+}
+
+// label definition, `.name` (the trailing ':' is already stripped)
+fn parse_label(line: usize, text: &str) -> Result<String, ParseError> {
+    parse_label_ref(line, text.trim())
+}
+
+fn parse_label_ref(line: usize, text: &str) -> Result<String, ParseError> {
+    match text.strip_prefix('.') {
+        Some(name) if is_ident(name) => Ok(name.to_string()),
+        _ => Err(error(line, format!("expected a label like `.name`, found `{}`", text))),
+    }
+}
+
+fn parse_type(line: usize, text: &str) -> Result<Types, ParseError> {
+    match text {
+        "int" => Ok(Types::Int),
+        "float" => Ok(Types::Float),
+        "bool" => Ok(Types::Bool),
+        _ => match text.strip_prefix("ptr<").and_then(|rest| rest.strip_suffix('>')) {
+            Some(inner) => Ok(Types::Ptr(Box::new(parse_type(line, inner.trim())?))),
+            None => Err(error(line, format!("unknown type `{}`", text))),
+        },
+    }
+}
+
+fn parse_literal(line: usize, typ: &Types, text: &str) -> Result<Literal, ParseError> {
+    match typ {
+        Types::Int => text
+            .parse::<i64>()
+            .map(Literal::Int)
+            .map_err(|_| error(line, format!("invalid int literal `{}`", text))),
+        Types::Bool => match text {
+            "true" => Ok(Literal::Bool(true)),
+            "false" => Ok(Literal::Bool(false)),
+            _ => Err(error(line, format!("invalid bool literal `{}`", text))),
+        },
+        Types::Float => {
+            let bits = text.strip_prefix("nan:0x").map(|hex| u64::from_str_radix(hex, 16));
+            match bits {
+                Some(Ok(bits)) if f64::from_bits(bits).is_nan() => Ok(Literal::Float(f64::from_bits(bits))),
+                Some(_) => Err(error(line, format!("`{}` is not the bits of a NaN", text))),
+                None => text
+                    .parse::<f64>()
+                    .map(Literal::Float)
+                    .map_err(|_| error(line, format!("invalid float literal `{}`", text))),
+            }
+        }
+        Types::Ptr(_) => Err(error(line, "pointers have no literals, use `alloc`")),
+    }
+}
+
+fn is_ident(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+// =====================================
+//             TESTS
+// =====================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIAMOND: &str = "\
+@main {
+  v0: bool = const true
+  br v0 .then_blk .else_blk
+.then_blk:
+  v1: int = const 10
+  jmp .merge_blk
+.else_blk:
+  v2: int = const 20
+  jmp .merge_blk
+.merge_blk:
+  ret v1
+}
+";
+
+    #[test]
+    fn test_parse_diamond() {
+        let f = parse_function(DIAMOND).unwrap();
+
+        assert_eq!(f.name, "main");
+        assert_eq!(f.instr.len(), 10);
+        assert_eq!(f.instr[0], Instruction::Const { dest: "v0".into(), typ: Types::Bool, values: Literal::Bool(true) });
+        assert_eq!(
+            f.instr[1],
+            Instruction::Br { cond: "v0".into(), then_label: "then_blk".into(), else_label: "else_blk".into() }
+        );
+        assert_eq!(f.instr[2], Instruction::Label { label: "then_blk".into() });
+        assert_eq!(f.instr[9], Instruction::Ret { value: Some("v1".into()) });
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let f = parse_function(DIAMOND).unwrap();
+        assert_eq!(f.to_string(), DIAMOND);
+
+        let src = "\
+@ops {
+  a: int = const -4
+  b: int = const 2
+  c = add a b
+  d = mul c b
+  e = eq d a
+  f = id e
+  g = move f
+  h = sub a b
+  i = div a b
+  j = rem a b
+  k = lt a b
+  l = le a b
+  m = gt a b
+  n = ge a b
+  o = ne a b
+  p = and k l
+  q = or m n
+  r = not q
+  s = shl a b
+  t = shr a b
+  u = bitand a b
+  w = bitor a b
+  x = bitxor a b
+  y: float = const 1.5
+  z: float = const -0.0
+  fa = fadd y z
+  fs = fsub y z
+  fm = fmul y z
+  fd = fdiv y z
+  fe = feq y z
+  fl = flt y z
+  fle = fle y z
+  fg = fgt y z
+  fge = fge y z
+  nan: float = const NaN
+  inf: float = const -inf
+  tiny: float = const 5e-324
+  print g
+  ret
+}
+";
+        let f = parse_function(src).unwrap();
+        assert_eq!(f.to_string(), src);
+        assert_eq!(parse_function(&f.to_string()).unwrap(), f);
+
+        // integer spelling of a float still gets a float literal, printed with a `.0`
+        let f = parse_function("@f {\n  a: float = const 3\n}").unwrap();
+        assert_eq!(f.instr[0].to_string(), "a: float = const 3.0");
+
+        // a NaN other than `NaN` keeps its sign and payload through the text
+        for bits in [0xfff8000000000000, 0x7ff0000000000001, f64::NAN.to_bits()] {
+            let instr = Instruction::Const { dest: "n".into(), typ: Types::Float, values: Literal::Float(f64::from_bits(bits)) };
+            let f = parse_function(&format!("@f {{\n  {}\n}}", instr)).unwrap();
+            let Instruction::Const { values: Literal::Float(v), .. } = f.instr[0] else { panic!("{}", f) };
+            assert_eq!(v.to_bits(), bits, "{}", instr);
+        }
+        assert_eq!(Literal::Float(f64::from_bits(0xfff8000000000000)).to_string(), "nan:0xfff8000000000000");
+        assert!(parse_function("@f {\n  n: float = const nan:0x3ff0000000000000\n}").is_err());
+
+        for op in BinOp::ALL {
+            assert_eq!(BinOp::from_opcode(&op.to_string()), Some(op));
+        }
+    }
+
+    #[test]
+    fn test_program_round_trip() {
+        let src = "\
+@main {
+  a: int = const 3
+  b: int = call @twice a
+  call @show b
+}
+
+@twice(x: int): int {
+  y = add x x
+  ret y
+}
+
+@show(v: int, unused: bool) {
+  print v
+}
+
+@answer: int {
+  v: int = const 42
+  ret v
+}
+";
+        let p = parse_program(src).unwrap();
+        assert_eq!(p.functions.len(), 4);
+        assert_eq!(p.function("show").unwrap().args[1], Argument { name: "unused".into(), typ: Types::Bool });
+        assert_eq!(p.function("answer").unwrap().ret_type, Some(Types::Int));
+        assert_eq!(p.to_string(), src);
+
+        assert!(parse_program("@f {\n}\n@f {\n}").is_err());
+        assert!(parse_program("@f(x) {\n}").is_err());
+        assert!(parse_program("@f(x: int, x: int) {\n}").is_err());
+        assert!(parse_function("@f {\n  x = call @g\n}").is_err());
+        assert!(parse_function("@f {\n  call g\n}").is_err());
+        assert!(parse_function("@f {\n}\n@g {\n}").is_err());
+        assert_eq!(parse_program("# nothing\n").unwrap(), Program::default());
+    }
+
+    #[test]
+    fn test_memory_round_trip() {
+        let src = "\
+@f(n: int): int {
+  p: ptr<int> = alloc n
+  one: int = const 1
+  q = ptradd p one
+  store q n
+  x = load q
+  pp: ptr<ptr<int>> = alloc one
+  store pp p
+  free pp
+  free p
+  ret x
+}
+";
+        let f = parse_function(src).unwrap();
+        assert_eq!(f.instr[0], Instruction::Alloc {
+            dest: "p".into(),
+            typ: Types::Ptr(Box::new(Types::Int)),
+            size: "n".into()
+        });
+        assert_eq!(f.to_string(), src);
+
+        assert!(parse_function("@f {\n  p: int = alloc n\n}").is_err());
+        assert!(parse_function("@f {\n  p: ptr<int> = const 0\n}").is_err());
+        assert!(parse_function("@f {\n  p: ptr<int = alloc n\n}").is_err());
+    }
+
+    #[test]
+    fn test_phi_round_trip() {
+        let src = "@f {\n.a:\n  x: int = const 1\n.b:\n  y = phi .a x .b y\n  jmp .b\n}\n";
+        let f = parse_function(src).unwrap();
+        assert_eq!(f.instr[3], Instruction::Phi {
+            dest: "y".into(),
+            args: vec![("a".into(), "x".into()), ("b".into(), "y".into())]
+        });
+        assert_eq!(f.to_string(), src);
+
+        assert!(parse_function("@f {\n  y = phi .a\n}").is_err());
+        assert!(parse_function("@f {\n  y = phi a x\n}").is_err());
+    }
+
+    #[test]
+    fn test_comments_and_blank_lines() {
+        let f = parse_function("# leading comment\n\n@f {   \n  x: int = const 1 # trailing\n\n  print x\n}\n").unwrap();
+        assert_eq!(f.instr, parse_instrs("x: int = const 1\nprint x").unwrap());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = parse_function("@f {\n  x: int = const true\n}").unwrap_err();
+        assert_eq!(err.line, 2);
+
+        let err = parse_function("@f {\n  x = frob a b\n}").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(err.message.contains("frob"));
+
+        let err = parse_function("@f {\n  x = add a\n}").unwrap_err();
+        assert!(err.message.contains("expects 2"));
+
+        assert!(parse_function("@f {\n  jmp end\n}").is_err());
+        assert!(parse_function("@f {\n  ret\n").is_err());
+        assert!(parse_function("f {\n}").is_err());
+        assert!(parse_instruction("x = const 3").is_err());
+
+        let err = parse_program("@f {\n}\n@g {\n}\n\n@f {\n  ret\n}").unwrap_err();
+        assert_eq!(err.line, 6);
+        assert!(err.message.contains("defined twice"));
+    }
+
+    #[test]
+    fn test_type_annotations() {
+        // the result type of an operation is fixed by the operation
+        let err = parse_function("@f(a: int, b: int) {\n  x: bool = add a b\n}").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(err.message.contains("`add` gives `int`"), "{}", err);
+        assert!(parse_instruction("x: int = not b").is_err());
+        assert!(parse_instruction("x: bool = lt a b").is_ok());
+
+        // a copy, load or phi holds what its operands hold
+        let err = parse_function("@f(a: int) {\n  c = id a\n  x: bool = id c\n}").unwrap_err();
+        assert_eq!(err.line, 3);
+        assert!(err.message.contains("holds `int`"), "{}", err);
+        assert!(parse_function("@f {\n  n: int = const 1\n  p: ptr<int> = alloc n\n  x: float = load p\n}").is_err());
+        assert!(parse_instrs("a: int = const 1\nx: bool = move a").is_err());
+        let f = parse_function("@f(a: int) {\n  x: int = id a\n}").unwrap();
+        assert_eq!(f.instr, vec![Instruction::Id { dest: "x".into(), src: "a".into() }]);
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::types::*;
+
+// Type inference over a whole Function. Only `Const` carries a type, every
+// other definition gets one from its operation (`add` gives int, `lt` gives
+// bool, see `binary_signature`) or from its source (`id`/`move`). A variable
+// has a single type for the whole function, so a variable that is int on one
+// path and bool on another is a conflict.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypeError {
+    /// `var` was already inferred as `expected`, the definition at `index` makes it `found`.
+    Conflict { var: String, index: usize, expected: Types, found: Types },
+    /// An operand of the instruction at `index` has the wrong type.
+    Operand { var: String, index: usize, expected: Types, found: Types },
+    /// No definition ever gives `var` a type.
+    Unknown { var: String, index: usize },
+    /// The instruction at `index` uses `var` as a pointer.
+    NotAPointer { var: String, index: usize, found: Types },
+    /// The `ret` at `index` has a value and the function declares no return
+    /// type (`expected` is `None`), or it has none and the function does.
+    Return { index: usize, expected: Option<Types> },
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeError::Conflict { var, index, expected, found } => {
+                write!(f, "instruction {}: `{}` is {} here but {} elsewhere", index, var, found, expected)
+            }
+            TypeError::Operand { var, index, expected, found } => {
+                write!(f, "instruction {}: expected `{}` to be {}, found {}", index, var, expected, found)
+            }
+            TypeError::Unknown { var, index } => write!(f, "instruction {}: cannot infer a type for `{}`", index, var),
+            TypeError::NotAPointer { var, index, found } => {
+                write!(f, "instruction {}: expected `{}` to be a pointer, found {}", index, var, found)
+            }
+            TypeError::Return { index, expected: Some(expected) } => {
+                write!(f, "instruction {}: `ret` without a value, the function returns {}", index, expected)
+            }
+            TypeError::Return { index, expected: None } => {
+                write!(f, "instruction {}: `ret` with a value, the function returns nothing", index)
+            }
+        }
+    }
+}
+
+/// The inferred types, plus everything that did not check. Types are still
+/// reported for the variables that could be typed when there are errors.
+#[derive(Clone, Debug, Default)]
+pub struct TypeInference {
+    pub types: HashMap<String, Types>,
+    pub errors: Vec<TypeError>,
+}
+
+pub fn type_check(f: &Function) -> Result<HashMap<String, Types>, Vec<TypeError>> {
+    let inferred = infer_types(f);
+    if inferred.errors.is_empty() { Ok(inferred.types) } else { Err(inferred.errors) }
+}
+
+pub fn infer_types(f: &Function) -> TypeInference {
+    let mut types: HashMap<String, Types> = f.args.iter().map(|a| (a.name.clone(), a.typ.clone())).collect();
+    let mut errors = Vec::new();
+
+    // a copy can appear (in program order) before the definition of its
+    // source, e.g. in a loop, so iterate until no new variable gets a type
+    loop {
+        let before = types.len();
+        errors.clear();
+        for (index, instr) in f.instr.iter().enumerate() {
+            let Some((dest, typ)) = result_type(instr, &types) else {
+                continue;
+            };
+            match types.get(dest) {
+                None => {
+                    types.insert(dest.clone(), typ);
+                }
+                Some(expected) if *expected != typ => {
+                    errors.push(TypeError::Conflict { var: dest.clone(), index, expected: expected.clone(), found: typ });
+                }
+                Some(_) => {}
+            }
+        }
+        if types.len() == before {
+            break;
+        }
+    }
+
+    for (index, instr) in f.instr.iter().enumerate() {
+        check_operands(index, instr, &types, f.ret_type.as_ref(), &mut errors);
+    }
+
+    TypeInference { types, errors }
+}
+
+// what `instr` defines and its type, `None` when it defines nothing or the type is not known yet
+fn result_type<'a>(instr: &'a Instruction, types: &HashMap<String, Types>) -> Option<(&'a String, Types)> {
+    if let Some((op, dest, _, _)) = instr.as_binary() {
+        return Some((dest, binary_signature(op).1));
+    }
+    match instr {
+        Instruction::Const { dest, typ, .. } => Some((dest, typ.clone())),
+        Instruction::Not { dest, .. } => Some((dest, Types::Bool)),
+        Instruction::Call { dest: Some((dest, typ)), .. } => Some((dest, typ.clone())),
+        Instruction::Id { dest, src } | Instruction::Move { dest, src } => types.get(src).map(|t| (dest, t.clone())),
+        Instruction::Alloc { dest, typ, .. } => Some((dest, typ.clone())),
+        Instruction::Load { dest, ptr } => match types.get(ptr) {
+            Some(Types::Ptr(elem)) => Some((dest, (**elem).clone())),
+            _ => None,
+        },
+        Instruction::PtrAdd { dest, ptr, .. } => match types.get(ptr) {
+            Some(typ @ Types::Ptr(_)) => Some((dest, typ.clone())),
+            _ => None,
+        },
+        // the first argument whose type is known, `check_operands` makes the rest agree
+        Instruction::Phi { dest, args } => args.iter().find_map(|(_, var)| types.get(var)).map(|t| (dest, t.clone())),
+        _ => None,
+    }
+}
+
+fn check_operands(
+    index: usize,
+    instr: &Instruction,
+    types: &HashMap<String, Types>,
+    ret_type: Option<&Types>,
+    errors: &mut Vec<TypeError>,
+) {
+    let mut expect = |var: &String, expected: Option<&Types>| match (types.get(var), expected) {
+        (None, _) => errors.push(TypeError::Unknown { var: var.clone(), index }),
+        (Some(found), Some(expected)) if found != expected => errors.push(TypeError::Operand {
+            var: var.clone(),
+            index,
+            expected: expected.clone(),
+            found: found.clone(),
+        }),
+        _ => {}
+    };
+
+    if let Some((op, _, op1, op2)) = instr.as_binary() {
+        match binary_signature(op).0 {
+            Some(operand) => {
+                expect(op1, Some(&operand));
+                expect(op2, Some(&operand));
+            }
+            None => {
+                // both sides must agree, whatever the type is
+                expect(op1, None);
+                let left = types.get(op1).cloned();
+                expect(op2, left.as_ref());
+            }
+        }
+        return;
+    }
+    match instr {
+        Instruction::Not { src, .. } | Instruction::Br { cond: src, .. } => expect(src, Some(&Types::Bool)),
+        Instruction::Id { src, .. } | Instruction::Move { src, .. } => expect(src, None),
+        Instruction::Print { value } => expect(value, None),
+        Instruction::Ret { value: Some(value) } if ret_type.is_some() => expect(value, ret_type),
+        Instruction::Ret { value } if value.is_some() != ret_type.is_some() => {
+            errors.push(TypeError::Return { index, expected: ret_type.cloned() })
+        }
+        Instruction::Call { args, .. } => {
+            for arg in args {
+                expect(arg, None);
+            }
+        }
+        Instruction::Alloc { size, .. } => expect(size, Some(&Types::Int)),
+        Instruction::Free { ptr } | Instruction::Load { ptr, .. } => match pointee(index, ptr, types) {
+            Ok(_) => expect(ptr, None),
+            Err(e) => errors.push(e),
+        },
+        Instruction::Store { ptr, value } => match pointee(index, ptr, types) {
+            Ok(elem) => {
+                expect(ptr, None);
+                expect(value, elem.as_ref());
+            }
+            Err(e) => errors.push(e),
+        },
+        Instruction::PtrAdd { ptr, offset, .. } => match pointee(index, ptr, types) {
+            Ok(_) => {
+                expect(ptr, None);
+                expect(offset, Some(&Types::Int));
+            }
+            Err(e) => errors.push(e),
+        },
+        Instruction::Phi { dest, args } => {
+            let typ = types.get(dest).cloned();
+            for (_, var) in args {
+                expect(var, typ.as_ref());
+            }
+        }
+        _ => {}
+    }
+}
+
+// what `var` points to, `None` when its type is not known at all
+fn pointee(index: usize, var: &String, types: &HashMap<String, Types>) -> Result<Option<Types>, TypeError> {
+    match types.get(var) {
+        None => Ok(None),
+        Some(Types::Ptr(elem)) => Ok(Some((**elem).clone())),
+        Some(found) => Err(TypeError::NotAPointer { var: var.clone(), index, found: found.clone() }),
+    }
+}
+
+// =====================================
+//             TESTS
+// =====================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::*;
+    use crate::text::parse_function;
+
+    #[test]
+    fn test_infer_diamond() {
+        let f = parse_function("
+@main: int {
+  v0: bool = const true
+  br v0 .then_blk .else_blk
+.then_blk:
+  v1: int = const 10
+  jmp .merge_blk
+.else_blk:
+  v1: int = const 20
+  jmp .merge_blk
+.merge_blk:
+  v2 = add v1 v1
+  v3 = eq v2 v1
+  v4 = id v3
+  print v4
+  ret v2
+}
+")
+        .unwrap();
+
+        let types = type_check(&f).unwrap();
+        assert_eq!(types["v0"], Types::Bool);
+        assert_eq!(types["v1"], Types::Int);
+        assert_eq!(types["v2"], Types::Int);
+        assert_eq!(types["v3"], Types::Bool);
+        assert_eq!(types["v4"], Types::Bool);
+    }
+
+    #[test]
+    fn test_copy_before_definition() {
+        // `b` copies `a` before `a` is defined in program order (loop carried)
+        let f = parse_function("
+@f {
+  t: bool = const true
+  jmp .start
+.copy:
+  b = id a
+  print b
+  ret
+.start:
+  a: int = const 1
+  br t .copy .copy
+}
+")
+        .unwrap();
+
+        assert_eq!(type_check(&f).unwrap()["b"], Types::Int);
+    }
+
+    #[test]
+    fn test_path_conflict() {
+        let f = parse_function("
+@f {
+  c: bool = const true
+  br c .left .right
+.left:
+  x: int = const 1
+  jmp .join
+.right:
+  x: bool = const false
+  jmp .join
+.join:
+  print x
+}
+")
+        .unwrap();
+
+        let inferred = infer_types(&f);
+        assert_eq!(inferred.types["x"], Types::Int);
+        assert_eq!(
+            inferred.errors,
+            vec![TypeError::Conflict { var: "x".into(), index: 6, expected: Types::Int, found: Types::Bool }]
+        );
+    }
+
+    #[test]
+    fn test_operand_errors() {
+        let f = parse_function("
+@f {
+  b: bool = const true
+  i: int = const 1
+  s = add b i
+  e = eq i b
+  br i .x .x
+.x:
+  print nope
+}
+")
+        .unwrap();
+
+        assert_eq!(
+            type_check(&f).unwrap_err(),
+            vec![
+                TypeError::Operand { var: "b".into(), index: 2, expected: Types::Int, found: Types::Bool },
+                TypeError::Operand { var: "b".into(), index: 3, expected: Types::Int, found: Types::Bool },
+                TypeError::Operand { var: "i".into(), index: 4, expected: Types::Bool, found: Types::Int },
+                TypeError::Unknown { var: "nope".into(), index: 6 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_return_errors() {
+        // a value from a function that declares no return type, none from one that does
+        let f = parse_function("@f {\n  i: int = const 1\n  ret i\n}").unwrap();
+        assert_eq!(type_check(&f).unwrap_err(), vec![TypeError::Return { index: 1, expected: None }]);
+        let f = parse_function("@f: int {\n  ret\n}").unwrap();
+        assert_eq!(type_check(&f).unwrap_err(), vec![TypeError::Return { index: 0, expected: Some(Types::Int) }]);
+        let f = parse_function("@f: int {\n  b: bool = const true\n  ret b\n}").unwrap();
+        assert_eq!(
+            type_check(&f).unwrap_err(),
+            vec![TypeError::Operand { var: "b".into(), index: 1, expected: Types::Int, found: Types::Bool }]
+        );
+        assert!(type_check(&parse_function("@f {\n  ret\n}").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_arguments_and_calls() {
+        let f = parse_function("
+@f(n: int, flag: bool): int {
+  r: int = call @g n flag
+  s = add r n
+  br flag .a .b
+.a:
+  ret s
+.b:
+  ret flag
+}
+")
+        .unwrap();
+
+        let inferred = infer_types(&f);
+        assert_eq!(inferred.types["n"], Types::Int);
+        assert_eq!(inferred.types["s"], Types::Int);
+        assert_eq!(
+            inferred.errors,
+            vec![TypeError::Operand { var: "flag".into(), index: 6, expected: Types::Int, found: Types::Bool }]
+        );
+    }
+
+    #[test]
+    fn test_pointers() {
+        let f = parse_function("
+@f(n: int) {
+  p: ptr<float> = alloc n
+  q = ptradd p n
+  x: float = const 1.5
+  store q x
+  y = load p
+  z = fadd x y
+  store p n
+  w = load n
+  free p
+}
+")
+        .unwrap();
+
+        let inferred = infer_types(&f);
+        assert_eq!(inferred.types["q"], Types::Ptr(Box::new(Types::Float)));
+        assert_eq!(inferred.types["y"], Types::Float);
+        assert_eq!(
+            inferred.errors,
+            vec![
+                TypeError::Operand { var: "n".into(), index: 6, expected: Types::Float, found: Types::Int },
+                TypeError::NotAPointer { var: "n".into(), index: 7, found: Types::Int },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_generated_programs_check() {
+        for_each_seed(200, &GenConfig::default(), |seed, f| {
+            assert!(type_check(f).is_ok(), "seed {}\n{}", seed, f);
+        });
+    }
+}
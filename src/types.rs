@@ -1,44 +1,302 @@
-use std::clone;
-
-#[derive(Clone, Debug,PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Types{
     Int,
     Float,
-    Bool
+    Bool,
+    // pointer to the first of some consecutive values of the inner type
+    Ptr(Box<Types>)
 }
 
-#[derive(Clone,Debug,Hash, Eq, PartialEq)]
+#[derive(Clone,Debug)]
 pub enum Literal{
     Int(i64),
-    //Float(f64),
+    Float(f64),
     Bool(bool)
 }
 
+// Floats compare and hash by their bits, so `-0.0` and `0.0` are different
+// constants and a NaN is equal to itself. This is what value numbering needs:
+// two constants are interchangeable only when they are the same bits.
+impl PartialEq for Literal {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Literal::Int(a), Literal::Int(b)) => a == b,
+            (Literal::Float(a), Literal::Float(b)) => a.to_bits() == b.to_bits(),
+            (Literal::Bool(a), Literal::Bool(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Literal {}
+
+impl std::hash::Hash for Literal {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Literal::Int(v) => v.hash(state),
+            Literal::Float(v) => v.to_bits().hash(state),
+            Literal::Bool(v) => v.hash(state),
+        }
+    }
+}
+
 #[derive(Clone,Debug,PartialEq)]
 pub enum Instruction{
     Const {dest: String, typ: Types, values: Literal},
     Add {dest: String, op1: String, op2: String},
     Mul {dest: String, op1: String, op2: String},
     Eq {dest: String, op1: String, op2: String},
+    Sub {dest: String, op1: String, op2: String},
+    Div {dest: String, op1: String, op2: String},
+    Rem {dest: String, op1: String, op2: String},
+    Lt {dest: String, op1: String, op2: String},
+    Le {dest: String, op1: String, op2: String},
+    Gt {dest: String, op1: String, op2: String},
+    Ge {dest: String, op1: String, op2: String},
+    Ne {dest: String, op1: String, op2: String},
+    And {dest: String, op1: String, op2: String},
+    Or {dest: String, op1: String, op2: String},
+    Not {dest: String, src: String},
+    Shl {dest: String, op1: String, op2: String},
+    Shr {dest: String, op1: String, op2: String},
+    BitAnd {dest: String, op1: String, op2: String},
+    BitOr {dest: String, op1: String, op2: String},
+    BitXor {dest: String, op1: String, op2: String},
+    FAdd {dest: String, op1: String, op2: String},
+    FSub {dest: String, op1: String, op2: String},
+    FMul {dest: String, op1: String, op2: String},
+    FDiv {dest: String, op1: String, op2: String},
+    FEq {dest: String, op1: String, op2: String},
+    FLt {dest: String, op1: String, op2: String},
+    FLe {dest: String, op1: String, op2: String},
+    FGt {dest: String, op1: String, op2: String},
+    FGe {dest: String, op1: String, op2: String},
     Jmp {label: String},
     Move {dest: String, src: String},
     Id {dest: String, src: String},
     Label {label: String},
     Br { cond: String, then_label: String, else_label: String},
     Ret {value: Option<String>},
-    Print {value: String}
+    Print {value: String},
+    // `dest: type = call @func args...`, or just `call @func args...`
+    Call {dest: Option<(String, Types)>, func: String, args: Vec<String>},
+    // `typ` is the pointer type, `size` the number of elements
+    Alloc {dest: String, typ: Types, size: String},
+    Free {ptr: String},
+    Load {dest: String, ptr: String},
+    Store {ptr: String, value: String},
+    PtrAdd {dest: String, ptr: String, offset: String},
+    // SSA merge, `args` are `(label, var)`: `dest` gets `var` when control
+    // came from the block called `label`. Phis sit at the top of a block and
+    // read their arguments all at once.
+    Phi {dest: String, args: Vec<(String, String)>}
+
+}
 
+// Every two operand operation has the same shape, these helpers let passes
+// handle them by opcode instead of matching each variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add, Mul, Eq, Sub, Div, Rem, Lt, Le, Gt, Ge, Ne,
+    And, Or, Shl, Shr, BitAnd, BitOr, BitXor,
+    FAdd, FSub, FMul, FDiv, FEq, FLt, FLe, FGt, FGe,
 }
 
+impl BinOp {
+    pub const ALL: [BinOp; 27] = [
+        BinOp::Add, BinOp::Mul, BinOp::Eq, BinOp::Sub, BinOp::Div, BinOp::Rem, BinOp::Lt, BinOp::Le,
+        BinOp::Gt, BinOp::Ge, BinOp::Ne, BinOp::And, BinOp::Or, BinOp::Shl, BinOp::Shr, BinOp::BitAnd,
+        BinOp::BitOr, BinOp::BitXor, BinOp::FAdd, BinOp::FSub, BinOp::FMul, BinOp::FDiv, BinOp::FEq,
+        BinOp::FLt, BinOp::FLe, BinOp::FGt, BinOp::FGe,
+    ];
+
+    /// The name in the text format (`add`, `lt`, ...).
+    pub fn opcode(self) -> &'static str {
+        match self {
+            BinOp::Add => "add",
+            BinOp::Mul => "mul",
+            BinOp::Eq => "eq",
+            BinOp::Sub => "sub",
+            BinOp::Div => "div",
+            BinOp::Rem => "rem",
+            BinOp::Lt => "lt",
+            BinOp::Le => "le",
+            BinOp::Gt => "gt",
+            BinOp::Ge => "ge",
+            BinOp::Ne => "ne",
+            BinOp::And => "and",
+            BinOp::Or => "or",
+            BinOp::Shl => "shl",
+            BinOp::Shr => "shr",
+            BinOp::BitAnd => "bitand",
+            BinOp::BitOr => "bitor",
+            BinOp::BitXor => "bitxor",
+            BinOp::FAdd => "fadd",
+            BinOp::FSub => "fsub",
+            BinOp::FMul => "fmul",
+            BinOp::FDiv => "fdiv",
+            BinOp::FEq => "feq",
+            BinOp::FLt => "flt",
+            BinOp::FLe => "fle",
+            BinOp::FGt => "fgt",
+            BinOp::FGe => "fge",
+        }
+    }
+
+    pub fn from_opcode(opcode: &str) -> Option<BinOp> {
+        BinOp::ALL.into_iter().find(|op| op.opcode() == opcode)
+    }
+}
+
+impl Instruction {
+    /// Build a two operand instruction from its opcode.
+    pub fn binary(op: BinOp, dest: String, op1: String, op2: String) -> Instruction {
+        match op {
+            BinOp::Add => Instruction::Add { dest, op1, op2 },
+            BinOp::Mul => Instruction::Mul { dest, op1, op2 },
+            BinOp::Eq => Instruction::Eq { dest, op1, op2 },
+            BinOp::Sub => Instruction::Sub { dest, op1, op2 },
+            BinOp::Div => Instruction::Div { dest, op1, op2 },
+            BinOp::Rem => Instruction::Rem { dest, op1, op2 },
+            BinOp::Lt => Instruction::Lt { dest, op1, op2 },
+            BinOp::Le => Instruction::Le { dest, op1, op2 },
+            BinOp::Gt => Instruction::Gt { dest, op1, op2 },
+            BinOp::Ge => Instruction::Ge { dest, op1, op2 },
+            BinOp::Ne => Instruction::Ne { dest, op1, op2 },
+            BinOp::And => Instruction::And { dest, op1, op2 },
+            BinOp::Or => Instruction::Or { dest, op1, op2 },
+            BinOp::Shl => Instruction::Shl { dest, op1, op2 },
+            BinOp::Shr => Instruction::Shr { dest, op1, op2 },
+            BinOp::BitAnd => Instruction::BitAnd { dest, op1, op2 },
+            BinOp::BitOr => Instruction::BitOr { dest, op1, op2 },
+            BinOp::BitXor => Instruction::BitXor { dest, op1, op2 },
+            BinOp::FAdd => Instruction::FAdd { dest, op1, op2 },
+            BinOp::FSub => Instruction::FSub { dest, op1, op2 },
+            BinOp::FMul => Instruction::FMul { dest, op1, op2 },
+            BinOp::FDiv => Instruction::FDiv { dest, op1, op2 },
+            BinOp::FEq => Instruction::FEq { dest, op1, op2 },
+            BinOp::FLt => Instruction::FLt { dest, op1, op2 },
+            BinOp::FLe => Instruction::FLe { dest, op1, op2 },
+            BinOp::FGt => Instruction::FGt { dest, op1, op2 },
+            BinOp::FGe => Instruction::FGe { dest, op1, op2 },
+        }
+    }
+
+    /// `(opcode, dest, op1, op2)` of a two operand instruction.
+    pub fn as_binary(&self) -> Option<(BinOp, &String, &String, &String)> {
+        Some(match self {
+            Instruction::Add { dest, op1, op2 } => (BinOp::Add, dest, op1, op2),
+            Instruction::Mul { dest, op1, op2 } => (BinOp::Mul, dest, op1, op2),
+            Instruction::Eq { dest, op1, op2 } => (BinOp::Eq, dest, op1, op2),
+            Instruction::Sub { dest, op1, op2 } => (BinOp::Sub, dest, op1, op2),
+            Instruction::Div { dest, op1, op2 } => (BinOp::Div, dest, op1, op2),
+            Instruction::Rem { dest, op1, op2 } => (BinOp::Rem, dest, op1, op2),
+            Instruction::Lt { dest, op1, op2 } => (BinOp::Lt, dest, op1, op2),
+            Instruction::Le { dest, op1, op2 } => (BinOp::Le, dest, op1, op2),
+            Instruction::Gt { dest, op1, op2 } => (BinOp::Gt, dest, op1, op2),
+            Instruction::Ge { dest, op1, op2 } => (BinOp::Ge, dest, op1, op2),
+            Instruction::Ne { dest, op1, op2 } => (BinOp::Ne, dest, op1, op2),
+            Instruction::And { dest, op1, op2 } => (BinOp::And, dest, op1, op2),
+            Instruction::Or { dest, op1, op2 } => (BinOp::Or, dest, op1, op2),
+            Instruction::Shl { dest, op1, op2 } => (BinOp::Shl, dest, op1, op2),
+            Instruction::Shr { dest, op1, op2 } => (BinOp::Shr, dest, op1, op2),
+            Instruction::BitAnd { dest, op1, op2 } => (BinOp::BitAnd, dest, op1, op2),
+            Instruction::BitOr { dest, op1, op2 } => (BinOp::BitOr, dest, op1, op2),
+            Instruction::BitXor { dest, op1, op2 } => (BinOp::BitXor, dest, op1, op2),
+            Instruction::FAdd { dest, op1, op2 } => (BinOp::FAdd, dest, op1, op2),
+            Instruction::FSub { dest, op1, op2 } => (BinOp::FSub, dest, op1, op2),
+            Instruction::FMul { dest, op1, op2 } => (BinOp::FMul, dest, op1, op2),
+            Instruction::FDiv { dest, op1, op2 } => (BinOp::FDiv, dest, op1, op2),
+            Instruction::FEq { dest, op1, op2 } => (BinOp::FEq, dest, op1, op2),
+            Instruction::FLt { dest, op1, op2 } => (BinOp::FLt, dest, op1, op2),
+            Instruction::FLe { dest, op1, op2 } => (BinOp::FLe, dest, op1, op2),
+            Instruction::FGt { dest, op1, op2 } => (BinOp::FGt, dest, op1, op2),
+            Instruction::FGe { dest, op1, op2 } => (BinOp::FGe, dest, op1, op2),
+            _ => return None,
+        })
+    }
+
+    /// Whether running this does anything besides writing its destination,
+    /// such instructions are never merged or deleted by the passes.
+    pub fn has_side_effects(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Print { .. }
+                | Instruction::Call { .. }
+                | Instruction::Alloc { .. }
+                | Instruction::Free { .. }
+                | Instruction::Store { .. }
+        )
+    }
+
+    /// Whether running this can stop the program with an error: a load, or
+    /// a division or remainder unless `constant` shows its divisor is a
+    /// non-zero constant. Passes keep these even when the result is unused,
+    /// so the error still happens.
+    pub fn may_trap(&self, constant: impl Fn(&String) -> Option<Literal>) -> bool {
+        match self {
+            Instruction::Load { .. } => true,
+            Instruction::Div { op2, .. } | Instruction::Rem { op2, .. } => {
+                !matches!(constant(op2), Some(Literal::Int(d)) if d != 0)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// `op1 OP op2 == op2 OP op1`. IEEE addition and multiplication are left
+/// out: swapping their operands can change which NaN payload comes out.
+pub fn is_commutative(op: BinOp) -> bool {
+    matches!(
+        op,
+        BinOp::Add | BinOp::Mul | BinOp::Eq | BinOp::Ne | BinOp::And | BinOp::Or
+            | BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::FEq
+    )
+}
+
+/// Operand and result types of a two operand opcode. `eq`/`ne` compare any
+/// two values of the same type, so their operand type is `None`.
+pub fn binary_signature(op: BinOp) -> (Option<Types>, Types) {
+    match op {
+        BinOp::Eq | BinOp::Ne => (None, Types::Bool),
+        BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => (Some(Types::Int), Types::Bool),
+        BinOp::And | BinOp::Or => (Some(Types::Bool), Types::Bool),
+        BinOp::FAdd | BinOp::FSub | BinOp::FMul | BinOp::FDiv => (Some(Types::Float), Types::Float),
+        BinOp::FEq | BinOp::FLt | BinOp::FLe | BinOp::FGt | BinOp::FGe => (Some(Types::Float), Types::Bool),
+        BinOp::Add | BinOp::Mul | BinOp::Sub | BinOp::Div | BinOp::Rem
+            | BinOp::Shl | BinOp::Shr | BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor => (Some(Types::Int), Types::Int),
+    }
+}
 
 pub struct Block{
     pub label: String,
     pub instrs: Vec<String>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Argument{
+    pub name: String,
+    pub typ: Types
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Function{
     pub name: String,
+    pub args: Vec<Argument>,
+    pub ret_type: Option<Types>,
     pub instr: Vec<Instruction>
 }
 
+/// A whole program, execution starts at `@main`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program{
+    pub functions: Vec<Function>
+}
+
+impl Program {
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+}
+
@@ -0,0 +1,586 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use petgraph::graph::NodeIndex;
+
+use crate::cfg::*;
+use crate::global::Dominators;
+use crate::lvn::{get_dest, get_used_var};
+use crate::types::*;
+
+// Well-formedness checks for a Function and for the CFG built from it. Every
+// problem is reported, each with the index of the offending instruction.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum VerifyError {
+    EmptyFunction,
+    DuplicateLabel { label: String, index: usize, first: usize },
+    UndefinedLabel { label: String, index: usize },
+    UndefinedVariable { var: String, index: usize },
+    // `block` is the block the terminator ends, its label or `block0`
+    InstructionAfterTerminator { block: String, index: usize },
+    NonBoolCondition { cond: String, index: usize, found: Types },
+    // Program level problems, `index` is relative to the calling function
+    DuplicateFunction { name: String },
+    UnknownFunction { func: String, index: usize },
+    ArgumentCount { func: String, index: usize, expected: usize, found: usize },
+    NoReturnType { func: String, index: usize },
+    // CFG level problems, `index` is relative to the block
+    DuplicateBlock { name: String },
+    MissingEdge { block: String, index: usize, target: String },
+    EmptyBlock { block: String },
+    // SSA problems, `index` is relative to the block as well
+    Redefined { var: String, block: String, index: usize, first: String },
+    NotDominated { var: String, block: String, index: usize },
+    PhiOperandNotDominated { var: String, block: String, index: usize, pred: String },
+    PhiNotAtTop { block: String, index: usize },
+    PhiPredecessors { block: String, index: usize, found: Vec<String>, expected: Vec<String> },
+    PhiInEntry { block: String, index: usize },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::EmptyFunction => write!(f, "function has no instructions"),
+            VerifyError::DuplicateLabel { label, index, first } => {
+                write!(f, "instruction {}: label `.{}` already defined at {}", index, label, first)
+            }
+            VerifyError::UndefinedLabel { label, index } => {
+                write!(f, "instruction {}: jump to undefined label `.{}`", index, label)
+            }
+            VerifyError::UndefinedVariable { var, index } => {
+                write!(f, "instruction {}: `{}` is never defined", index, var)
+            }
+            VerifyError::InstructionAfterTerminator { block, index } => {
+                write!(f, "block {} instruction {}: unreachable, follows a terminator without a label", block, index)
+            }
+            VerifyError::NonBoolCondition { cond, index, found } => {
+                write!(f, "instruction {}: branch condition `{}` is {}, not bool", index, cond, found)
+            }
+            VerifyError::DuplicateFunction { name } => write!(f, "two functions are named `@{}`", name),
+            VerifyError::UnknownFunction { func, index } => {
+                write!(f, "instruction {}: call to undefined function `@{}`", index, func)
+            }
+            VerifyError::ArgumentCount { func, index, expected, found } => {
+                write!(f, "instruction {}: `@{}` takes {} argument(s), found {}", index, func, expected, found)
+            }
+            VerifyError::NoReturnType { func, index } => {
+                write!(f, "instruction {}: uses the result of `@{}`, which returns nothing", index, func)
+            }
+            VerifyError::DuplicateBlock { name } => write!(f, "two blocks are named `{}`", name),
+            VerifyError::MissingEdge { block, index, target } => {
+                write!(f, "block {} instruction {}: no edge to `{}`", block, index, target)
+            }
+            VerifyError::EmptyBlock { block } => write!(f, "block {} has no instructions", block),
+            VerifyError::Redefined { var, block, index, first } => {
+                write!(f, "block {} instruction {}: `{}` already defined in block {}", block, index, var, first)
+            }
+            VerifyError::NotDominated { var, block, index } => {
+                write!(f, "block {} instruction {}: the definition of `{}` does not dominate this use", block, index, var)
+            }
+            VerifyError::PhiOperandNotDominated { var, block, index, pred } => {
+                write!(f, "block {} instruction {}: the definition of `{}` does not dominate the end of {}", block, index, var, pred)
+            }
+            VerifyError::PhiNotAtTop { block, index } => {
+                write!(f, "block {} instruction {}: phi after the start of the block", block, index)
+            }
+            VerifyError::PhiPredecessors { block, index, found, expected } => {
+                write!(f, "block {} instruction {}: phi has arguments for {:?}, the predecessors are {:?}", block, index, found, expected)
+            }
+            VerifyError::PhiInEntry { block, index } => write!(f, "block {} instruction {}: phi in the entry block", block, index),
+        }
+    }
+}
+
+pub fn verify(f: &Function) -> Result<(), Vec<VerifyError>> {
+    let mut errors = Vec::new();
+    if f.instr.is_empty() {
+        errors.push(VerifyError::EmptyFunction);
+    }
+
+    let mut labels: HashMap<&String, usize> = HashMap::new();
+    let mut defined: HashSet<&String> = f.args.iter().map(|a| &a.name).collect();
+    for (index, instr) in f.instr.iter().enumerate() {
+        if let Instruction::Label { label } = instr {
+            if let Some(&first) = labels.get(label) {
+                errors.push(VerifyError::DuplicateLabel { label: label.clone(), index, first });
+            } else {
+                labels.insert(label, index);
+            }
+        }
+        if let Some(dest) = get_dest(instr) {
+            defined.insert(dest);
+        }
+    }
+
+    let types = definition_types(f);
+    let mut after_terminator = false;
+    let mut block = "block0";
+    for (index, instr) in f.instr.iter().enumerate() {
+        match instr {
+            Instruction::Label { label } => {
+                after_terminator = false;
+                block = label;
+            }
+            _ if after_terminator => {
+                errors.push(VerifyError::InstructionAfterTerminator { block: block.to_string(), index });
+                // one report per unreachable run is enough
+                after_terminator = false;
+            }
+            _ => {}
+        }
+
+        for var in get_used_var(instr) {
+            if !defined.contains(&var) {
+                errors.push(VerifyError::UndefinedVariable { var, index });
+            }
+        }
+
+        for label in targets(instr) {
+            if !labels.contains_key(label) {
+                errors.push(VerifyError::UndefinedLabel { label: label.clone(), index });
+            }
+        }
+        if let Instruction::Phi { args, .. } = instr {
+            for (label, _) in args.iter().filter(|(label, _)| !labels.contains_key(label)) {
+                errors.push(VerifyError::UndefinedLabel { label: label.clone(), index });
+            }
+        }
+
+        if let Instruction::Br { cond, .. } = instr {
+            let wrong = types.get(cond).and_then(|ts| ts.iter().find(|t| **t != Types::Bool));
+            if let Some(found) = wrong {
+                errors.push(VerifyError::NonBoolCondition { cond: cond.clone(), index, found: found.clone() });
+            }
+        }
+
+        if is_terminator(instr) {
+            after_terminator = true;
+        }
+    }
+
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+/// `verify` on every function plus the calls between them: unique function
+/// names, call targets that exist, matching argument counts and no value
+/// taken from a function without a return type. Errors come with the name of
+/// the function they were found in.
+pub fn verify_program(p: &Program) -> Result<(), Vec<(String, VerifyError)>> {
+    let mut errors = Vec::new();
+    let mut names = HashSet::new();
+    for f in &p.functions {
+        if !names.insert(&f.name) {
+            errors.push((f.name.clone(), VerifyError::DuplicateFunction { name: f.name.clone() }));
+        }
+        if let Err(found) = verify(f) {
+            errors.extend(found.into_iter().map(|e| (f.name.clone(), e)));
+        }
+
+        for (index, instr) in f.instr.iter().enumerate() {
+            let Instruction::Call { dest, func, args } = instr else {
+                continue;
+            };
+            let error = match p.function(func) {
+                None => VerifyError::UnknownFunction { func: func.clone(), index },
+                Some(callee) if callee.args.len() != args.len() => {
+                    let (expected, found) = (callee.args.len(), args.len());
+                    VerifyError::ArgumentCount { func: func.clone(), index, expected, found }
+                }
+                Some(callee) if dest.is_some() && callee.ret_type.is_none() => {
+                    VerifyError::NoReturnType { func: func.clone(), index }
+                }
+                Some(_) => continue,
+            };
+            errors.push((f.name.clone(), error));
+        }
+    }
+
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+/// Structural checks on a CFG: unique block names, non empty blocks, no
+/// instruction after a terminator and an edge for every jump target.
+pub fn verify_cfg(cfg: &Cfg) -> Result<(), Vec<VerifyError>> {
+    let mut errors = Vec::new();
+    let mut names = HashSet::new();
+
+    for node in cfg.node_indices() {
+        let block = &cfg[node];
+        if !names.insert(&block.name) {
+            errors.push(VerifyError::DuplicateBlock { name: block.name.clone() });
+        }
+        if block.instructions.is_empty() {
+            if node != cfg.exit {
+                errors.push(VerifyError::EmptyBlock { block: block.name.clone() });
+            }
+            continue;
+        }
+
+        let last = block.instructions.len() - 1;
+        for (index, instr) in block.instructions.iter().enumerate() {
+            if is_terminator(instr) && index != last {
+                errors.push(VerifyError::InstructionAfterTerminator { block: block.name.clone(), index: index + 1 });
+            }
+        }
+
+        let succs: HashSet<&String> = cfg.neighbors(node).map(|s| &cfg[s].name).collect();
+        for target in targets(&block.instructions[last]) {
+            if !succs.contains(target) {
+                errors.push(VerifyError::MissingEdge { block: block.name.clone(), index: last, target: target.clone() });
+            }
+        }
+    }
+
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+/// SSA form checks on a CFG: one definition per variable, every use
+/// dominated by its definition (a phi operand at the end of the predecessor
+/// it comes from), phis only at the top of a block, never in the entry, and
+/// with one operand per predecessor. Variables nothing defines are left to
+/// `verify`; as a phi operand they stand for a value undefined on that edge.
+/// Uses in blocks the entry can't reach are not checked for dominance.
+pub fn verify_ssa(cfg: &Cfg) -> Result<(), Vec<VerifyError>> {
+    let mut errors = Vec::new();
+    let doms = Dominators::new(cfg);
+    let name = |node: NodeIndex| cfg[node].name.clone();
+
+    // where each variable is defined, arguments before the first instruction
+    let mut defs: HashMap<&String, (NodeIndex, Option<usize>)> =
+        cfg.args.iter().map(|a| (&a.name, (cfg.entry, None))).collect();
+    let mut nodes: Vec<NodeIndex> = cfg.node_indices().collect();
+    nodes.sort_by_key(|&n| (n != cfg.entry, n));
+    for &node in &nodes {
+        for (index, instr) in cfg[node].instructions.iter().enumerate() {
+            let Some(dest) = get_dest(instr) else {
+                continue;
+            };
+            match defs.get(dest) {
+                Some(&(first, _)) => {
+                    errors.push(VerifyError::Redefined { var: dest.clone(), block: name(node), index, first: name(first) });
+                }
+                None => {
+                    defs.insert(dest, (node, Some(index)));
+                }
+            }
+        }
+    }
+    // `var` is available at `index` of `node` (`None` for its end), anything
+    // goes in blocks that never run
+    let available = |var: &String, node: NodeIndex, index: Option<usize>| match defs.get(var) {
+        None => true,
+        _ if !doms.is_reachable(node) => true,
+        Some(&(block, _)) if block != node => doms.dominates(block, node),
+        Some(&(_, def)) => match (def, index) {
+            (None, _) | (Some(_), None) => true,
+            (Some(def), Some(index)) => def < index,
+        },
+    };
+
+    for &node in &nodes {
+        let block = &cfg[node];
+        let mut preds: Vec<String> = cfg.predecessors(node).map(name).collect();
+        preds.sort();
+        preds.dedup();
+        let mut at_top = true;
+        for (index, instr) in block.instructions.iter().enumerate() {
+            let Instruction::Phi { args, .. } = instr else {
+                at_top &= matches!(instr, Instruction::Label { .. });
+                let mut used = get_used_var(instr);
+                used.sort();
+                used.dedup();
+                for var in used {
+                    if !available(&var, node, Some(index)) {
+                        errors.push(VerifyError::NotDominated { var, block: name(node), index });
+                    }
+                }
+                continue;
+            };
+
+            if node == cfg.entry {
+                errors.push(VerifyError::PhiInEntry { block: name(node), index });
+            }
+            if !at_top {
+                errors.push(VerifyError::PhiNotAtTop { block: name(node), index });
+            }
+            let mut found: Vec<String> = args.iter().map(|(label, _)| label.clone()).collect();
+            found.sort();
+            if found != preds {
+                errors.push(VerifyError::PhiPredecessors { block: name(node), index, found, expected: preds.clone() });
+            }
+            for (label, var) in args {
+                if let Some(pred) = cfg.block(label)
+                    && !available(var, pred, None)
+                {
+                    errors.push(VerifyError::PhiOperandNotDominated {
+                        var: var.clone(),
+                        block: name(node),
+                        index,
+                        pred: label.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+/// Panics with every problem found when `f` is malformed. Compiled out of
+/// release builds, so passes can call it after each transformation.
+pub fn debug_verify(f: &Function, after: &str) {
+    if cfg!(debug_assertions) && let Err(errors) = verify(f) {
+        let report: Vec<String> = errors.iter().map(|e| format!("  {}", e)).collect();
+        panic!("@{} is malformed after {}:\n{}\n{}", f.name, after, report.join("\n"), f);
+    }
+}
+
+fn targets(instr: &Instruction) -> Vec<&String> {
+    match instr {
+        Instruction::Jmp { label } => vec![label],
+        Instruction::Br { then_label, else_label, .. } => vec![then_label, else_label],
+        _ => vec![],
+    }
+}
+
+// every type a variable is given by one of its definitions, copies take the
+// types of their source (iterated, a copy can come before its source's def)
+fn definition_types(f: &Function) -> HashMap<&String, HashSet<Types>> {
+    let mut types: HashMap<&String, HashSet<Types>> =
+        f.args.iter().map(|a| (&a.name, HashSet::from([a.typ.clone()]))).collect();
+    loop {
+        let mut changed = false;
+        for instr in &f.instr {
+            if let Some((op, dest, _, _)) = instr.as_binary() {
+                changed |= types.entry(dest).or_default().insert(binary_signature(op).1);
+                continue;
+            }
+            let (dest, new): (&String, HashSet<Types>) = match instr {
+                Instruction::Const { dest, typ, .. } => (dest, HashSet::from([typ.clone()])),
+                Instruction::Not { dest, .. } => (dest, HashSet::from([Types::Bool])),
+                Instruction::Call { dest: Some((dest, typ)), .. } => (dest, HashSet::from([typ.clone()])),
+                Instruction::Alloc { dest, typ, .. } => (dest, HashSet::from([typ.clone()])),
+                Instruction::PtrAdd { dest, ptr, .. } => (dest, types.get(ptr).cloned().unwrap_or_default()),
+                Instruction::Load { dest, ptr } => {
+                    let elems = types.get(ptr).into_iter().flatten().filter_map(|t| match t {
+                        Types::Ptr(elem) => Some((**elem).clone()),
+                        _ => None,
+                    });
+                    (dest, elems.collect())
+                }
+                Instruction::Id { dest, src } | Instruction::Move { dest, src } => {
+                    (dest, types.get(src).cloned().unwrap_or_default())
+                }
+                Instruction::Phi { dest, args } => {
+                    (dest, args.iter().flat_map(|(_, var)| types.get(var).into_iter().flatten().cloned()).collect())
+                }
+                _ => continue,
+            };
+            let entry = types.entry(dest).or_default();
+            for t in new {
+                changed |= entry.insert(t);
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    types
+}
+
+// =====================================
+//             TESTS
+// =====================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::{parse_function, parse_program};
+
+    #[test]
+    fn test_valid_diamond() {
+        let f = parse_function("
+@main {
+  v0: bool = const true
+  br v0 .then_blk .else_blk
+.then_blk:
+  v1: int = const 10
+  jmp .merge_blk
+.else_blk:
+  v1: int = const 20
+  jmp .merge_blk
+.merge_blk:
+  print v1
+  ret v1
+}
+")
+        .unwrap();
+
+        assert_eq!(verify(&f), Ok(()));
+        assert_eq!(verify_cfg(&Cfg::from_function(&f)), Ok(()));
+    }
+
+    #[test]
+    fn test_reports_every_problem() {
+        let f = parse_function("
+@bad {
+  a: int = const 1
+  br a .yes .no
+  print a
+.yes:
+  b = add a c
+  jmp .done
+.yes:
+  ret
+}
+")
+        .unwrap();
+
+        assert_eq!(
+            verify(&f),
+            Err(vec![
+                VerifyError::DuplicateLabel { label: "yes".into(), index: 6, first: 3 },
+                VerifyError::UndefinedLabel { label: "no".into(), index: 1 },
+                VerifyError::NonBoolCondition { cond: "a".into(), index: 1, found: Types::Int },
+                VerifyError::InstructionAfterTerminator { block: "block0".into(), index: 2 },
+                VerifyError::UndefinedVariable { var: "c".into(), index: 4 },
+                VerifyError::UndefinedLabel { label: "done".into(), index: 5 },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_empty_function() {
+        let f = Function { name: "empty".into(), args: vec![], ret_type: None, instr: vec![] };
+        assert_eq!(verify(&f), Err(vec![VerifyError::EmptyFunction]));
+    }
+
+    #[test]
+    fn test_condition_through_copy() {
+        let f = parse_function("@f {\n  a: int = const 1\n  b = id a\n  br b .x .x\n.x:\n}").unwrap();
+        assert_eq!(
+            verify(&f),
+            Err(vec![VerifyError::NonBoolCondition { cond: "b".into(), index: 2, found: Types::Int }])
+        );
+    }
+
+    #[test]
+    fn test_program_calls() {
+        let p = parse_program("
+@main {
+  a: int = const 1
+  b: int = call @inc a
+  c: int = call @log b
+  call @inc a b
+  call @missing
+}
+
+@inc(x: int): int {
+  y = add x x
+  ret y
+}
+
+@log(v: int) {
+  print v
+  print w
+}
+")
+        .unwrap();
+
+        assert_eq!(
+            verify_program(&p),
+            Err(vec![
+                ("main".into(), VerifyError::NoReturnType { func: "log".into(), index: 2 }),
+                ("main".into(), VerifyError::ArgumentCount { func: "inc".into(), index: 3, expected: 1, found: 2 }),
+                ("main".into(), VerifyError::UnknownFunction { func: "missing".into(), index: 4 }),
+                ("log".into(), VerifyError::UndefinedVariable { var: "w".into(), index: 1 }),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_cfg_missing_edge() {
+        let f = parse_function("@f {\n  jmp .gone\n}").unwrap();
+        let cfg = Cfg::from_function(&f);
+        assert_eq!(
+            verify_cfg(&cfg),
+            Err(vec![VerifyError::MissingEdge { block: "block0".into(), index: 0, target: "gone".into() }])
+        );
+    }
+
+    #[test]
+    fn test_cfg_instruction_after_terminator() {
+        let f = parse_function("@f {
+  jmp .next
+.next:
+  ret
+}").unwrap();
+        let mut cfg = Cfg::from_function(&f);
+        let next = cfg.block("next").unwrap();
+        cfg[next].instructions.push(Instruction::Print { value: "x".into() });
+        let err = VerifyError::InstructionAfterTerminator { block: "next".into(), index: 2 };
+        assert_eq!(err.to_string(), "block next instruction 2: unreachable, follows a terminator without a label");
+        assert_eq!(verify_cfg(&cfg), Err(vec![err]));
+    }
+
+    #[test]
+    fn test_ssa_form() {
+        let f = parse_function("
+@main(n: int) {
+  i: int = const 0
+  one: int = const 1
+.loop:
+  i = add i one
+  c = lt i n
+  br c .loop .out
+.out:
+  print i
+}
+")
+        .unwrap();
+        let ssa = crate::ssa::to_ssa(&f, crate::ssa::SsaFlavor::Minimal);
+        assert_eq!(verify_ssa(&Cfg::from_function(&ssa)), Ok(()));
+        // `i` is defined twice
+        assert_eq!(
+            verify_ssa(&Cfg::from_function(&f)),
+            Err(vec![VerifyError::Redefined { var: "i".into(), block: "loop".into(), index: 1, first: "block0".into() }])
+        );
+    }
+
+    #[test]
+    fn test_ssa_reports_every_problem() {
+        let f = parse_function("
+@bad {
+.entry:
+  p = phi .entry x
+  x: int = const 1
+  t: bool = const true
+  br t .a .b
+.a:
+  y: int = const 2
+  jmp .m
+.b:
+  x: int = const 3
+  z = add y y
+.m:
+  w = phi .a y
+  print w
+  v = phi .a y .b y
+}
+")
+        .unwrap();
+        let block = |b: &str| b.to_string();
+        assert_eq!(
+            verify_ssa(&Cfg::from_function(&f)),
+            Err(vec![
+                VerifyError::Redefined { var: "x".into(), block: block("b"), index: 1, first: block("entry") },
+                VerifyError::PhiInEntry { block: block("entry"), index: 1 },
+                VerifyError::PhiPredecessors { block: block("entry"), index: 1, found: vec![block("entry")], expected: vec![] },
+                VerifyError::NotDominated { var: "y".into(), block: block("b"), index: 2 },
+                VerifyError::PhiPredecessors { block: block("m"), index: 1, found: vec![block("a")], expected: vec![block("a"), block("b")] },
+                VerifyError::PhiNotAtTop { block: block("m"), index: 3 },
+                VerifyError::PhiOperandNotDominated { var: "y".into(), block: block("m"), index: 3, pred: block("b") },
+            ])
+        );
+    }
+}